@@ -0,0 +1,31 @@
+//! Checksums for data that goes over the wire or into EEPROM.
+
+/// CRC-8 with the polynomial 0x07 (CRC-8/SMBUS), continuing from `crc`.
+/// Start with 0.
+pub fn crc8_update(crc: u8, byte: u8) -> u8 {
+    let mut crc = crc ^ byte;
+    for _ in 0..8 {
+        crc = match crc & 0x80 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x07,
+        };
+    }
+    crc
+}
+
+/// CRC-8 of `bytes`, see `crc8_update()`.
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| crc8_update(crc, *byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc8_check_value() {
+        // the standard check value for CRC-8/SMBUS
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc8(&[]), 0);
+    }
+}
@@ -0,0 +1,116 @@
+//! Debouncing of push buttons, fed by pin change interrupts.
+
+/// How long a button must stay pressed or released before it counts,
+/// longer than the bouncing of a typical push button.
+pub const DEBOUNCE_MILLIS: u32 = 20;
+
+/// Debouncing state of one button.
+/// Times are milliseconds from a clock that may wrap around.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Debouncer {
+    /// level of the pin at the last change
+    raw_pressed: bool,
+    /// level once it stopped bouncing
+    pressed: bool,
+    /// time of the last change
+    changed_millis: u32,
+}
+
+impl Default for Debouncer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debouncer {
+    /// A released button
+    pub const fn new() -> Self {
+        Self {
+            raw_pressed: false,
+            pressed: false,
+            changed_millis: 0,
+        }
+    }
+
+    /// Record the level of the pin, called whenever it may have changed.
+    /// Every change, like a bounce, restarts the debounce time.
+    pub fn sample(&mut self, pressed: bool, now_millis: u32) {
+        if pressed != self.raw_pressed {
+            self.raw_pressed = pressed;
+            self.changed_millis = now_millis;
+        }
+    }
+
+    /// Has the button just been pressed?
+    /// `true` once per press, as soon as the pin settled for `DEBOUNCE_MILLIS`.
+    pub fn poll(&mut self, now_millis: u32) -> bool {
+        let settled = now_millis.wrapping_sub(self.changed_millis) >= DEBOUNCE_MILLIS;
+        if settled && self.raw_pressed != self.pressed {
+            self.pressed = self.raw_pressed;
+            return self.pressed;
+        }
+        false
+    }
+
+    /// Has `poll()` caught up with the last change of the pin?
+    /// Until then the clock must keep running.
+    pub fn is_settled(&self) -> bool {
+        self.raw_pressed == self.pressed
+    }
+
+    /// Is the button held down, after debouncing?
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bouncing_press_counts_once() {
+        let mut button = Debouncer::new();
+
+        // contacts bounce for a few milliseconds
+        for (millis, pressed) in [
+            (100, true),
+            (101, false),
+            (103, true),
+            (104, false),
+            (106, true),
+        ] {
+            button.sample(pressed, millis);
+            assert!(!button.poll(millis));
+        }
+
+        assert!(!button.poll(106 + DEBOUNCE_MILLIS - 1));
+        assert!(button.poll(106 + DEBOUNCE_MILLIS));
+        assert!(button.is_pressed());
+        assert!(!button.poll(200));
+
+        // releasing isn't an action
+        button.sample(false, 300);
+        assert!(!button.is_settled());
+        assert!(!button.poll(300 + DEBOUNCE_MILLIS));
+        assert!(!button.is_pressed());
+        assert!(button.is_settled());
+    }
+
+    #[test]
+    fn glitch_is_ignored() {
+        let mut button = Debouncer::new();
+        button.sample(true, 10);
+        button.sample(false, 12);
+        assert!(!button.poll(100));
+        assert!(!button.is_pressed());
+    }
+
+    #[test]
+    fn clock_wraps_around() {
+        let mut button = Debouncer::new();
+        button.sample(true, u32::MAX - 5);
+        assert!(!button.poll(u32::MAX));
+        assert!(button.poll(DEBOUNCE_MILLIS));
+    }
+}
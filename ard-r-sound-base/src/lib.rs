@@ -1,15 +1,89 @@
 #![no_std]
+// reading program memory needs inline assembly, see `progmem`
+#![cfg_attr(target_arch = "avr", feature(asm_experimental_arch))]
+
+pub mod crc;
+pub mod debounce;
+pub mod library;
+pub mod packed;
+pub mod phrases;
+pub mod progmem;
+pub mod protocol;
+pub mod song_bytes;
+pub mod timer_duration;
 
 pub struct OptimizedStatic<const UNIQUES: usize, const LIST: usize> {
     pub uniques: [Note; UNIQUES],
     pub list: [usize; LIST],
 }
 
+/// A song that can be played back one note at a time,
+/// regardless of how it is stored
+pub trait Song {
+    /// The note at `index` into the song, `None` past the end
+    fn note(&self, index: usize) -> Option<Note>;
+
+    /// Total number of notes in the song
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The note at `cursor`, moving `cursor` on to the next one, `None` past the end.
+    /// Songs that can only be read in order, like `phrases::PhrasesStatic`,
+    /// pick up where `cursor` left off instead of starting over for every note.
+    fn next_note(&self, cursor: &mut Cursor) -> Option<Note> {
+        let note = self.note(cursor.index)?;
+        cursor.index += 1;
+        Some(note)
+    }
+}
+
+/// Where playback is in a song, moved on by `Song::next_note()`.
+/// The default is the first note.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cursor {
+    /// index of the next note
+    index: usize,
+    /// where a phrase compressed song is in its rules
+    phrases: phrases::Position,
+}
+
+impl Cursor {
+    /// Index of the next note
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// A song in a table of songs, like the `SONGS` table generated for a
+/// directory of songs in a build script
+#[derive(Clone, Copy)]
+pub struct SongEntry {
+    /// The name of its `static`
+    pub name: &'static str,
+    pub song: &'static (dyn Song + Sync),
+    /// Number of notes, the same as `song.len()`
+    pub len: usize,
+}
+
+impl<const UNIQUES: usize, const LIST: usize> Song for OptimizedStatic<UNIQUES, LIST> {
+    fn note(&self, index: usize) -> Option<Note> {
+        self.uniques.get(*self.list.get(index)?).cloned()
+    }
+
+    fn len(&self) -> usize {
+        LIST
+    }
+}
+
 /// A single note
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Note {
     pub pitch: PitchOrRest,
     pub length: Length,
+    pub dynamic: Dynamic,
 }
 
 /// Musical pitch or a rest
@@ -23,6 +97,25 @@ pub enum PitchOrRest {
     Rest,
 }
 
+impl PitchOrRest {
+    /// The pitch `half_steps` higher, or lower if negative.
+    /// `None` if the octave doesn't fit, rests stay rests.
+    pub fn transposed(&self, half_steps: i32) -> Option<Self> {
+        match self {
+            PitchOrRest::Pitch { class, octave } => {
+                let half_steps =
+                    *octave as i32 * 12 + class.half_steps_from_a() as i32 + half_steps;
+                Some(PitchOrRest::Pitch {
+                    class: enum_iterator::all::<PitchClass>()
+                        .nth(half_steps.rem_euclid(12) as usize)?,
+                    octave: half_steps.div_euclid(12).try_into().ok()?,
+                })
+            }
+            PitchOrRest::Rest => Some(PitchOrRest::Rest),
+        }
+    }
+}
+
 /// Twelve-tone pitch class
 /// TODO: is this relative to key?
 #[derive(Debug, Copy, Clone, enum_iterator::Sequence, PartialEq, Eq, Hash)]
@@ -80,3 +173,99 @@ pub enum Length {
     Multiple(u32),
     Division(u32),
 }
+
+/// How loud a note is played, set by ABC decorations like `!p!` or `!ff!`
+#[derive(Debug, Copy, Clone, Default, enum_iterator::Sequence, PartialEq, Eq, Hash)]
+pub enum Dynamic {
+    /// `pppp`
+    Pianissississimo,
+    /// `ppp`
+    Pianississimo,
+    /// `pp`
+    Pianissimo,
+    /// `p`
+    Piano,
+    /// `mp`
+    MezzoPiano,
+    /// `mf`, also used for notes without any dynamic marking
+    #[default]
+    MezzoForte,
+    /// `f`
+    Forte,
+    /// `ff`
+    Fortissimo,
+    /// `fff`
+    Fortississimo,
+    /// `ffff`
+    Fortissississimo,
+}
+
+impl TryFrom<&str> for Dynamic {
+    type Error = ();
+
+    /// Parse the name of a dynamic decoration, without the `!`s
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "pppp" => Dynamic::Pianissississimo,
+            "ppp" => Dynamic::Pianississimo,
+            "pp" => Dynamic::Pianissimo,
+            "p" => Dynamic::Piano,
+            "mp" => Dynamic::MezzoPiano,
+            "mf" => Dynamic::MezzoForte,
+            "f" => Dynamic::Forte,
+            "ff" => Dynamic::Fortissimo,
+            "fff" => Dynamic::Fortississimo,
+            "ffff" => Dynamic::Fortissississimo,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl Dynamic {
+    /// Fraction of each period that a passive buzzer is driven high.
+    /// A longer pulse moves the buzzer further, so it sounds louder,
+    /// up to half of the period.
+    pub fn duty_cycle(&self) -> f32 {
+        match self {
+            Dynamic::Pianissississimo => 0.001,
+            Dynamic::Pianississimo => 0.002,
+            Dynamic::Pianissimo => 0.003,
+            Dynamic::Piano => 0.005,
+            Dynamic::MezzoPiano => 0.007,
+            Dynamic::MezzoForte => 0.01,
+            Dynamic::Forte => 0.03,
+            Dynamic::Fortissimo => 0.1,
+            Dynamic::Fortississimo => 0.25,
+            Dynamic::Fortissississimo => 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transpose() {
+        let g_sharp = PitchOrRest::Pitch {
+            class: PitchClass::GSharpAFlat,
+            octave: 0,
+        };
+        assert_eq!(
+            g_sharp.transposed(2),
+            Some(PitchOrRest::Pitch {
+                class: PitchClass::ASharpBFlat,
+                octave: 1,
+            })
+        );
+        assert_eq!(
+            g_sharp.transposed(-12 * 2 - 11),
+            Some(PitchOrRest::Pitch {
+                class: PitchClass::A,
+                octave: -2,
+            })
+        );
+        assert_eq!(g_sharp.transposed(12 * 128), None);
+        assert_eq!(PitchOrRest::Rest.transposed(5), Some(PitchOrRest::Rest));
+    }
+}
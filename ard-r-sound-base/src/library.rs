@@ -0,0 +1,324 @@
+//! Several songs in one image, written to the EEPROM so that the firmware
+//! can choose between them without being reflashed.
+//!
+//! Layout, multi-byte numbers are little endian:
+//!
+//! | bytes     | content                                                  |
+//! |-----------|----------------------------------------------------------|
+//! | 2         | `MAGIC`                                                  |
+//! | 1         | `VERSION`                                                |
+//! | 1         | number of songs                                          |
+//! | 1         | index of the song played at boot                         |
+//! | 5 * songs | each song's offset from the start (2 bytes),             |
+//! |           | length (2 bytes) and CRC-8 (1 byte)                      |
+//! | 1         | CRC-8 of all the bytes before it                         |
+//! | ...       | the songs, each in the `song_bytes` format               |
+
+use crate::crc::{crc8, crc8_update};
+use crate::song_bytes::{ByteSong, Bytes, SongError};
+
+/// First bytes of a library
+pub const MAGIC: [u8; 2] = *b"AS";
+/// Version of the layout
+pub const VERSION: u8 = 1;
+/// Size of the EEPROM of the ATmega328P
+pub const CAPACITY: usize = 1024;
+
+/// Size of the header before the table of songs
+const HEADER_LEN: usize = 5;
+/// Size of one song in the table
+const ENTRY_LEN: usize = 5;
+
+/// Why a library can't be built or read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LibraryError {
+    /// Fewer bytes than the header says there are
+    Truncated,
+    /// Doesn't start with `MAGIC`, e.g. an erased EEPROM
+    BadMagic,
+    UnsupportedVersion,
+    /// The header or a song was corrupted
+    BadCrc,
+    /// More than 255 songs, or an image larger than 64 KiB
+    TooManySongs,
+    /// There is no song at that index
+    NoSuchSong,
+    InvalidSong(SongError),
+}
+
+impl core::fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LibraryError::Truncated => f.write_str("library is truncated"),
+            LibraryError::BadMagic => f.write_str("not a song library"),
+            LibraryError::UnsupportedVersion => f.write_str("unsupported library version"),
+            LibraryError::BadCrc => f.write_str("library is corrupted"),
+            LibraryError::TooManySongs => f.write_str("too many songs"),
+            LibraryError::NoSuchSong => f.write_str("no song at that index"),
+            LibraryError::InvalidSong(e) => write!(f, "invalid song: {}", e),
+        }
+    }
+}
+
+/// Total size of a library holding `songs`.
+pub fn encoded_len(songs: &[&[u8]]) -> usize {
+    table_len(songs.len()) + songs.iter().map(|song| song.len()).sum::<usize>()
+}
+
+/// Size of the header, table and header CRC of a library with `count` songs.
+fn table_len(count: usize) -> usize {
+    HEADER_LEN + count * ENTRY_LEN + 1
+}
+
+/// Write a library holding `songs` (in the `song_bytes` format) to `out`,
+/// playing song `default_song` at boot.
+/// `out` must be `encoded_len(songs)` bytes long.
+pub fn encode(songs: &[&[u8]], default_song: u8, out: &mut [u8]) -> Result<(), LibraryError> {
+    let count: u8 = songs
+        .len()
+        .try_into()
+        .map_err(|_| LibraryError::TooManySongs)?;
+    if default_song as usize >= songs.len() {
+        return Err(LibraryError::NoSuchSong);
+    }
+    if encoded_len(songs) > u16::MAX as usize {
+        return Err(LibraryError::TooManySongs);
+    }
+    if out.len() != encoded_len(songs) {
+        return Err(LibraryError::Truncated);
+    }
+
+    out[..2].copy_from_slice(&MAGIC);
+    out[2] = VERSION;
+    out[3] = count;
+    out[4] = default_song;
+
+    let mut offset = table_len(songs.len());
+    for (i, song) in songs.iter().enumerate() {
+        let entry = HEADER_LEN + i * ENTRY_LEN;
+        out[entry..entry + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+        out[entry + 2..entry + 4].copy_from_slice(&(song.len() as u16).to_le_bytes());
+        out[entry + 4] = crc8(song);
+
+        out[offset..offset + song.len()].copy_from_slice(song);
+        offset += song.len();
+    }
+
+    let crc_index = table_len(songs.len()) - 1;
+    out[crc_index] = crc8(&out[..crc_index]);
+
+    Ok(())
+}
+
+/// Where a song is in a library.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// from the start of the library
+    pub offset: usize,
+    pub len: usize,
+    crc: u8,
+}
+
+/// Part of `bytes`, such as one song of a library.
+#[derive(Debug, Copy, Clone)]
+pub struct Window<B> {
+    bytes: B,
+    start: usize,
+    len: usize,
+}
+
+impl<B: Bytes> Window<B> {
+    pub const fn new(bytes: B, start: usize, len: usize) -> Self {
+        Self { bytes, start, len }
+    }
+}
+
+impl<B: Bytes> Bytes for Window<B> {
+    fn byte(&self, index: usize) -> Option<u8> {
+        match index < self.len {
+            true => self.bytes.byte(self.start + index),
+            false => None,
+        }
+    }
+}
+
+/// A library, read one byte at a time.
+pub struct Library<B> {
+    bytes: B,
+}
+
+impl<B: Bytes + Copy> Library<B> {
+    pub const fn new(bytes: B) -> Self {
+        Self { bytes }
+    }
+
+    fn byte(&self, index: usize) -> Result<u8, LibraryError> {
+        self.bytes.byte(index).ok_or(LibraryError::Truncated)
+    }
+
+    /// Check the header and table, but not the songs themselves,
+    /// see `validate_song()`.
+    pub fn validate(&self) -> Result<(), LibraryError> {
+        if [self.byte(0)?, self.byte(1)?] != MAGIC {
+            return Err(LibraryError::BadMagic);
+        }
+        if self.byte(2)? != VERSION {
+            return Err(LibraryError::UnsupportedVersion);
+        }
+
+        let crc_index = table_len(self.len()) - 1;
+        let mut crc = 0;
+        for i in 0..crc_index {
+            crc = crc8_update(crc, self.byte(i)?);
+        }
+        if crc != self.byte(crc_index)? {
+            return Err(LibraryError::BadCrc);
+        }
+
+        if self.default_song() >= self.len() {
+            return Err(LibraryError::NoSuchSong);
+        }
+
+        Ok(())
+    }
+
+    /// Number of songs, only meaningful once `validate()` passed
+    pub fn len(&self) -> usize {
+        self.bytes.byte(3).unwrap_or(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Index of the song to play at boot
+    pub fn default_song(&self) -> usize {
+        self.bytes.byte(4).unwrap_or(0) as usize
+    }
+
+    pub fn entry(&self, index: usize) -> Result<Entry, LibraryError> {
+        if index >= self.len() {
+            return Err(LibraryError::NoSuchSong);
+        }
+
+        let start = HEADER_LEN + index * ENTRY_LEN;
+        let u16_at = |i| -> Result<usize, LibraryError> {
+            Ok(u16::from_le_bytes([self.byte(start + i)?, self.byte(start + i + 1)?]) as usize)
+        };
+
+        Ok(Entry {
+            offset: u16_at(0)?,
+            len: u16_at(2)?,
+            crc: self.byte(start + 4)?,
+        })
+    }
+
+    /// Check that song `index` is intact and valid.
+    pub fn validate_song(&self, index: usize) -> Result<(), LibraryError> {
+        let entry = self.entry(index)?;
+
+        let mut crc = 0;
+        for i in 0..entry.len {
+            crc = crc8_update(crc, self.byte(entry.offset + i)?);
+        }
+        if crc != entry.crc {
+            return Err(LibraryError::BadCrc);
+        }
+
+        self.song(index)?
+            .validate()
+            .map_err(LibraryError::InvalidSong)
+    }
+
+    /// Song `index`, see `validate_song()`.
+    pub fn song(&self, index: usize) -> Result<ByteSong<Window<B>>, LibraryError> {
+        let entry = self.entry(index)?;
+        Ok(ByteSong::new(Window::new(
+            self.bytes,
+            entry.offset,
+            entry.len,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song_bytes::{encode_header, encode_note};
+    use crate::{Dynamic, Length, Note, PitchOrRest, Song};
+
+    /// A song playing a rest of length `length` `notes` times
+    fn rests(length: u32, notes: usize, out: &mut [u8; 8]) -> &[u8] {
+        let rest = Note {
+            pitch: PitchOrRest::Rest,
+            length: Length::Multiple(length),
+            dynamic: Dynamic::default(),
+        };
+        out[..3].copy_from_slice(&encode_header(1, notes).unwrap());
+        out[3..6].copy_from_slice(&encode_note(&rest).unwrap());
+        &out[..6 + notes]
+    }
+
+    #[test]
+    fn library_round_trip() {
+        let (mut a, mut b) = ([0; 8], [0; 8]);
+        let songs = [rests(2, 1, &mut a), rests(3, 2, &mut b)];
+
+        let mut image = [0; 32];
+        let image = &mut image[..encoded_len(&songs)];
+        encode(&songs, 1, image).unwrap();
+
+        let library = Library::new(&image[..]);
+        assert_eq!(library.validate(), Ok(()));
+        assert_eq!(library.len(), 2);
+        assert_eq!(library.default_song(), 1);
+
+        for (index, song) in songs.iter().enumerate() {
+            assert_eq!(library.validate_song(index), Ok(()));
+            let decoded = library.song(index).unwrap();
+            assert_eq!(decoded.len(), song.len() - 6);
+            assert_eq!(
+                decoded.note(0).map(|note| note.length),
+                Some(Length::Multiple(index as u32 + 2))
+            );
+        }
+
+        assert_eq!(library.validate_song(2), Err(LibraryError::NoSuchSong));
+        assert_eq!(encode(&songs, 2, image), Err(LibraryError::NoSuchSong));
+    }
+
+    #[test]
+    fn corrupted_libraries() {
+        let mut a = [0; 8];
+        let songs = [rests(2, 2, &mut a)];
+        let mut image = [0; 32];
+        let image = &mut image[..encoded_len(&songs)];
+        encode(&songs, 0, image).unwrap();
+
+        // an erased EEPROM
+        let erased = [0xFF; 32];
+        assert_eq!(
+            Library::new(&erased[..]).validate(),
+            Err(LibraryError::BadMagic)
+        );
+
+        image[3] = 2;
+        assert_eq!(
+            Library::new(&image[..]).validate(),
+            Err(LibraryError::BadCrc)
+        );
+        image[3] = 1;
+
+        // the last note of the song
+        let last = image.len() - 1;
+        image[last] ^= 1;
+        let library = Library::new(&image[..]);
+        assert_eq!(library.validate(), Ok(()));
+        assert_eq!(library.validate_song(0), Err(LibraryError::BadCrc));
+
+        assert_eq!(
+            Library::new(&image[..last]).validate_song(0),
+            Err(LibraryError::Truncated)
+        );
+    }
+}
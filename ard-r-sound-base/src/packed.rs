@@ -0,0 +1,318 @@
+//! Bit-packed notes for songs compiled into the firmware,
+//! a fraction of the size of an `OptimizedStatic`.
+//!
+//! Every unique note is packed into a `u16`, most significant bits first:
+//!
+//! | bits | content                                                          |
+//! |------|------------------------------------------------------------------|
+//! | 7    | pitch: `(octave + 4) * 12` plus the pitch class, `REST` if none  |
+//! | 5    | length: 0 for `Unit`, the top bit set for a `Division`,          |
+//! |      | the other bits the multiple or division minus one                |
+//! | 4    | dynamic                                                          |
+//!
+//! The song itself is one byte per note, indexing the unique notes.
+
+use crate::progmem::ProgMem;
+use crate::song_bytes::SongError;
+use crate::{Dynamic, Length, Note, PitchClass, PitchOrRest, Song};
+
+/// Most unique notes that a one-byte index can refer to
+pub const MAX_UNIQUES: usize = 256;
+/// Lowest and highest octave that can be packed
+pub const MIN_OCTAVE: i8 = -4;
+pub const MAX_OCTAVE: i8 = 5;
+/// Largest multiple or division that can be packed
+pub const MAX_LENGTH_FACTOR: u32 = 16;
+
+/// Pitch field of a rest
+const REST: u16 = 0x7F;
+const PITCH_SHIFT: u16 = 9;
+const LENGTH_SHIFT: u16 = 4;
+const LENGTH_MASK: u16 = 0x1F;
+const LENGTH_DIVISION: u16 = 0x10;
+const DYNAMIC_MASK: u16 = 0x0F;
+
+/// Number of pitch classes in an octave
+const CLASSES: u16 = 12;
+
+/// One note packed into 16 bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PackedNote(u16);
+
+impl PackedNote {
+    /// A note from its bits, as generated by `static_from_file!`.
+    /// Bits that don't decode to a note are only caught by `unpack()`.
+    pub const fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Pack `note`. `Multiple(1)` and `Division(1)` are packed as `Unit`,
+    /// which is just as long.
+    pub fn pack(note: &Note) -> Result<Self, SongError> {
+        let pitch = match &note.pitch {
+            PitchOrRest::Pitch { class, octave } => {
+                if !(MIN_OCTAVE..=MAX_OCTAVE).contains(octave) {
+                    return Err(SongError::OctaveOutOfRange);
+                }
+                (*octave - MIN_OCTAVE) as u16 * CLASSES + class.half_steps_from_a() as u16
+            }
+            PitchOrRest::Rest => REST,
+        };
+
+        let factor = |x: u32| match x {
+            1..=MAX_LENGTH_FACTOR => Ok(x as u16 - 1),
+            _ => Err(SongError::LengthOutOfRange),
+        };
+        let length = match note.length {
+            Length::Unit => 0,
+            Length::Multiple(m) => factor(m)?,
+            Length::Division(d) => match factor(d)? {
+                0 => 0,
+                d => LENGTH_DIVISION | d,
+            },
+        };
+
+        Ok(Self(
+            pitch << PITCH_SHIFT | length << LENGTH_SHIFT | note.dynamic as u16,
+        ))
+    }
+
+    pub fn unpack(&self) -> Result<Note, SongError> {
+        let pitch = match self.0 >> PITCH_SHIFT {
+            REST => PitchOrRest::Rest,
+            pitch => PitchOrRest::Pitch {
+                class: enum_iterator::all::<PitchClass>()
+                    .nth((pitch % CLASSES) as usize)
+                    .ok_or(SongError::InvalidNote)?,
+                octave: (pitch / CLASSES) as i8 + MIN_OCTAVE,
+            },
+        };
+        if let PitchOrRest::Pitch { octave, .. } = pitch {
+            if octave > MAX_OCTAVE {
+                return Err(SongError::InvalidNote);
+            }
+        }
+
+        let length = self.0 >> LENGTH_SHIFT & LENGTH_MASK;
+        let factor = (length & !LENGTH_DIVISION) as u32 + 1;
+        let length = match (length & LENGTH_DIVISION != 0, factor) {
+            (false, 1) => Length::Unit,
+            (false, m) => Length::Multiple(m),
+            (true, 1) => return Err(SongError::InvalidNote),
+            (true, d) => Length::Division(d),
+        };
+
+        let dynamic = enum_iterator::all::<Dynamic>()
+            .nth((self.0 & DYNAMIC_MASK) as usize)
+            .ok_or(SongError::InvalidNote)?;
+
+        Ok(Note {
+            pitch,
+            length,
+            dynamic,
+        })
+    }
+}
+
+/// A song of bit-packed notes, see the module documentation.
+/// Generated by `static_from_file!{NAME: PackedStatic, file}`.
+pub struct PackedStatic<const UNIQUES: usize, const LIST: usize> {
+    pub uniques: [PackedNote; UNIQUES],
+    pub list: [u8; LIST],
+}
+
+impl<const UNIQUES: usize, const LIST: usize> PackedStatic<UNIQUES, LIST> {
+    /// Every note of the song, in order
+    pub fn notes(&self) -> PackedNotes<'_> {
+        PackedNotes::new(&self.uniques, &self.list)
+    }
+}
+
+impl<const UNIQUES: usize, const LIST: usize> Song for PackedStatic<UNIQUES, LIST> {
+    fn note(&self, index: usize) -> Option<Note> {
+        self.uniques
+            .get(*self.list.get(index)? as usize)?
+            .unpack()
+            .ok()
+    }
+
+    fn len(&self) -> usize {
+        LIST
+    }
+}
+
+/// A song of bit-packed notes kept in program memory, see `progmem`.
+/// Only the two references take up SRAM.
+/// Generated by `static_from_file!{NAME: PackedProgMem, file}`.
+pub struct PackedProgMem<const UNIQUES: usize, const LIST: usize> {
+    pub uniques: &'static ProgMem<[PackedNote; UNIQUES]>,
+    pub list: &'static ProgMem<[u8; LIST]>,
+}
+
+impl<const UNIQUES: usize, const LIST: usize> PackedProgMem<UNIQUES, LIST> {
+    /// Every note of the song, in order.
+    /// Stops early at an index or note that doesn't decode.
+    pub fn notes(&self) -> impl Iterator<Item = Note> + '_ {
+        (0..LIST).map_while(|index| self.note(index))
+    }
+}
+
+impl<const UNIQUES: usize, const LIST: usize> Song for PackedProgMem<UNIQUES, LIST> {
+    fn note(&self, index: usize) -> Option<Note> {
+        self.uniques
+            .load_at(self.list.load_at(index)? as usize)?
+            .unpack()
+            .ok()
+    }
+
+    fn len(&self) -> usize {
+        LIST
+    }
+}
+
+/// Iterator unpacking the notes of a song as it goes.
+/// Stops early at an index or note that doesn't decode.
+#[derive(Debug, Clone)]
+pub struct PackedNotes<'a> {
+    uniques: &'a [PackedNote],
+    list: core::slice::Iter<'a, u8>,
+}
+
+impl<'a> PackedNotes<'a> {
+    pub fn new(uniques: &'a [PackedNote], list: &'a [u8]) -> Self {
+        Self {
+            uniques,
+            list: list.iter(),
+        }
+    }
+}
+
+impl<'a> Iterator for PackedNotes<'a> {
+    type Item = Note;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.uniques.get(*self.list.next()? as usize)?.unpack().ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.list.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(pitch: PitchOrRest, length: Length, dynamic: Dynamic) -> Note {
+        Note {
+            pitch,
+            length,
+            dynamic,
+        }
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let notes = [
+            note(PitchOrRest::Rest, Length::Unit, Dynamic::default()),
+            note(
+                PitchOrRest::Pitch {
+                    class: PitchClass::GSharpAFlat,
+                    octave: MAX_OCTAVE,
+                },
+                Length::Multiple(MAX_LENGTH_FACTOR),
+                Dynamic::Fortissississimo,
+            ),
+            note(
+                PitchOrRest::Pitch {
+                    class: PitchClass::A,
+                    octave: MIN_OCTAVE,
+                },
+                Length::Division(2),
+                Dynamic::Pianissississimo,
+            ),
+            note(
+                PitchOrRest::Pitch {
+                    class: PitchClass::C,
+                    octave: 0,
+                },
+                Length::Division(MAX_LENGTH_FACTOR),
+                Dynamic::Piano,
+            ),
+        ];
+
+        for note in notes {
+            assert_eq!(PackedNote::pack(&note).unwrap().unpack(), Ok(note));
+        }
+    }
+
+    #[test]
+    fn out_of_range_notes() {
+        let high = note(
+            PitchOrRest::Pitch {
+                class: PitchClass::A,
+                octave: MAX_OCTAVE + 1,
+            },
+            Length::Unit,
+            Dynamic::default(),
+        );
+        assert_eq!(PackedNote::pack(&high), Err(SongError::OctaveOutOfRange));
+
+        let long = note(
+            PitchOrRest::Rest,
+            Length::Multiple(MAX_LENGTH_FACTOR + 1),
+            Dynamic::default(),
+        );
+        assert_eq!(PackedNote::pack(&long), Err(SongError::LengthOutOfRange));
+
+        // a division by one
+        let invalid = PackedNote::from_bits(REST << PITCH_SHIFT | LENGTH_DIVISION << LENGTH_SHIFT);
+        assert_eq!(invalid.unpack(), Err(SongError::InvalidNote));
+    }
+
+    #[test]
+    fn iterate_song() {
+        let rest = PackedNote::pack(&note(
+            PitchOrRest::Rest,
+            Length::Multiple(2),
+            Dynamic::default(),
+        ))
+        .unwrap();
+        let unit =
+            PackedNote::pack(&note(PitchOrRest::Rest, Length::Unit, Dynamic::default())).unwrap();
+        let song = PackedStatic {
+            uniques: [rest, unit],
+            list: [1, 0, 0, 1],
+        };
+
+        let lengths = [
+            Length::Unit,
+            Length::Multiple(2),
+            Length::Multiple(2),
+            Length::Unit,
+        ];
+        assert!(song.notes().map(|note| note.length).eq(lengths));
+        assert_eq!(
+            song.note(1).map(|note| note.length),
+            Some(Length::Multiple(2))
+        );
+        assert_eq!(song.note(4), None);
+
+        // stops at an index past the unique notes
+        assert_eq!(PackedNotes::new(&song.uniques, &[0, 2, 0]).count(), 1);
+
+        static UNIQUES: ProgMem<[PackedNote; 1]> =
+            unsafe { ProgMem::new([PackedNote::from_bits(REST << PITCH_SHIFT)]) };
+        static LIST: ProgMem<[u8; 2]> = unsafe { ProgMem::new([0, 0]) };
+        let progmem = PackedProgMem {
+            uniques: &UNIQUES,
+            list: &LIST,
+        };
+        assert_eq!(progmem.notes().count(), 2);
+        assert_eq!(progmem.note(0).map(|note| note.length), Some(Length::Unit));
+    }
+}
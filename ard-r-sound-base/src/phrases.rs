@@ -0,0 +1,310 @@
+//! Phrase compression of the note indexes of a song,
+//! since melodies repeat whole bars and phrases.
+//!
+//! Each repeated pair of symbols is replaced by a rule, and pairs of rules
+//! by further rules, down to a list of symbols that has no repeated pairs
+//! left (a Re-Pair grammar, built on the host).
+//! Symbols below the number of unique notes are indexes of unique notes,
+//! symbol `uniques + r` stands for the two symbols of rule `r`.
+//! Rules are stored as two bytes each.
+//!
+//! `Expand` expands them one note at a time, without a buffer for the song.
+//! `PhrasesStatic` and `PhrasesProgMem` are songs compressed this way,
+//! generated by `include_song!(NAME, file, encoding = phrases)`.
+
+use crate::packed::PackedNote;
+use crate::progmem::ProgMem;
+use crate::song_bytes::Bytes;
+use crate::{Cursor, Note, Song};
+
+/// Most symbols a byte can hold, unique notes and rules together
+pub const MAX_SYMBOLS: usize = 256;
+/// Most rules nested in each other, which is how many symbols
+/// `Expand` has to keep track of.
+pub const MAX_HEIGHT: usize = 16;
+
+/// Where an `Expand` is in its song, to pick up from with `Expand::resume()`.
+/// The default is the start of the song.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    /// of the next symbol in the list
+    symbol: usize,
+    /// right halves of the rules being expanded, innermost last
+    pending: [u8; MAX_HEIGHT],
+    depth: usize,
+}
+
+/// Iterator over the note indexes of a compressed song.
+/// Stops at a rule that doesn't exist or refers to itself or a later rule,
+/// so corrupted data can't make it loop forever.
+#[derive(Debug, Clone)]
+pub struct Expand<L, R> {
+    list: L,
+    rules: R,
+    uniques: usize,
+    position: Position,
+    corrupted: bool,
+}
+
+impl<L: Bytes, R: Bytes> Expand<L, R> {
+    /// Expand `list`, a song with `uniques` unique notes compressed with `rules`.
+    pub fn new(list: L, rules: R, uniques: usize) -> Self {
+        Self::resume(list, rules, uniques, Position::default())
+    }
+
+    /// Expand the same song as `new()` from `position`,
+    /// the `position()` of an earlier `Expand`.
+    pub fn resume(list: L, rules: R, uniques: usize, position: Position) -> Self {
+        Self {
+            list,
+            rules,
+            uniques,
+            position,
+            corrupted: false,
+        }
+    }
+
+    /// Where the next note index comes from
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    fn next_symbol(&mut self) -> Option<usize> {
+        let position = &mut self.position;
+        if position.depth > 0 {
+            position.depth -= 1;
+            return Some(position.pending[position.depth] as usize);
+        }
+
+        let symbol = self.list.byte(position.symbol)?;
+        position.symbol += 1;
+        Some(symbol as usize)
+    }
+
+    /// The two halves of the rule for `symbol`
+    fn rule(&self, symbol: usize) -> Option<[u8; 2]> {
+        let rule = symbol - self.uniques;
+        let pair = [self.rules.byte(2 * rule)?, self.rules.byte(2 * rule + 1)?];
+
+        match pair.iter().all(|&half| (half as usize) < symbol) {
+            true => Some(pair),
+            false => None,
+        }
+    }
+}
+
+impl<L: Bytes, R: Bytes> Iterator for Expand<L, R> {
+    /// Index of a unique note
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.corrupted {
+            return None;
+        }
+
+        let mut symbol = self.next_symbol()?;
+        while symbol >= self.uniques {
+            match self.rule(symbol) {
+                Some([left, right]) if self.position.depth < MAX_HEIGHT => {
+                    self.position.pending[self.position.depth] = right;
+                    self.position.depth += 1;
+                    symbol = left as usize;
+                }
+                _ => {
+                    self.corrupted = true;
+                    return None;
+                }
+            }
+        }
+
+        Some(symbol as u8)
+    }
+}
+
+/// A phrase compressed song of bit-packed notes, see the module documentation.
+/// Generated by `include_song!(NAME, file, encoding = phrases)`.
+///
+/// It can only be read in order: `note()` expands the song from its start,
+/// `next_note()` picks up where the cursor left off.
+pub struct PhrasesStatic<const UNIQUES: usize, const RULES: usize, const LIST: usize> {
+    pub uniques: [PackedNote; UNIQUES],
+    /// Two bytes for each rule, `RULES` is twice the number of rules
+    pub rules: [u8; RULES],
+    pub list: [u8; LIST],
+    /// Number of notes of the expanded song
+    pub len: usize,
+}
+
+impl<const UNIQUES: usize, const RULES: usize, const LIST: usize>
+    PhrasesStatic<UNIQUES, RULES, LIST>
+{
+    /// The index into `uniques` of every note of the song, in order
+    pub fn indexes(&self) -> Expand<&[u8], &[u8]> {
+        Expand::new(&self.list[..], &self.rules[..], UNIQUES)
+    }
+}
+
+impl<const UNIQUES: usize, const RULES: usize, const LIST: usize> Song
+    for PhrasesStatic<UNIQUES, RULES, LIST>
+{
+    fn note(&self, index: usize) -> Option<Note> {
+        unpack(&self.uniques, self.indexes().nth(index)?)
+    }
+
+    fn next_note(&self, cursor: &mut Cursor) -> Option<Note> {
+        let expand = Expand::resume(&self.list[..], &self.rules[..], UNIQUES, cursor.phrases);
+        next_note(expand, cursor, |index| unpack(&self.uniques, index))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// A `PhrasesStatic` kept in program memory, see `progmem`.
+/// Only the references take up SRAM.
+/// Generated by `include_song!(NAME, file, encoding = phrases, storage = progmem)`.
+pub struct PhrasesProgMem<const UNIQUES: usize, const RULES: usize, const LIST: usize> {
+    pub uniques: &'static ProgMem<[PackedNote; UNIQUES]>,
+    /// Two bytes for each rule, `RULES` is twice the number of rules
+    pub rules: &'static ProgMem<[u8; RULES]>,
+    pub list: &'static ProgMem<[u8; LIST]>,
+    /// Number of notes of the expanded song
+    pub len: usize,
+}
+
+impl<const UNIQUES: usize, const RULES: usize, const LIST: usize>
+    PhrasesProgMem<UNIQUES, RULES, LIST>
+{
+    /// The index into `uniques` of every note of the song, in order
+    pub fn indexes(&self) -> Expand<&ProgMem<[u8; LIST]>, &ProgMem<[u8; RULES]>> {
+        Expand::new(self.list, self.rules, UNIQUES)
+    }
+
+    fn unpack(&self, index: u8) -> Option<Note> {
+        self.uniques.load_at(index as usize)?.unpack().ok()
+    }
+}
+
+impl<const UNIQUES: usize, const RULES: usize, const LIST: usize> Song
+    for PhrasesProgMem<UNIQUES, RULES, LIST>
+{
+    fn note(&self, index: usize) -> Option<Note> {
+        self.unpack(self.indexes().nth(index)?)
+    }
+
+    fn next_note(&self, cursor: &mut Cursor) -> Option<Note> {
+        let expand = Expand::resume(self.list, self.rules, UNIQUES, cursor.phrases);
+        next_note(expand, cursor, |index| self.unpack(index))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+fn unpack(uniques: &[PackedNote], index: u8) -> Option<Note> {
+    uniques.get(index as usize)?.unpack().ok()
+}
+
+/// The next note of `expand`, which picked up from `cursor`,
+/// moving `cursor` on past it
+fn next_note<L: Bytes, R: Bytes>(
+    mut expand: Expand<L, R>,
+    cursor: &mut Cursor,
+    unpack: impl Fn(u8) -> Option<Note>,
+) -> Option<Note> {
+    let note = unpack(expand.next()?)?;
+    cursor.index += 1;
+    cursor.phrases = expand.position();
+    Some(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Length;
+
+    #[test]
+    fn expand_nested_rules() {
+        // two unique notes, rule 2 = [0, 1], rule 3 = [2, 2]
+        let rules = [0, 1, 2, 2];
+        let list = [3, 1, 3, 2];
+
+        assert!(Expand::new(&list[..], &rules[..], 2).eq([0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1]));
+    }
+
+    #[test]
+    fn corrupted_rules_stop() {
+        // rule 2 refers to itself
+        let rules = [2, 0];
+        assert_eq!(Expand::new(&[0u8, 2, 1][..], &rules[..], 2).count(), 1);
+
+        // rule 3 doesn't exist
+        assert_eq!(Expand::new(&[3u8, 0][..], &rules[..], 2).count(), 0);
+    }
+
+    #[test]
+    fn resume_where_it_stopped() {
+        let rules = [0, 1, 2, 2];
+        let list = [3, 1, 3, 2];
+        let expected = [0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1];
+
+        let mut position = Position::default();
+        for &index in &expected {
+            let mut expand = Expand::resume(&list[..], &rules[..], 2, position);
+            assert_eq!(expand.next(), Some(index));
+            position = expand.position();
+        }
+        assert_eq!(
+            Expand::resume(&list[..], &rules[..], 2, position).next(),
+            None
+        );
+    }
+
+    /// A, and A sharp twice as long
+    const UNIQUES: [PackedNote; 2] = [
+        PackedNote::from_bits(0),
+        PackedNote::from_bits(1 << 9 | 1 << 4),
+    ];
+
+    #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+    static PROGMEM_UNIQUES: ProgMem<[PackedNote; 2]> = unsafe { ProgMem::new(UNIQUES) };
+    #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+    static PROGMEM_RULES: ProgMem<[u8; 4]> = unsafe { ProgMem::new([0, 1, 2, 2]) };
+    #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+    static PROGMEM_LIST: ProgMem<[u8; 4]> = unsafe { ProgMem::new([3, 1, 3, 2]) };
+
+    #[test]
+    fn phrases_songs_play_in_order() {
+        let song = PhrasesStatic {
+            uniques: UNIQUES,
+            rules: [0, 1, 2, 2],
+            list: [3, 1, 3, 2],
+            len: 11,
+        };
+        let progmem = PhrasesProgMem {
+            uniques: &PROGMEM_UNIQUES,
+            rules: &PROGMEM_RULES,
+            list: &PROGMEM_LIST,
+            len: 11,
+        };
+
+        let a = UNIQUES[0].unpack().unwrap();
+        let a_sharp = UNIQUES[1].unpack().unwrap();
+        assert_eq!(a_sharp.length, Length::Multiple(2));
+        let expected = [0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1].map(|i| [&a, &a_sharp][i].clone());
+
+        for song in [&song as &dyn Song, &progmem] {
+            let mut cursor = Cursor::default();
+            for (i, expected) in expected.iter().enumerate() {
+                assert_eq!(song.next_note(&mut cursor).as_ref(), Some(expected));
+                assert_eq!(song.note(i).as_ref(), Some(expected));
+                assert_eq!(cursor.index(), i + 1);
+            }
+            assert_eq!(song.next_note(&mut cursor), None);
+            assert_eq!(song.note(11), None);
+            assert_eq!(song.len(), 11);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Data kept in program memory (flash) instead of SRAM.
+//!
+//! An ordinary `static` is copied into the 2 KB of SRAM of the ATmega328P
+//! at startup. A `static` in the `.progmem.data` section stays in the 32 KB
+//! of flash, but can't be read with ordinary loads: the AVR has separate
+//! address spaces for program and data memory, so it must be read with
+//! the `lpm` instruction, one byte at a time.
+//! On any other target the data is read like any other `static`,
+//! so songs in program memory can be tested on the host.
+
+use crate::song_bytes::Bytes;
+
+/// A value in program memory, see the module documentation.
+/// Only its accessors may read it.
+///
+/// ```
+/// # use ard_r_sound_base::progmem::ProgMem;
+/// #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+/// static TABLE: ProgMem<[u8; 4]> = unsafe { ProgMem::new([1, 2, 3, 4]) };
+///
+/// assert_eq!(TABLE.load_at(2), Some(3));
+/// ```
+#[repr(transparent)]
+pub struct ProgMem<T>(T);
+
+impl<T> ProgMem<T> {
+    /// # Safety
+    /// Must be the value of a `static` placed in the `.progmem.data` section on AVR,
+    /// since it is read with `lpm`.
+    pub const unsafe fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Copy> ProgMem<T> {
+    pub fn load(&self) -> T {
+        // `new()` guarantees that it is in program memory
+        unsafe { read(&self.0) }
+    }
+}
+
+impl<T: Copy, const N: usize> ProgMem<[T; N]> {
+    /// The element at `index`, without loading the whole array
+    pub fn load_at(&self, index: usize) -> Option<T> {
+        // `new()` guarantees that the whole array is in program memory
+        self.0.get(index).map(|element| unsafe { read(element) })
+    }
+
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+}
+
+/// Song or library bytes in program memory
+impl<const N: usize> Bytes for &ProgMem<[u8; N]> {
+    fn byte(&self, index: usize) -> Option<u8> {
+        self.load_at(index)
+    }
+}
+
+/// Copy `value` out of program memory.
+///
+/// # Safety
+/// `value` must point into program memory.
+#[cfg(target_arch = "avr")]
+unsafe fn read<T: Copy>(value: *const T) -> T {
+    let mut out = core::mem::MaybeUninit::<T>::uninit();
+    let source = value as *const u8;
+    let target = out.as_mut_ptr() as *mut u8;
+
+    for i in 0..core::mem::size_of::<T>() {
+        let byte: u8;
+        core::arch::asm!(
+            "lpm {}, Z",
+            out(reg) byte,
+            in("Z") source.add(i),
+            options(pure, readonly, nostack, preserves_flags),
+        );
+        target.add(i).write(byte);
+    }
+
+    out.assume_init()
+}
+
+/// Program memory is ordinary memory on any other target.
+///
+/// # Safety
+/// `value` must be valid for reads.
+#[cfg(not(target_arch = "avr"))]
+unsafe fn read<T: Copy>(value: *const T) -> T {
+    core::ptr::read(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song_bytes::{encode_header, encode_note, ByteSong};
+    use crate::{Dynamic, Length, Note, PitchOrRest, Song};
+
+    #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+    static TABLE: ProgMem<[u16; 3]> = unsafe { ProgMem::new([1, 0x1234, 3]) };
+
+    #[test]
+    fn load() {
+        assert_eq!(TABLE.load(), [1, 0x1234, 3]);
+        assert_eq!(TABLE.load_at(1), Some(0x1234));
+        assert_eq!(TABLE.load_at(3), None);
+        assert_eq!(TABLE.len(), 3);
+    }
+
+    #[test]
+    fn song_bytes() {
+        let rest = Note {
+            pitch: PitchOrRest::Rest,
+            length: Length::Multiple(2),
+            dynamic: Dynamic::default(),
+        };
+        let [a, b, c] = encode_header(1, 2).unwrap();
+        let [d, e, f] = encode_note(&rest).unwrap();
+        // built at runtime, which is the same as a `static` off AVR
+        let bytes = unsafe { ProgMem::new([a, b, c, d, e, f, 0, 0]) };
+
+        let song = ByteSong::new(&bytes);
+        assert_eq!(song.validate(), Ok(()));
+        assert_eq!(song.note(1), Some(rest));
+    }
+}
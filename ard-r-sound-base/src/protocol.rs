@@ -0,0 +1,593 @@
+//! Framed binary protocol between the host and the firmware over serial.
+//!
+//! Every message is a frame:
+//!
+//! | bytes  | content                                        |
+//! |--------|------------------------------------------------|
+//! | 1      | `SYNC`                                         |
+//! | 1      | message kind                                   |
+//! | 1      | payload length, at most `MAX_PAYLOAD`          |
+//! | length | payload, multi-byte numbers are little endian  |
+//! | 1      | CRC-8 of the kind, length and payload bytes    |
+//!
+//! The host sends a `Command` and waits for the firmware to answer it
+//! with a `Response`, either `Ack` or `Status`, before sending the next one.
+//! Songs are uploaded in chunks, in the `song_bytes` format to RAM
+//! and in the `library` format to EEPROM.
+
+use crate::crc::{crc8, crc8_update};
+
+/// First byte of every frame
+pub const SYNC: u8 = 0xA5;
+/// Largest payload of a single frame
+pub const MAX_PAYLOAD: usize = 64;
+/// Largest frame, including the sync byte, kind, length and CRC
+pub const MAX_FRAME: usize = MAX_PAYLOAD + 4;
+/// Largest chunk of song data in one `UploadData` frame, leaves room for the offset
+pub const MAX_CHUNK: usize = MAX_PAYLOAD - 2;
+
+/// Message kinds
+mod kind {
+    pub const UPLOAD_BEGIN: u8 = 0x01;
+    pub const UPLOAD_DATA: u8 = 0x02;
+    pub const UPLOAD_END: u8 = 0x03;
+    pub const PLAY: u8 = 0x10;
+    pub const STOP: u8 = 0x11;
+    pub const SET_TEMPO: u8 = 0x12;
+    pub const QUERY_STATUS: u8 = 0x20;
+
+    pub const ACK: u8 = 0x80;
+    pub const STATUS: u8 = 0xA0;
+}
+
+/// A frame with its sync byte and CRC removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: u8,
+    len: u8,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Frame {
+    /// `None` if `payload` is longer than `MAX_PAYLOAD`
+    pub fn new(kind: u8, payload: &[u8]) -> Option<Self> {
+        let mut frame = Self {
+            kind,
+            len: payload.len().try_into().ok()?,
+            payload: [0; MAX_PAYLOAD],
+        };
+        frame
+            .payload
+            .get_mut(..payload.len())?
+            .copy_from_slice(payload);
+        Some(frame)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    /// Write the whole frame into `out`, returns the used part of it.
+    pub fn encode<'a>(&self, out: &'a mut [u8; MAX_FRAME]) -> &'a [u8] {
+        let len = self.len as usize;
+        out[0] = SYNC;
+        out[1] = self.kind;
+        out[2] = self.len;
+        out[3..3 + len].copy_from_slice(self.payload());
+        out[3 + len] = crc8(&out[1..3 + len]);
+        &out[..4 + len]
+    }
+}
+
+/// Why a frame was dropped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The length byte is above `MAX_PAYLOAD`
+    TooLong,
+    /// The CRC doesn't match, the frame was corrupted
+    BadCrc,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DecodeState {
+    Sync,
+    Kind,
+    Len,
+    Payload,
+    Crc,
+}
+
+/// Assembles frames from a stream of bytes, one byte at a time.
+/// Bytes before a `SYNC` byte are skipped, so a dropped frame doesn't
+/// affect the ones after it.
+pub struct FrameDecoder {
+    state: DecodeState,
+    frame: Frame,
+    received: usize,
+    crc: u8,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            state: DecodeState::Sync,
+            frame: Frame {
+                kind: 0,
+                len: 0,
+                payload: [0; MAX_PAYLOAD],
+            },
+            received: 0,
+            crc: 0,
+        }
+    }
+
+    /// Feed the next byte, returns a frame once one is complete.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        match self.state {
+            DecodeState::Sync => {
+                if byte == SYNC {
+                    self.state = DecodeState::Kind;
+                }
+            }
+            DecodeState::Kind => {
+                self.frame.kind = byte;
+                self.crc = crc8_update(0, byte);
+                self.state = DecodeState::Len;
+            }
+            DecodeState::Len => {
+                if byte as usize > MAX_PAYLOAD {
+                    self.state = DecodeState::Sync;
+                    return Some(Err(FrameError::TooLong));
+                }
+                self.frame.len = byte;
+                self.crc = crc8_update(self.crc, byte);
+                self.received = 0;
+                self.state = match byte {
+                    0 => DecodeState::Crc,
+                    _ => DecodeState::Payload,
+                };
+            }
+            DecodeState::Payload => {
+                self.frame.payload[self.received] = byte;
+                self.crc = crc8_update(self.crc, byte);
+                self.received += 1;
+                if self.received == self.frame.len as usize {
+                    self.state = DecodeState::Crc;
+                }
+            }
+            DecodeState::Crc => {
+                self.state = DecodeState::Sync;
+                return Some(match byte == self.crc {
+                    true => Ok(self.frame.clone()),
+                    false => Err(FrameError::BadCrc),
+                });
+            }
+        }
+
+        None
+    }
+}
+
+/// Where a song is stored on the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Storage {
+    /// The song compiled into the firmware
+    Flash = 0,
+    /// Uploaded, lost on reset
+    Ram = 1,
+    /// Uploaded, kept across resets
+    Eeprom = 2,
+}
+
+impl TryFrom<u8> for Storage {
+    type Error = ResultCode;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Storage::Flash,
+            1 => Storage::Ram,
+            2 => Storage::Eeprom,
+            _ => return Err(ResultCode::BadPayload),
+        })
+    }
+}
+
+/// Requests from the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// Start uploading `length` bytes to `target`, which can't be `Flash`.
+    /// Stops playback if it uses `target`.
+    UploadBegin {
+        target: Storage,
+        length: u16,
+    },
+    /// Song bytes starting at `offset`, at most `MAX_CHUNK` of them
+    /// (`to_frame()` fails with `BadPayload` for more)
+    UploadData {
+        offset: u16,
+        data: &'a [u8],
+    },
+    /// All bytes are uploaded, check that they are a valid song (or library)
+    UploadEnd,
+    /// Play the song in `source` from the start.
+    /// `song` is the index of the song in the EEPROM library, 0 otherwise.
+    Play {
+        source: Storage,
+        song: u8,
+        repeat: bool,
+    },
+    Stop,
+    /// Play at `percent` of the song's tempo
+    SetTempo {
+        percent: u16,
+    },
+    QueryStatus,
+}
+
+impl<'a> Command<'a> {
+    /// `BadPayload` for `UploadData` with more than `MAX_CHUNK` bytes
+    pub fn to_frame(&self) -> Result<Frame, ResultCode> {
+        let mut payload = [0u8; MAX_PAYLOAD];
+        let (kind, len) = match self {
+            Command::UploadBegin { target, length } => {
+                payload[0] = *target as u8;
+                payload[1..3].copy_from_slice(&length.to_le_bytes());
+                (kind::UPLOAD_BEGIN, 3)
+            }
+            Command::UploadData { offset, data } => {
+                if data.len() > MAX_CHUNK {
+                    return Err(ResultCode::BadPayload);
+                }
+                payload[..2].copy_from_slice(&offset.to_le_bytes());
+                payload[2..2 + data.len()].copy_from_slice(data);
+                (kind::UPLOAD_DATA, 2 + data.len())
+            }
+            Command::UploadEnd => (kind::UPLOAD_END, 0),
+            Command::Play {
+                source,
+                song,
+                repeat,
+            } => {
+                payload[0] = *source as u8;
+                payload[1] = *song;
+                payload[2] = *repeat as u8;
+                (kind::PLAY, 3)
+            }
+            Command::Stop => (kind::STOP, 0),
+            Command::SetTempo { percent } => {
+                payload[..2].copy_from_slice(&percent.to_le_bytes());
+                (kind::SET_TEMPO, 2)
+            }
+            Command::QueryStatus => (kind::QUERY_STATUS, 0),
+        };
+
+        Ok(Frame::new(kind, &payload[..len]).unwrap())
+    }
+
+    pub fn from_frame(frame: &'a Frame) -> Result<Self, ResultCode> {
+        let payload = frame.payload();
+        let expect_len = |len: usize| match payload.len() == len {
+            true => Ok(()),
+            false => Err(ResultCode::BadPayload),
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+        Ok(match frame.kind {
+            kind::UPLOAD_BEGIN => {
+                expect_len(3)?;
+                Command::UploadBegin {
+                    target: payload[0].try_into()?,
+                    length: u16_at(1),
+                }
+            }
+            kind::UPLOAD_DATA => {
+                if payload.len() < 2 {
+                    return Err(ResultCode::BadPayload);
+                }
+                Command::UploadData {
+                    offset: u16_at(0),
+                    data: &payload[2..],
+                }
+            }
+            kind::UPLOAD_END => {
+                expect_len(0)?;
+                Command::UploadEnd
+            }
+            kind::PLAY => {
+                expect_len(3)?;
+                Command::Play {
+                    source: payload[0].try_into()?,
+                    song: payload[1],
+                    repeat: payload[2] != 0,
+                }
+            }
+            kind::STOP => {
+                expect_len(0)?;
+                Command::Stop
+            }
+            kind::SET_TEMPO => {
+                expect_len(2)?;
+                Command::SetTempo { percent: u16_at(0) }
+            }
+            kind::QUERY_STATUS => {
+                expect_len(0)?;
+                Command::QueryStatus
+            }
+            _ => return Err(ResultCode::UnknownCommand),
+        })
+    }
+}
+
+/// Outcome of a command, sent back in an `Ack`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResultCode {
+    Ok = 0,
+    /// The frame was corrupted, the command was not run
+    BadFrame = 1,
+    UnknownCommand = 2,
+    /// The payload has the wrong size or an invalid value
+    BadPayload = 3,
+    /// The song doesn't fit into the upload target
+    OutOfSpace = 4,
+    /// `UploadData` or `UploadEnd` without `UploadBegin`,
+    /// or `UploadEnd` before all bytes were sent
+    NotUploading = 5,
+    /// The uploaded bytes are not a valid song, or not a valid library for EEPROM
+    InvalidSong = 6,
+    /// There is no valid song in the requested storage,
+    /// or no song at the requested index of the library
+    NoSong = 7,
+}
+
+impl ResultCode {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => ResultCode::Ok,
+            1 => ResultCode::BadFrame,
+            2 => ResultCode::UnknownCommand,
+            3 => ResultCode::BadPayload,
+            4 => ResultCode::OutOfSpace,
+            5 => ResultCode::NotUploading,
+            6 => ResultCode::InvalidSong,
+            7 => ResultCode::NoSong,
+            _ => return None,
+        })
+    }
+}
+
+impl core::fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ResultCode::Ok => "ok",
+            ResultCode::BadFrame => "corrupted frame",
+            ResultCode::UnknownCommand => "unknown command",
+            ResultCode::BadPayload => "invalid command payload",
+            ResultCode::OutOfSpace => "song is too large for the target",
+            ResultCode::NotUploading => "no upload in progress, or not all of it was sent",
+            ResultCode::InvalidSong => "uploaded bytes are not a valid song or library",
+            ResultCode::NoSong => "no song in that storage",
+        })
+    }
+}
+
+/// What the device is doing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub playing: bool,
+    /// Where the current (or last) song is stored
+    pub source: Storage,
+    /// Index of the song in the EEPROM library, 0 for other sources
+    pub song: u8,
+    /// Index of the next note of the first voice
+    pub note_index: u16,
+    /// Number of notes in the first voice
+    pub note_count: u16,
+    /// Tempo in percent of the song's tempo
+    pub tempo_percent: u16,
+}
+
+/// Answers from the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Answer to every command except `QueryStatus`
+    Ack { kind: u8, result: ResultCode },
+    /// Answer to `QueryStatus`
+    Status(Status),
+}
+
+impl Response {
+    pub fn to_frame(&self) -> Frame {
+        match self {
+            Response::Ack { kind, result } => {
+                Frame::new(kind::ACK, &[*kind, *result as u8]).unwrap()
+            }
+            Response::Status(status) => {
+                let [index_low, index_high] = status.note_index.to_le_bytes();
+                let [count_low, count_high] = status.note_count.to_le_bytes();
+                let [tempo_low, tempo_high] = status.tempo_percent.to_le_bytes();
+                Frame::new(
+                    kind::STATUS,
+                    &[
+                        status.playing as u8,
+                        status.source as u8,
+                        status.song,
+                        index_low,
+                        index_high,
+                        count_low,
+                        count_high,
+                        tempo_low,
+                        tempo_high,
+                    ],
+                )
+                .unwrap()
+            }
+        }
+    }
+
+    /// `None` if the frame isn't a valid response
+    pub fn from_frame(frame: &Frame) -> Option<Self> {
+        let payload = frame.payload();
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+        match (frame.kind, payload.len()) {
+            (kind::ACK, 2) => Some(Response::Ack {
+                kind: payload[0],
+                result: ResultCode::from_u8(payload[1])?,
+            }),
+            (kind::STATUS, 9) => Some(Response::Status(Status {
+                playing: payload[0] != 0,
+                source: payload[1].try_into().ok()?,
+                song: payload[2],
+                note_index: u16_at(3),
+                note_count: u16_at(5),
+                tempo_percent: u16_at(7),
+            })),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(bytes: &[u8]) -> Option<Result<Frame, FrameError>> {
+        let mut decoder = FrameDecoder::new();
+        let mut last = None;
+        for byte in bytes {
+            if let Some(result) = decoder.push(*byte) {
+                last = Some(result);
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        let data = [1, 2, 3, SYNC, 5];
+        let commands = [
+            Command::UploadBegin {
+                target: Storage::Eeprom,
+                length: 300,
+            },
+            Command::UploadData {
+                offset: 62,
+                data: &data,
+            },
+            Command::UploadEnd,
+            Command::Play {
+                source: Storage::Eeprom,
+                song: 3,
+                repeat: true,
+            },
+            Command::Stop,
+            Command::SetTempo { percent: 150 },
+            Command::QueryStatus,
+        ];
+
+        for command in commands {
+            let mut buf = [0; MAX_FRAME];
+            let frame = decode_all(command.to_frame().unwrap().encode(&mut buf))
+                .unwrap()
+                .unwrap();
+            assert_eq!(Command::from_frame(&frame), Ok(command));
+        }
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        let data = [0; MAX_CHUNK + 1];
+        let chunk = |len| Command::UploadData {
+            offset: 0,
+            data: &data[..len],
+        };
+
+        assert_eq!(
+            chunk(MAX_CHUNK).to_frame().unwrap().payload().len(),
+            MAX_PAYLOAD
+        );
+        assert_eq!(chunk(MAX_CHUNK + 1).to_frame(), Err(ResultCode::BadPayload));
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let responses = [
+            Response::Ack {
+                kind: kind::PLAY,
+                result: ResultCode::NoSong,
+            },
+            Response::Status(Status {
+                playing: true,
+                source: Storage::Flash,
+                song: 0,
+                note_index: 12,
+                note_count: 300,
+                tempo_percent: 80,
+            }),
+        ];
+
+        for response in responses {
+            let mut buf = [0; MAX_FRAME];
+            let frame = decode_all(response.to_frame().encode(&mut buf))
+                .unwrap()
+                .unwrap();
+            assert_eq!(Response::from_frame(&frame), Some(response));
+        }
+    }
+
+    #[test]
+    fn corrupted_frames_are_dropped() {
+        let mut buf = [0; MAX_FRAME];
+        let mut bytes = [0; MAX_FRAME];
+        let encoded = Command::SetTempo { percent: 100 }.to_frame().unwrap();
+        let encoded = encoded.encode(&mut buf);
+        bytes[..encoded.len()].copy_from_slice(encoded);
+        bytes[3] ^= 0xFF;
+        assert_eq!(
+            decode_all(&bytes[..encoded.len()]),
+            Some(Err(FrameError::BadCrc))
+        );
+
+        assert_eq!(
+            decode_all(&[SYNC, kind::STOP, MAX_PAYLOAD as u8 + 1]),
+            Some(Err(FrameError::TooLong))
+        );
+    }
+
+    #[test]
+    fn garbage_before_a_frame_is_skipped() {
+        let mut buf = [0; MAX_FRAME];
+        let frame = Command::Stop.to_frame().unwrap();
+        let encoded = frame.encode(&mut buf);
+
+        let mut decoder = FrameDecoder::new();
+        for byte in [0x00, 0x42, 0xFF] {
+            assert_eq!(decoder.push(byte), None);
+        }
+        let mut result = None;
+        for byte in encoded {
+            result = decoder.push(*byte);
+        }
+        assert_eq!(result, Some(Ok(frame)));
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let frame = Frame::new(0x7F, &[]).unwrap();
+        assert_eq!(Command::from_frame(&frame), Err(ResultCode::UnknownCommand));
+
+        let frame = Frame::new(kind::PLAY, &[9, 0, 0]).unwrap();
+        assert_eq!(Command::from_frame(&frame), Err(ResultCode::BadPayload));
+
+        let frame = Frame::new(kind::STOP, &[0]).unwrap();
+        assert_eq!(Command::from_frame(&frame), Err(ResultCode::BadPayload));
+
+        assert_eq!(Frame::new(kind::UPLOAD_DATA, &[0; MAX_PAYLOAD + 1]), None);
+    }
+}
@@ -0,0 +1,308 @@
+//! Compact byte format for songs that aren't compiled into the firmware,
+//! like songs uploaded over serial or stored in EEPROM.
+//!
+//! Layout, multi-byte numbers are little endian:
+//!
+//! | bytes            | content                                    |
+//! |------------------|--------------------------------------------|
+//! | 1                | number of unique notes                     |
+//! | 2                | number of notes in the song                |
+//! | 3 * uniques      | unique notes: pitch, length, dynamic       |
+//! | 1 * notes        | the song, as indexes into the unique notes |
+
+use crate::{Dynamic, Length, Note, PitchClass, PitchOrRest, Song};
+
+/// Size of the header before the unique notes
+pub const HEADER_LEN: usize = 3;
+/// Size of one encoded unique note
+pub const NOTE_LEN: usize = 3;
+
+/// Pitch byte of a rest
+const REST: u8 = 0xFF;
+/// Top bits of a length byte
+const LENGTH_KIND_MASK: u8 = 0b1100_0000;
+const LENGTH_MULTIPLE: u8 = 0b0100_0000;
+const LENGTH_DIVISION: u8 = 0b1000_0000;
+/// Largest multiple or division a length byte can hold
+pub const MAX_LENGTH_FACTOR: u32 = 0b0011_1111;
+
+/// Why a song can't be encoded or decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SongError {
+    /// Fewer bytes than the header says there are
+    Truncated,
+    /// More than 255 unique notes, or more than 65535 notes
+    TooManyNotes,
+    /// The octave is outside of -8..=7, or of -4..=5 when packed
+    OctaveOutOfRange,
+    /// A length multiple or division above `MAX_LENGTH_FACTOR`, or 16 when packed
+    LengthOutOfRange,
+    /// A byte that doesn't decode to a note
+    InvalidNote,
+    /// The song refers to a unique note that doesn't exist
+    IndexOutOfRange,
+}
+
+impl core::fmt::Display for SongError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            SongError::Truncated => "song data is truncated",
+            SongError::TooManyNotes => "too many notes",
+            SongError::OctaveOutOfRange => "octave is out of range",
+            SongError::LengthOutOfRange => "note length is out of range",
+            SongError::InvalidNote => "invalid note",
+            SongError::IndexOutOfRange => "note index is out of range",
+        })
+    }
+}
+
+/// Somewhere to read encoded song bytes from.
+pub trait Bytes {
+    /// The byte at `index`, `None` past the end
+    fn byte(&self, index: usize) -> Option<u8>;
+}
+
+impl Bytes for &[u8] {
+    fn byte(&self, index: usize) -> Option<u8> {
+        self.get(index).copied()
+    }
+}
+
+/// Header of an encoded song with `uniques` unique notes and `notes` notes.
+pub fn encode_header(uniques: usize, notes: usize) -> Result<[u8; HEADER_LEN], SongError> {
+    let uniques: u8 = uniques.try_into().map_err(|_| SongError::TooManyNotes)?;
+    let notes: u16 = notes.try_into().map_err(|_| SongError::TooManyNotes)?;
+    let [low, high] = notes.to_le_bytes();
+    Ok([uniques, low, high])
+}
+
+/// Total size of a song with `uniques` unique notes and `notes` notes.
+pub fn encoded_len(uniques: usize, notes: usize) -> usize {
+    HEADER_LEN + uniques * NOTE_LEN + notes
+}
+
+pub fn encode_note(note: &Note) -> Result<[u8; NOTE_LEN], SongError> {
+    let pitch = match &note.pitch {
+        PitchOrRest::Pitch { class, octave } => {
+            if !(-8..=7).contains(octave) {
+                return Err(SongError::OctaveOutOfRange);
+            }
+            ((*octave as u8) << 4) | class.half_steps_from_a() as u8
+        }
+        PitchOrRest::Rest => REST,
+    };
+
+    let factor = |x: u32| match x {
+        1..=MAX_LENGTH_FACTOR => Ok(x as u8),
+        _ => Err(SongError::LengthOutOfRange),
+    };
+    let length = match note.length {
+        Length::Unit => 0,
+        Length::Multiple(m) => LENGTH_MULTIPLE | factor(m)?,
+        Length::Division(d) => LENGTH_DIVISION | factor(d)?,
+    };
+
+    Ok([pitch, length, note.dynamic as u8])
+}
+
+pub fn decode_note(bytes: [u8; NOTE_LEN]) -> Result<Note, SongError> {
+    let [pitch, length, dynamic] = bytes;
+
+    let pitch = match pitch {
+        REST => PitchOrRest::Rest,
+        _ => PitchOrRest::Pitch {
+            class: enum_iterator::all::<PitchClass>()
+                .nth((pitch & 0x0F) as usize)
+                .ok_or(SongError::InvalidNote)?,
+            // shift the sign bit back into place
+            octave: (pitch as i8) >> 4,
+        },
+    };
+
+    let factor = (length & !LENGTH_KIND_MASK) as u32;
+    let length = match length & LENGTH_KIND_MASK {
+        0 if factor == 0 => Length::Unit,
+        LENGTH_MULTIPLE if factor > 0 => Length::Multiple(factor),
+        LENGTH_DIVISION if factor > 0 => Length::Division(factor),
+        _ => return Err(SongError::InvalidNote),
+    };
+
+    let dynamic = enum_iterator::all::<Dynamic>()
+        .nth(dynamic as usize)
+        .ok_or(SongError::InvalidNote)?;
+
+    Ok(Note {
+        pitch,
+        length,
+        dynamic,
+    })
+}
+
+/// A song in the byte format, read one note at a time.
+pub struct ByteSong<B> {
+    bytes: B,
+}
+
+impl<B: Bytes> ByteSong<B> {
+    pub const fn new(bytes: B) -> Self {
+        Self { bytes }
+    }
+
+    fn unique_count(&self) -> usize {
+        self.bytes.byte(0).unwrap_or(0) as usize
+    }
+
+    fn unique(&self, index: usize) -> Result<Note, SongError> {
+        let start = HEADER_LEN + index * NOTE_LEN;
+        let byte = |i| self.bytes.byte(start + i).ok_or(SongError::Truncated);
+        decode_note([byte(0)?, byte(1)?, byte(2)?])
+    }
+
+    /// Check that every note can be decoded, so that `note()` only returns
+    /// `None` past the end.
+    pub fn validate(&self) -> Result<(), SongError> {
+        let header = |i| self.bytes.byte(i).ok_or(SongError::Truncated);
+        header(0)?;
+        header(1)?;
+        header(2)?;
+
+        for index in 0..self.unique_count() {
+            self.unique(index)?;
+        }
+
+        let list_start = HEADER_LEN + self.unique_count() * NOTE_LEN;
+        for i in 0..self.len() {
+            let index = self
+                .bytes
+                .byte(list_start + i)
+                .ok_or(SongError::Truncated)?;
+            if index as usize >= self.unique_count() {
+                return Err(SongError::IndexOutOfRange);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<B: Bytes> Song for ByteSong<B> {
+    fn note(&self, index: usize) -> Option<Note> {
+        if index >= self.len() {
+            return None;
+        }
+        let list_start = HEADER_LEN + self.unique_count() * NOTE_LEN;
+        let unique = self.bytes.byte(list_start + index)?;
+        self.unique(unique as usize).ok()
+    }
+
+    fn len(&self) -> usize {
+        let low = self.bytes.byte(1).unwrap_or(0);
+        let high = self.bytes.byte(2).unwrap_or(0);
+        u16::from_le_bytes([low, high]) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(pitch: PitchOrRest, length: Length, dynamic: Dynamic) -> Note {
+        Note {
+            pitch,
+            length,
+            dynamic,
+        }
+    }
+
+    #[test]
+    fn notes_round_trip() {
+        let notes = [
+            note(PitchOrRest::Rest, Length::Unit, Dynamic::MezzoForte),
+            note(
+                PitchOrRest::Pitch {
+                    class: PitchClass::GSharpAFlat,
+                    octave: -8,
+                },
+                Length::Division(32),
+                Dynamic::Pianissississimo,
+            ),
+            note(
+                PitchOrRest::Pitch {
+                    class: PitchClass::A,
+                    octave: 7,
+                },
+                Length::Multiple(MAX_LENGTH_FACTOR),
+                Dynamic::Fortissississimo,
+            ),
+        ];
+
+        for note in notes {
+            assert_eq!(decode_note(encode_note(&note).unwrap()), Ok(note));
+        }
+    }
+
+    #[test]
+    fn out_of_range_notes() {
+        let high = PitchOrRest::Pitch {
+            class: PitchClass::C,
+            octave: 8,
+        };
+        assert_eq!(
+            encode_note(&note(high, Length::Unit, Dynamic::Piano)),
+            Err(SongError::OctaveOutOfRange)
+        );
+        assert_eq!(
+            encode_note(&note(
+                PitchOrRest::Rest,
+                Length::Division(64),
+                Dynamic::Piano
+            )),
+            Err(SongError::LengthOutOfRange)
+        );
+        assert_eq!(decode_note([0x0C, 0, 0]), Err(SongError::InvalidNote));
+        assert_eq!(
+            decode_note([REST, LENGTH_MULTIPLE, 0]),
+            Err(SongError::InvalidNote)
+        );
+        assert_eq!(decode_note([REST, 0, 10]), Err(SongError::InvalidNote));
+    }
+
+    #[test]
+    fn byte_song() {
+        let rest = note(PitchOrRest::Rest, Length::Multiple(2), Dynamic::Forte);
+        let a = note(
+            PitchOrRest::Pitch {
+                class: PitchClass::A,
+                octave: 0,
+            },
+            Length::Unit,
+            Dynamic::Piano,
+        );
+
+        let mut bytes = [0u8; 3 + 2 * 3 + 3];
+        bytes[..3].copy_from_slice(&encode_header(2, 3).unwrap());
+        bytes[3..6].copy_from_slice(&encode_note(&a).unwrap());
+        bytes[6..9].copy_from_slice(&encode_note(&rest).unwrap());
+        bytes[9..].copy_from_slice(&[0, 1, 0]);
+        assert_eq!(bytes.len(), encoded_len(2, 3));
+
+        let song = ByteSong::new(&bytes[..]);
+        assert_eq!(song.validate(), Ok(()));
+        assert_eq!(song.len(), 3);
+        assert_eq!(song.note(0), Some(a.clone()));
+        assert_eq!(song.note(1), Some(rest));
+        assert_eq!(song.note(2), Some(a));
+        assert_eq!(song.note(3), None);
+
+        assert_eq!(
+            ByteSong::new(&bytes[..bytes.len() - 1]).validate(),
+            Err(SongError::Truncated)
+        );
+
+        bytes[9] = 2;
+        assert_eq!(
+            ByteSong::new(&bytes[..]).validate(),
+            Err(SongError::IndexOutOfRange)
+        );
+    }
+}
@@ -0,0 +1,542 @@
+//! Target-independent math for driving buzzers from the AVR timers.
+
+/// Clock rate of the ATmega328P on the Arduino Uno, in Hz
+pub const CLOCK_RATE: f32 = 16_000_000.;
+
+/// Clock prescaler of an AVR timer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Prescaler {
+    Direct,
+    Prescale8,
+    /// TIMER2 only
+    Prescale32,
+    Prescale64,
+    /// TIMER2 only
+    Prescale128,
+    Prescale256,
+    Prescale1024,
+}
+
+impl Prescaler {
+    /// Number of clock cycles per timer tick.
+    pub fn factor(&self) -> u32 {
+        match self {
+            Prescaler::Direct => 1,
+            Prescaler::Prescale8 => 8,
+            Prescaler::Prescale32 => 32,
+            Prescaler::Prescale64 => 64,
+            Prescaler::Prescale128 => 128,
+            Prescaler::Prescale256 => 256,
+            Prescaler::Prescale1024 => 1024,
+        }
+    }
+}
+
+/// The prescalers and counter size of an AVR timer.
+#[derive(Debug)]
+pub struct Timer {
+    /// Available prescalers, from the finest to the coarsest
+    pub prescalers: &'static [Prescaler],
+    /// Most ticks between two compare matches
+    pub max_ticks: u32,
+}
+
+impl Timer {
+    /// The 16-bit TIMER1.
+    pub const TIMER1: Timer = Timer {
+        prescalers: &[
+            Prescaler::Direct,
+            Prescaler::Prescale8,
+            Prescaler::Prescale64,
+            Prescaler::Prescale256,
+            Prescaler::Prescale1024,
+        ],
+        max_ticks: u16::MAX as u32,
+    };
+
+    /// The 8-bit TIMER2.
+    pub const TIMER2: Timer = Timer {
+        prescalers: &[
+            Prescaler::Direct,
+            Prescaler::Prescale8,
+            Prescaler::Prescale32,
+            Prescaler::Prescale64,
+            Prescaler::Prescale128,
+            Prescaler::Prescale256,
+            Prescaler::Prescale1024,
+        ],
+        max_ticks: u8::MAX as u32 + 1,
+    };
+}
+
+/// Why a frequency or duration can't be generated by the timer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimerError {
+    /// The frequency is zero, negative or not a number.
+    InvalidFrequency,
+    /// Shorter than a single tick of the undivided clock.
+    TooShort,
+    /// Longer than the counter can count, even with the largest prescaler.
+    TooLong,
+    /// The duty cycle is not between 0 and 1.
+    InvalidDutyCycle,
+}
+
+impl core::fmt::Display for TimerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            TimerError::InvalidFrequency => "frequency must be positive",
+            TimerError::TooShort => "duration is shorter than one clock cycle",
+            TimerError::TooLong => "duration is too long for the timer",
+            TimerError::InvalidDutyCycle => "duty cycle must be between 0 and 1",
+        })
+    }
+}
+
+/// Represents a timer duration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimerDuration {
+    /// Private field, use ticks()
+    ticks: u16,
+    /// Private field, use prescale()
+    prescale: Prescaler,
+}
+
+impl TimerDuration {
+    pub fn new(ticks: u16, prescale: Prescaler) -> Self {
+        Self { ticks, prescale }
+    }
+
+    /// Number of timer ticks until the compare match.
+    pub fn ticks(&self) -> u16 {
+        self.ticks
+    }
+
+    /// Value for the output compare register.
+    /// In CTC mode the counter goes from 0 up to and including this value,
+    /// so it is one less than `ticks()`.
+    pub fn compare_value(&self) -> u16 {
+        self.ticks.saturating_sub(1)
+    }
+
+    pub fn prescale(&self) -> Prescaler {
+        self.prescale
+    }
+
+    pub fn total_ticks(&self) -> u32 {
+        self.ticks as u32 * self.prescale.factor()
+    }
+
+    pub fn seconds(&self) -> f32 {
+        self.total_ticks() as f32 / CLOCK_RATE
+    }
+
+    /// How many times per second this duration elapses.
+    /// For a half period this is twice the frequency of the tone.
+    pub fn frequency(&self) -> f32 {
+        CLOCK_RATE / self.total_ticks() as f32
+    }
+
+    /// Duration of half a period of `frequency`, for a pin that is toggled
+    /// on every compare match of TIMER1.
+    pub fn try_from_half_period(frequency: f32) -> Result<Self, TimerError> {
+        Self::try_from_half_period_on(frequency, &Timer::TIMER1)
+    }
+
+    /// Like `try_from_half_period()`, for any `timer`.
+    pub fn try_from_half_period_on(frequency: f32, timer: &Timer) -> Result<Self, TimerError> {
+        Self::try_from_ticks_on(ticks_per_period(frequency * 2.)?, timer)
+    }
+
+    /// Pick the TIMER1 prescaler that comes closest to `ticks` clock cycles,
+    /// preferring the finer one if two are equally close.
+    pub fn try_from_ticks(ticks: u32) -> Result<Self, TimerError> {
+        Self::try_from_ticks_on(ticks, &Timer::TIMER1)
+    }
+
+    /// Like `try_from_ticks()`, for any `timer`.
+    pub fn try_from_ticks_on(ticks: u32, timer: &Timer) -> Result<Self, TimerError> {
+        if ticks == 0 {
+            return Err(TimerError::TooShort);
+        }
+
+        let mut best: Option<(u32, Self)> = None;
+
+        for &prescale in timer.prescalers {
+            let factor = prescale.factor();
+
+            // round to the nearest tick of this prescaler
+            let scaled = ticks.saturating_add(factor / 2) / factor;
+            if scaled == 0 || scaled > timer.max_ticks {
+                continue;
+            }
+
+            let error = (scaled * factor).abs_diff(ticks);
+            let better = match &best {
+                Some((best_error, _)) => error < *best_error,
+                None => true,
+            };
+
+            if better {
+                best = Some((error, Self::new(scaled as u16, prescale)));
+            }
+        }
+
+        best.map(|(_, duration)| duration)
+            .ok_or(TimerError::TooLong)
+    }
+}
+
+/// Two timer durations, one for active and one for inactive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveInactiveTimerDurations {
+    pub active: TimerDuration,
+    pub inactive: TimerDuration,
+}
+
+impl ActiveInactiveTimerDurations {
+    /// Split a period of `frequency` so that the pin is active for
+    /// `duty_cycle` of it, see `Dynamic::duty_cycle()`.
+    pub fn try_from_frequency(frequency: f32, duty_cycle: f32) -> Result<Self, TimerError> {
+        if !(duty_cycle > 0. && duty_cycle < 1.) {
+            return Err(TimerError::InvalidDutyCycle);
+        }
+
+        let ticks_per_period = ticks_per_period(frequency)?;
+        let active_ticks = ((ticks_per_period as f32 * duty_cycle) as u32).max(1);
+        let active = TimerDuration::try_from_ticks(active_ticks)?;
+
+        // the inactive part gets whatever the active part didn't,
+        // so rounding the active duration doesn't change the pitch
+        let inactive_ticks = ticks_per_period.saturating_sub(active.total_ticks());
+        let inactive = TimerDuration::try_from_ticks(inactive_ticks)?;
+
+        Ok(Self { active, inactive })
+    }
+
+    /// The frequency that is actually played.
+    pub fn frequency(&self) -> f32 {
+        CLOCK_RATE / (self.active.total_ticks() + self.inactive.total_ticks()) as f32
+    }
+}
+
+/// Number of clock cycles in one period of `frequency`, rounded.
+fn ticks_per_period(frequency: f32) -> Result<u32, TimerError> {
+    if frequency.is_nan() || frequency <= 0. {
+        return Err(TimerError::InvalidFrequency);
+    }
+
+    // float to int casts saturate, very low frequencies end up as `TooLong`
+    Ok((CLOCK_RATE / frequency + 0.5) as u32)
+}
+
+/// How far `achieved` is from `target` in cents (hundredths of a half step),
+/// positive if `achieved` is sharp.
+pub fn cent_error(target: f32, achieved: f32) -> f32 {
+    1200. * ln(achieved / target) / core::f32::consts::LN_2
+}
+
+/// Natural logarithm, since `core` has none.
+/// Uses ln(x) = 2 atanh((x - 1) / (x + 1)), which converges quickly
+/// for the ratios close to 1 that `cent_error` deals with.
+fn ln(x: f32) -> f32 {
+    let y = (x - 1.) / (x + 1.);
+    let mut term = y;
+    let mut sum = 0.;
+    let mut n = 1.;
+
+    while term.abs() > 1e-9 && n < 1000. {
+        sum += term / n;
+        term *= y * y;
+        n += 2.;
+    }
+
+    2. * sum
+}
+
+/// Switches the buzzer between its active and inactive state,
+/// one step per timer compare match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToneState {
+    /// are we active right now?
+    active: bool,
+    /// the durations of the active and inactive states
+    timer_durations: ActiveInactiveTimerDurations,
+}
+
+impl ToneState {
+    /// Start in the active state, the pin should be raised for
+    /// `timer_durations.active`.
+    pub fn start(timer_durations: ActiveInactiveTimerDurations) -> Self {
+        Self {
+            active: true,
+            timer_durations,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Duration of the current state.
+    pub fn duration(&self) -> &TimerDuration {
+        match self.active {
+            true => &self.timer_durations.active,
+            false => &self.timer_durations.inactive,
+        }
+    }
+
+    /// Switch to the other state, call on every compare match.
+    /// Afterwards, the pin should be set high if `is_active()` and the timer
+    /// should run for `duration()`.
+    pub fn advance(&mut self) {
+        self.active = !self.active;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the duty cycle of notes without a dynamic marking
+    const DUTY_CYCLE: f32 = 0.01;
+
+    #[test]
+    fn prescaler_total_ticks_and_seconds() {
+        let cases = [
+            (Prescaler::Direct, 1),
+            (Prescaler::Prescale8, 8),
+            (Prescaler::Prescale32, 32),
+            (Prescaler::Prescale64, 64),
+            (Prescaler::Prescale128, 128),
+            (Prescaler::Prescale256, 256),
+            (Prescaler::Prescale1024, 1024),
+        ];
+
+        for (prescale, factor) in cases {
+            let duration = TimerDuration::new(1000, prescale);
+            assert_eq!(prescale.factor(), factor);
+            assert_eq!(duration.total_ticks(), 1000 * factor);
+            assert_eq!(duration.seconds(), 1000. * factor as f32 / CLOCK_RATE);
+        }
+    }
+
+    #[test]
+    fn ticks_that_fit_are_not_prescaled() {
+        let duration = TimerDuration::try_from_ticks(u16::MAX as u32).unwrap();
+        assert_eq!(duration, TimerDuration::new(u16::MAX, Prescaler::Direct));
+    }
+
+    #[test]
+    fn ticks_that_do_not_fit_are_prescaled() {
+        let duration = TimerDuration::try_from_ticks(u16::MAX as u32 + 1).unwrap();
+        assert_eq!(duration.prescale(), Prescaler::Prescale8);
+        assert_eq!(duration.ticks(), 8192);
+
+        let duration = TimerDuration::try_from_ticks(u16::MAX as u32 * 8 + 8).unwrap();
+        assert_eq!(duration.prescale(), Prescaler::Prescale64);
+    }
+
+    #[test]
+    fn active_part_is_at_least_a_tick() {
+        // the highest frequency where `DUTY_CYCLE` is still exactly a tick
+        let max = CLOCK_RATE / 100.;
+        let durations = ActiveInactiveTimerDurations::try_from_frequency(max, DUTY_CYCLE).unwrap();
+        assert_eq!(durations.active.total_ticks(), 1);
+        assert_eq!(durations.inactive.total_ticks(), 99);
+
+        // above it the active part would round down to zero ticks,
+        // it's kept at one, so the duty cycle grows instead of the note going silent
+        let durations =
+            ActiveInactiveTimerDurations::try_from_frequency(max * 4., DUTY_CYCLE).unwrap();
+        assert_eq!(durations.active.total_ticks(), 1);
+        assert_eq!(durations.inactive.total_ticks(), 24);
+        assert_eq!(durations.frequency(), max * 4.);
+    }
+
+    #[test]
+    fn ticks_that_only_fit_coarse_prescalers() {
+        let duration = TimerDuration::try_from_ticks(u16::MAX as u32 * 256).unwrap();
+        assert_eq!(
+            duration,
+            TimerDuration::new(u16::MAX, Prescaler::Prescale256)
+        );
+
+        let duration = TimerDuration::try_from_ticks(u16::MAX as u32 * 1024).unwrap();
+        assert_eq!(
+            duration,
+            TimerDuration::new(u16::MAX, Prescaler::Prescale1024)
+        );
+
+        assert_eq!(
+            TimerDuration::try_from_ticks(u16::MAX as u32 * 1024 + 1024),
+            Err(TimerError::TooLong)
+        );
+        assert_eq!(TimerDuration::try_from_ticks(0), Err(TimerError::TooShort));
+    }
+
+    #[test]
+    fn closest_prescaler_wins() {
+        // doesn't fit without a prescaler, 8 and 64 are both 3 cycles off,
+        // so the finer one wins
+        let duration = TimerDuration::try_from_ticks(8 * 9000 + 3).unwrap();
+        assert_eq!(duration, TimerDuration::new(9000, Prescaler::Prescale8));
+
+        // exactly representable with 256 but not with 64
+        let duration = TimerDuration::try_from_ticks(256 * 40000).unwrap();
+        assert_eq!(duration, TimerDuration::new(40000, Prescaler::Prescale256));
+    }
+
+    #[test]
+    fn compare_value_is_one_less_than_ticks() {
+        assert_eq!(
+            TimerDuration::new(100, Prescaler::Direct).compare_value(),
+            99
+        );
+        assert_eq!(TimerDuration::new(0, Prescaler::Direct).compare_value(), 0);
+    }
+
+    #[test]
+    fn middle_a() {
+        let durations = ActiveInactiveTimerDurations::try_from_frequency(440., DUTY_CYCLE).unwrap();
+        assert_eq!(durations.active, TimerDuration::new(363, Prescaler::Direct));
+        assert_eq!(
+            durations.inactive,
+            TimerDuration::new(36001, Prescaler::Direct)
+        );
+        let period = durations.active.seconds() + durations.inactive.seconds();
+        assert!((1. / period - durations.frequency()).abs() < 0.001);
+        assert!(cent_error(440., durations.frequency()).abs() < 0.1);
+    }
+
+    #[test]
+    fn lowest_representable_frequency() {
+        // the inactive duration can be at most 65535 * 1024 cycles,
+        // which is about 0.24 Hz
+        assert!(ActiveInactiveTimerDurations::try_from_frequency(0.25, DUTY_CYCLE).is_ok());
+        assert_eq!(
+            ActiveInactiveTimerDurations::try_from_frequency(0.2, DUTY_CYCLE),
+            Err(TimerError::TooLong)
+        );
+    }
+
+    #[test]
+    fn invalid_frequencies() {
+        for frequency in [0., -440., f32::NAN] {
+            assert_eq!(
+                ActiveInactiveTimerDurations::try_from_frequency(frequency, DUTY_CYCLE),
+                Err(TimerError::InvalidFrequency)
+            );
+            assert_eq!(
+                TimerDuration::try_from_half_period(frequency),
+                Err(TimerError::InvalidFrequency)
+            );
+        }
+    }
+
+    #[test]
+    fn duty_cycle_splits_the_period() {
+        let durations = ActiveInactiveTimerDurations::try_from_frequency(1000., 0.5).unwrap();
+        assert_eq!(
+            durations.active,
+            TimerDuration::new(8000, Prescaler::Direct)
+        );
+        assert_eq!(
+            durations.inactive,
+            TimerDuration::new(8000, Prescaler::Direct)
+        );
+
+        let durations = ActiveInactiveTimerDurations::try_from_frequency(1000., 0.25).unwrap();
+        assert_eq!(durations.active.total_ticks(), 4000);
+        assert_eq!(durations.inactive.total_ticks(), 12000);
+    }
+
+    #[test]
+    fn invalid_duty_cycles() {
+        for duty_cycle in [0., 1., -0.5, 2., f32::NAN] {
+            assert_eq!(
+                ActiveInactiveTimerDurations::try_from_frequency(440., duty_cycle),
+                Err(TimerError::InvalidDutyCycle)
+            );
+        }
+    }
+
+    #[test]
+    fn piano_range_is_within_half_a_cent() {
+        // A0 up to C8, one half step at a time
+        let mut frequency: f32 = 27.5;
+        while frequency < 4200. {
+            let durations =
+                ActiveInactiveTimerDurations::try_from_frequency(frequency, DUTY_CYCLE).unwrap();
+            assert!(cent_error(frequency, durations.frequency()).abs() < 0.5);
+
+            let half = TimerDuration::try_from_half_period(frequency).unwrap();
+            assert!(cent_error(frequency, half.frequency() / 2.).abs() < 0.5);
+
+            frequency *= 1.059_463_1;
+        }
+    }
+
+    #[test]
+    fn cent_error_of_known_intervals() {
+        assert!((cent_error(440., 880.) - 1200.).abs() < 0.01);
+        assert!((cent_error(440., 220.) + 1200.).abs() < 0.01);
+        assert!((cent_error(440., 466.163_76) - 100.).abs() < 0.01);
+        assert_eq!(cent_error(440., 440.), 0.);
+    }
+
+    #[test]
+    fn half_period_of_middle_a() {
+        let half = TimerDuration::try_from_half_period(440.).unwrap();
+        assert_eq!(half, TimerDuration::new(18182, Prescaler::Direct));
+    }
+
+    #[test]
+    fn timer2_counts_up_to_256_ticks() {
+        let duration = TimerDuration::try_from_ticks_on(256, &Timer::TIMER2).unwrap();
+        assert_eq!(duration, TimerDuration::new(256, Prescaler::Direct));
+        assert_eq!(duration.compare_value(), u8::MAX as u16);
+
+        // only representable with TIMER2's extra prescalers
+        let duration = TimerDuration::try_from_ticks_on(32 * 200, &Timer::TIMER2).unwrap();
+        assert_eq!(duration, TimerDuration::new(200, Prescaler::Prescale32));
+
+        assert_eq!(
+            TimerDuration::try_from_ticks_on(1024 * 257, &Timer::TIMER2),
+            Err(TimerError::TooLong)
+        );
+    }
+
+    #[test]
+    fn timer1_never_picks_timer2_prescalers() {
+        let duration = TimerDuration::try_from_ticks(128 * 60000).unwrap();
+        assert_ne!(duration.prescale(), Prescaler::Prescale128);
+    }
+
+    #[test]
+    fn timer2_middle_a_is_within_a_cent() {
+        let half = TimerDuration::try_from_half_period_on(440., &Timer::TIMER2).unwrap();
+        assert!(cent_error(440., half.frequency() / 2.).abs() < 1.);
+    }
+
+    #[test]
+    fn tone_state_alternates_durations() {
+        let durations = ActiveInactiveTimerDurations {
+            active: TimerDuration::new(10, Prescaler::Direct),
+            inactive: TimerDuration::new(990, Prescaler::Prescale8),
+        };
+        let mut state = ToneState::start(durations.clone());
+
+        assert!(state.is_active());
+        assert_eq!(state.duration(), &durations.active);
+
+        state.advance();
+        assert!(!state.is_active());
+        assert_eq!(state.duration(), &durations.inactive);
+
+        // going active again must use the active duration
+        state.advance();
+        assert!(state.is_active());
+        assert_eq!(state.duration(), &durations.active);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+pub use ard_r_sound_base::*;
+
+/// Everything in an ABC file
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct ABC {
+    pub version: Option<Version>,
+    pub headers: Headers,
+    /// Notes of the first voice
+    pub notes: Vec<Note>,
+    /// Positions of bar lines, as the number of notes before each one
+    pub bar_lines: Vec<usize>,
+    /// Every voice after the first one, in order of appearance
+    pub voices: Vec<Voice>,
+}
+
+/// A voice of a tune with several voices, started by a `V:` field
+#[derive(Debug, Clone, Default)]
+pub struct Voice {
+    /// The voice's id, as in `V:<id>`.
+    /// `None` for notes before the first `V:` field.
+    pub id: Option<String>,
+    pub notes: Vec<Note>,
+    pub bar_lines: Vec<usize>,
+}
+
+impl ABC {
+    /// A tune with `notes` and `bar_lines` in the first voice, and further `voices`.
+    pub fn new(
+        version: Option<Version>,
+        headers: Headers,
+        notes: Vec<Note>,
+        bar_lines: Vec<usize>,
+        voices: Vec<Voice>,
+    ) -> Self {
+        Self {
+            version,
+            headers,
+            notes,
+            bar_lines,
+            voices,
+        }
+    }
+
+    pub fn total_playtime_secs(&self) -> f64 {
+        let mut total = 0f64;
+        // TODO: support non 4/4 time
+        const BASE: f64 = 1. / 4.;
+        for note in &self.notes {
+            total += match note.length {
+                Length::Unit => BASE,
+                Length::Multiple(x) => BASE * x as f64,
+                Length::Division(x) => BASE / x as f64,
+            }
+        }
+        total
+    }
+
+    /// Number of voices, at least 1
+    pub fn voice_count(&self) -> usize {
+        1 + self.voices.len()
+    }
+
+    /// The voice at `index` as a tune of its own, with the same headers.
+    /// Voice 0 is the first voice, `self.notes`.
+    pub fn voice(&self, index: usize) -> Option<ABC> {
+        let (notes, bar_lines) = match index {
+            0 => (self.notes.clone(), self.bar_lines.clone()),
+            _ => {
+                let voice = self.voices.get(index - 1)?;
+                (voice.notes.clone(), voice.bar_lines.clone())
+            }
+        };
+
+        Some(ABC::new(
+            self.version.clone(),
+            self.headers.clone(),
+            notes,
+            bar_lines,
+            Vec::new(),
+        ))
+    }
+
+    /// Transpose every voice by `half_steps`, down if negative.
+    pub fn transpose(&mut self, half_steps: i32) -> Result<(), anyhow::Error> {
+        let voices = self.voices.iter_mut().map(|voice| &mut voice.notes);
+        for notes in std::iter::once(&mut self.notes).chain(voices) {
+            for note in notes {
+                note.pitch = note.pitch.transposed(half_steps).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "{:?} is out of range transposed by {}",
+                        note.pitch,
+                        half_steps
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The last value of header `key`, if any
+    pub fn header(&self, key: char) -> Option<&str> {
+        Some(self.headers.get(&key)?.last()?.trim())
+    }
+
+    /// The unit note length from `L:`, as a fraction of a whole note.
+    /// 1/8 without one.
+    pub fn unit_length(&self) -> (u32, u32) {
+        self.header('L').and_then(parse_fraction).unwrap_or((1, 8))
+    }
+
+    /// The meter (time signature) from `M:`, `C` being 4/4 and `C|` 2/2.
+    /// 4/4 without one.
+    pub fn meter(&self) -> (u32, u32) {
+        match self.header('M') {
+            Some("C") => (4, 4),
+            Some("C|") => (2, 2),
+            Some(meter) => parse_fraction(meter).unwrap_or((4, 4)),
+            None => (4, 4),
+        }
+    }
+
+    /// The tempo from `Q:`, like `1/4=120`, or just `120` beats of the unit length.
+    /// Any text around it, like `"Allegro" 1/4=120`, is ignored.
+    /// 120 quarter notes per minute without one.
+    pub fn tempo(&self) -> Tempo {
+        const DEFAULT: Tempo = Tempo {
+            beat: (1, 4),
+            beats_per_minute: 120,
+        };
+
+        let tempo = match self.header('Q') {
+            Some(tempo) => tempo,
+            None => return DEFAULT,
+        };
+        let words = tempo.split_whitespace().filter(|word| !word.contains('"'));
+        for word in words {
+            let (beat, bpm) = match word.split_once('=') {
+                Some((beat, bpm)) => (parse_fraction(beat), bpm),
+                None => (Some(self.unit_length()), word),
+            };
+            if let (Some(beat), Ok(beats_per_minute)) = (beat, bpm.parse()) {
+                return Tempo {
+                    beat,
+                    beats_per_minute,
+                };
+            }
+        }
+        DEFAULT
+    }
+
+    /// Index of the first note of each bar of the first voice, found from `bar_lines`.
+    /// The first bar always starts at 0, even without a leading bar line.
+    /// Built on every call, callers that look bars up often keep it.
+    pub fn bar_starts(&self) -> Vec<usize> {
+        let mut bar_starts = vec![0];
+        for &line in &self.bar_lines {
+            // skip empty bars, like a repeated or trailing bar line
+            if line > *bar_starts.last().unwrap() && line < self.notes.len() {
+                bar_starts.push(line);
+            }
+        }
+        bar_starts
+    }
+}
+
+impl Default for ABC {
+    fn default() -> Self {
+        ABC::new(None, Headers::new(), Vec::new(), Vec::new(), Vec::new())
+    }
+}
+
+/// Index into `bar_starts`, from `ABC::bar_starts()`,
+/// of the bar that contains the note at `note_index`.
+pub fn bar_of_note(bar_starts: &[usize], note_index: usize) -> usize {
+    bar_starts
+        .partition_point(|start| *start <= note_index)
+        .saturating_sub(1)
+}
+
+/// Tempo of a tune, from its `Q:` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tempo {
+    /// Length of a beat, as a fraction of a whole note
+    pub beat: (u32, u32),
+    pub beats_per_minute: u32,
+}
+
+/// A fraction like `1/8`, or a whole number
+fn parse_fraction(value: &str) -> Option<(u32, u32)> {
+    let (num, den) = value.trim().split_once('/').unwrap_or((value.trim(), "1"));
+    match (num.trim().parse().ok()?, den.trim().parse().ok()?) {
+        (_, 0) | (0, _) => None,
+        fraction => Some(fraction),
+    }
+}
+
+/// The version of an ABC file
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+pub type Headers = HashMap<char, Vec<String>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_headers(headers: &[(char, &str)]) -> ABC {
+        let mut abc = ABC::default();
+        for (key, value) in headers {
+            abc.headers.entry(*key).or_default().push(value.to_string());
+        }
+        abc
+    }
+
+    #[test]
+    fn tempo_meter_and_unit_length() {
+        let abc = with_headers(&[('L', "1/16"), ('M', "C|"), ('Q', "\"Allegro\" 3/8=60")]);
+        assert_eq!(abc.unit_length(), (1, 16));
+        assert_eq!(abc.meter(), (2, 2));
+        assert_eq!(
+            abc.tempo(),
+            Tempo {
+                beat: (3, 8),
+                beats_per_minute: 60
+            }
+        );
+
+        let abc = with_headers(&[('M', "6/8"), ('Q', "90")]);
+        assert_eq!(abc.meter(), (6, 8));
+        assert_eq!(abc.tempo().beat, (1, 8));
+        assert_eq!(abc.tempo().beats_per_minute, 90);
+
+        let abc = ABC::default();
+        assert_eq!(abc.unit_length(), (1, 8));
+        assert_eq!(abc.meter(), (4, 4));
+        assert_eq!(abc.tempo().beats_per_minute, 120);
+    }
+
+    #[test]
+    fn bars() {
+        // repeated and trailing bar lines don't start empty bars
+        let abc = crate::parser::parse_abc("C D | E F || G |").unwrap();
+        let bar_starts = abc.bar_starts();
+        assert_eq!(bar_starts, [0, 2, 4]);
+        assert_eq!(bar_of_note(&bar_starts, 0), 0);
+        assert_eq!(bar_of_note(&bar_starts, 3), 1);
+        assert_eq!(bar_of_note(&bar_starts, 4), 2);
+        assert_eq!(bar_of_note(&bar_starts, 100), 2);
+
+        // bars follow changes to the notes, and an empty tune is one bar
+        let mut abc = abc;
+        abc.notes.truncate(3);
+        assert_eq!(abc.bar_starts(), [0, 2]);
+        assert_eq!(ABC::default().bar_starts(), [0]);
+    }
+}
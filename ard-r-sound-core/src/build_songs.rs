@@ -0,0 +1,136 @@
+//! Generating a module with every song in a directory, from a build script.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("songs.rs");
+//!     ard_r_sound_core::build_songs::generate("songs", &out).unwrap();
+//! }
+//!
+//! // main.rs
+//! include!(concat!(env!("OUT_DIR"), "/songs.rs"));
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::codegen::{rust_static, HeaderOptions, Optimized};
+use crate::parser;
+
+/// Write the module for the `.abc` files in `dir` to `out`,
+/// and tell cargo to run the build script again when any of them changes
+/// or a file is added to or removed from `dir`.
+pub fn generate(dir: impl AsRef<Path>, out: &Path) -> Result<(), anyhow::Error> {
+    let dir = dir.as_ref();
+    let files = abc_files(dir)?;
+
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for file in &files {
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+
+    std::fs::write(out, songs_module(&files)?)?;
+
+    Ok(())
+}
+
+/// The `.abc` files in `dir`, sorted by name
+pub fn abc_files(dir: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("unable to read {}: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "abc") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// A module with a `static` for the first voice of every tune in `files`, and a
+/// `SONGS` table of them in order. A file's first tune is named after the file,
+/// like `MARY` for `mary.abc`, and any further tune `n` is `MARY_TUNE_<n>`.
+pub fn songs_module(files: &[PathBuf]) -> Result<String, anyhow::Error> {
+    let mut statics = String::new();
+    let mut entries = String::new();
+    // name of each static, and the file it came from
+    let mut names: HashMap<String, &Path> = HashMap::new();
+
+    for file in files {
+        let file_str = std::fs::read_to_string(file)
+            .map_err(|e| anyhow::anyhow!("unable to read {}: {}", file.display(), e))?;
+        let file_name = HeaderOptions::named_after(file, false).name.to_uppercase();
+
+        for (index, tune) in parser::tunes(&file_str).into_iter().enumerate() {
+            let name = match index {
+                0 => file_name.clone(),
+                _ => format!("{}_TUNE_{}", file_name, index),
+            };
+            if let Some(other) = names.insert(name.clone(), file) {
+                anyhow::bail!(
+                    "{} and {} both make a song named {}",
+                    other.display(),
+                    file.display(),
+                    name
+                );
+            }
+
+            let abc = parser::parse_abc(tune)
+                .map_err(|e| anyhow::anyhow!("{}, tune {}: {}", file.display(), index, e))?;
+
+            statics.push_str(&format!("\n// {}", file.display()));
+            statics.push_str(&rust_static(&name, &abc));
+            entries.push_str(&format!(
+                "\n    ard_r_sound_base::SongEntry {{ name: \"{}\", song: &{}, len: {} }},",
+                name,
+                name,
+                Optimized::from(&abc).list.len()
+            ));
+        }
+    }
+
+    Ok(format!(
+        "// Songs generated by ard-r-sound, needs only the `ard-r-sound-base` crate.\n\
+         {}\n\
+         /// Every song, in order of file name and tune\n\
+         #[allow(dead_code)]\n\
+         pub static SONGS: [ard_r_sound_base::SongEntry; {}] = [{}\n];\n",
+        statics,
+        names.len(),
+        entries
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_static_per_tune() {
+        let dir = std::env::temp_dir().join(format!("ard-r-sound-songs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.abc"), "X:1\nK:C\nA B|\nX:2\nK:C\nc|\n").unwrap();
+        std::fs::write(dir.join("a-1.abc"), "K:C\nA z A|\n").unwrap();
+        std::fs::write(dir.join("_.abc"), "K:C\nC|\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a song").unwrap();
+
+        let files = abc_files(&dir).unwrap();
+        let module = songs_module(&files).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.len(), 3);
+        syn::parse_file(&module).expect("the module doesn't parse as Rust");
+        assert!(module.contains("pub static A_1: ard_r_sound_base::OptimizedStatic<2, 3>"));
+        assert!(module.contains("pub static B_TUNE_1: ard_r_sound_base::OptimizedStatic<1, 1>"));
+        assert!(module.contains(
+            "pub static SONGS: [ard_r_sound_base::SongEntry; 4] = [\n    \
+             ard_r_sound_base::SongEntry { name: \"SONG__\", song: &SONG__, len: 1 },\n    \
+             ard_r_sound_base::SongEntry { name: \"A_1\", song: &A_1, len: 3 },\n    \
+             ard_r_sound_base::SongEntry { name: \"B\", song: &B, len: 2 },\n    \
+             ard_r_sound_base::SongEntry { name: \"B_TUNE_1\", song: &B_TUNE_1, len: 1 },\n];"
+        ));
+    }
+}
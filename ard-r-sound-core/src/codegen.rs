@@ -0,0 +1,569 @@
+use std::io::Write;
+use std::{collections::HashMap, path::Path};
+
+use tracing::info;
+
+use crate::abc::{library, song_bytes, Length, Note, PitchOrRest, ABC};
+
+#[macro_export]
+macro_rules! HEADER_TEMPLATE {
+    () => {
+        r#"#ifndef ARDSOUND_SONG_{upper_name}_H
+#define ARDSOUND_SONG_{upper_name}_H
+
+// {title}, generated by ard-r-sound
+
+#ifndef ARDSOUND_TYPES
+#define ARDSOUND_TYPES
+
+#include <stdint.h>
+#include <string.h>
+
+#ifdef __AVR__
+#include <avr/pgmspace.h>
+#else
+#define PROGMEM
+#define pgm_read_byte(address) (*(const uint8_t *)(address))
+#define memcpy_P memcpy
+#endif
+
+// pitch of a rest
+#define ARDSOUND_REST 127
+
+struct ArdSoundNote {{
+    // half steps from the A at 440 Hz, ARDSOUND_REST for a rest
+    int8_t pitch;
+    // in Hz, 0 for a rest
+    uint16_t frequency;
+    // fraction of a whole note
+    uint16_t length_num;
+    uint16_t length_den;
+    // 0 (pppp) to 9 (ffff), 5 is mf
+    uint8_t dynamic;
+}};
+
+struct ArdSoundSong {{
+    // every unique note
+    const struct ArdSoundNote *notes;
+    // the song, as indexes into notes
+    const uint8_t *list;
+    // number of notes in the song
+    uint16_t length;
+    // whether notes and list are in program memory, see ardsound_note()
+    uint8_t progmem;
+    // beats per minute, each beat_num/beat_den of a whole note
+    uint16_t tempo;
+    uint8_t beat_num;
+    uint8_t beat_den;
+    // time signature
+    uint8_t meter_num;
+    uint8_t meter_den;
+}};
+
+// note number index of song, wherever it is stored
+static inline struct ArdSoundNote ardsound_note(const struct ArdSoundSong *song, uint16_t index) {{
+    struct ArdSoundNote note;
+    if (song->progmem) {{
+        uint8_t unique = pgm_read_byte(&song->list[index]);
+        memcpy_P(&note, &song->notes[unique], sizeof note);
+    }} else {{
+        note = song->notes[song->list[index]];
+    }}
+    return note;
+}}
+
+// how long note lasts at the song's tempo, in milliseconds
+static inline uint32_t ardsound_note_millis(const struct ArdSoundSong *song, const struct ArdSoundNote *note) {{
+    return (uint32_t)60000 * note->length_num * song->beat_den
+        / ((uint32_t)note->length_den * song->beat_num * song->tempo);
+}}
+
+#endif
+
+static const struct ArdSoundNote {name}_notes[] {progmem}= {{{notes}
+}};
+
+static const uint8_t {name}_list[] {progmem}= {{
+{list}
+}};
+
+static const struct ArdSoundSong {name} = {{
+    {name}_notes,
+    {name}_list,
+    {length},
+    {is_progmem},
+    {tempo},
+    {beat_num},
+    {beat_den},
+    {meter_num},
+    {meter_den},
+}};
+
+#endif
+"#
+    };
+}
+
+#[macro_export]
+macro_rules! LOOKUP_LINE {
+    () => {
+        "\n    {{ {:>4}, {:>5}, {:>2}, {:>3}, {} }},"
+    };
+}
+
+#[macro_export]
+macro_rules! SKETCH_TEMPLATE {
+    () => {
+        r#"// Plays {title}, generated by ard-r-sound.
+// Open this folder in the Arduino IDE and upload it,
+// with a passive buzzer between BUZZER_PIN and GND.
+
+#include "{header}"
+
+#define BUZZER_PIN 5
+// start the song over once it ends
+#define REPEAT true
+
+void play(const struct ArdSoundSong *song) {{
+    for (uint16_t i = 0; i < song->length; i++) {{
+        struct ArdSoundNote note = ardsound_note(song, i);
+        uint32_t millis = ardsound_note_millis(song, &note);
+        if (note.pitch != ARDSOUND_REST) {{
+            // stop a little early, so that repeated notes don't blur together
+            tone(BUZZER_PIN, note.frequency, millis * 9 / 10);
+        }}
+        delay(millis);
+    }}
+}}
+
+void setup() {{
+    pinMode(BUZZER_PIN, OUTPUT);
+}}
+
+void loop() {{
+    play(&{name});
+
+    if (!REPEAT) {{
+        while (true) {{}}
+    }}
+}}
+"#
+    };
+}
+
+#[macro_export]
+macro_rules! RUST_TEMPLATE {
+    () => {
+        r#"// {title}, generated by ard-r-sound.
+// Needs only the `ard-r-sound-base` crate, use it with `mod` or `include!`.
+{statics}
+/// Every voice of the song, to be played at the same time
+#[allow(dead_code)]
+pub static {name}_VOICES: [&(dyn ard_r_sound_base::Song + Sync); {voice_count}] = [{names}];
+"#
+    };
+}
+
+#[macro_export]
+macro_rules! RUST_STATIC_TEMPLATE {
+    () => {
+        r#"
+pub static {name}: ard_r_sound_base::OptimizedStatic<{uniques_len}, {list_len}> = {{
+    use ard_r_sound_base::{{Dynamic, Length, Note, OptimizedStatic, PitchClass, PitchOrRest}};
+
+    OptimizedStatic {{
+        uniques: [{uniques}
+        ],
+        list: [
+{list}
+        ],
+    }}
+}};
+"#
+    };
+}
+
+/// Options of the generated C code
+#[derive(Debug, Clone)]
+pub struct HeaderOptions {
+    /// Name of the song's variables, a C identifier
+    pub name: String,
+    /// Keep the song in program memory with `PROGMEM`, so it doesn't take up SRAM
+    pub progmem: bool,
+}
+
+impl HeaderOptions {
+    /// Options for a song named after `path`, the input or output file
+    pub fn named_after(path: &Path, progmem: bool) -> Self {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let mut name: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        // a lone `_` is a C identifier, but not a Rust one
+        if name == "_" || !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            name.insert_str(0, "song_");
+        }
+
+        Self { name, progmem }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Optimized<'a> {
+    pub uniques: Vec<&'a Note>,
+    pub list: Vec<usize>,
+}
+
+impl<'a> From<&'a ABC> for Optimized<'a> {
+    fn from(abc: &'a ABC) -> Self {
+        let mut optimized = Self::default();
+
+        // lookup table for list index
+        let mut lookup: HashMap<&'a Note, usize> = HashMap::new();
+
+        for note in abc.notes.iter() {
+            if !optimized.uniques.contains(&note) {
+                optimized.uniques.push(note);
+
+                lookup.insert(note, optimized.uniques.len() - 1);
+            }
+
+            optimized.list.push(*lookup.get(note).unwrap());
+        }
+
+        optimized
+    }
+}
+
+/// Encode the first voice of `abc` in the `song_bytes` format,
+/// for songs that are uploaded to the Arduino at runtime.
+pub fn to_song_bytes(abc: &ABC) -> Result<Vec<u8>, anyhow::Error> {
+    let optimized = Optimized::from(abc);
+
+    let mut bytes = Vec::with_capacity(song_bytes::encoded_len(
+        optimized.uniques.len(),
+        optimized.list.len(),
+    ));
+
+    let header = song_bytes::encode_header(optimized.uniques.len(), optimized.list.len())
+        .map_err(|e| anyhow::anyhow!("unable to encode song: {}", e))?;
+    bytes.extend_from_slice(&header);
+
+    for unique in &optimized.uniques {
+        let note = song_bytes::encode_note(unique)
+            .map_err(|e| anyhow::anyhow!("unable to encode {:?}: {}", unique, e))?;
+        bytes.extend_from_slice(&note);
+    }
+
+    // fits, encode_header() checked the number of uniques
+    bytes.extend(optimized.list.iter().map(|index| *index as u8));
+
+    Ok(bytes)
+}
+
+/// Build an EEPROM image holding the first voice of each of `abcs`,
+/// see `ard_r_sound_base::library`.
+pub fn to_library(abcs: &[ABC], default_song: usize) -> Result<Vec<u8>, anyhow::Error> {
+    let songs = abcs
+        .iter()
+        .map(to_song_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+    let songs: Vec<&[u8]> = songs.iter().map(Vec::as_slice).collect();
+
+    let default_song = default_song
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("no song at index {}", default_song))?;
+
+    let mut image = vec![0; library::encoded_len(&songs)];
+    library::encode(&songs, default_song, &mut image)
+        .map_err(|e| anyhow::anyhow!("unable to build library: {}", e))?;
+
+    if image.len() > library::CAPACITY {
+        anyhow::bail!(
+            "library is {} bytes, but the EEPROM only holds {}",
+            image.len(),
+            library::CAPACITY
+        );
+    }
+
+    Ok(image)
+}
+
+/// A self-contained C header holding the first voice of `abc`,
+/// with the types and functions to play it, see `HEADER_TEMPLATE`.
+pub fn c_header(abc: &ABC, options: &HeaderOptions) -> Result<String, anyhow::Error> {
+    let optimized = Optimized::from(abc);
+
+    info!("{:#?}", optimized);
+
+    if optimized.list.is_empty() {
+        anyhow::bail!("the song has no notes");
+    }
+    if optimized.uniques.len() > u8::MAX as usize + 1 {
+        anyhow::bail!(
+            "{} unique notes, but at most 256 can be indexed by a byte",
+            optimized.uniques.len()
+        );
+    }
+    // `ArdSoundSong::length` and the index of `ardsound_note()` are `uint16_t`
+    if optimized.list.len() > u16::MAX as usize {
+        anyhow::bail!(
+            "{} notes, but at most {} fit in a header",
+            optimized.list.len(),
+            u16::MAX
+        );
+    }
+
+    let unit = abc.unit_length();
+    let mut notes = String::new();
+    for unique in &optimized.uniques {
+        let (pitch, frequency) = pitch_and_frequency(&unique.pitch)
+            .ok_or_else(|| anyhow::anyhow!("{:?} is too high or low", unique.pitch))?;
+        let (length_num, length_den) = whole_note_fraction(&unique.length, unit)
+            .ok_or_else(|| anyhow::anyhow!("{:?} is too long or short", unique.length))?;
+        notes.push_str(&format!(
+            LOOKUP_LINE!(),
+            pitch, frequency, length_num, length_den, unique.dynamic as u8
+        ));
+    }
+
+    let list = optimized
+        .list
+        .chunks(16)
+        .map(|line| {
+            let line: Vec<String> = line.iter().map(usize::to_string).collect();
+            format!("    {},", line.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let tempo = abc.tempo();
+    let (meter_num, meter_den) = abc.meter();
+
+    Ok(format!(
+        HEADER_TEMPLATE!(),
+        upper_name = options.name.to_uppercase(),
+        title = abc.header('T').unwrap_or(&options.name),
+        name = options.name,
+        progmem = if options.progmem { "PROGMEM " } else { "" },
+        notes = notes,
+        list = list,
+        length = optimized.list.len(),
+        is_progmem = options.progmem as u8,
+        tempo = tempo.beats_per_minute,
+        beat_num = tempo.beat.0,
+        beat_den = tempo.beat.1,
+        meter_num = meter_num,
+        meter_den = meter_den,
+    ))
+}
+
+pub fn generate_c_header(
+    abc: &ABC,
+    file: &Path,
+    options: &HeaderOptions,
+) -> Result<(), anyhow::Error> {
+    let mut output_file = std::fs::File::create(file)?;
+    output_file.write_all(c_header(abc, options)?.as_bytes())?;
+
+    Ok(())
+}
+
+/// Write an Arduino IDE sketch playing the first voice of `abc` to the folder `dir`,
+/// made of `<dir>.ino` and the song's header.
+pub fn generate_sketch(
+    abc: &ABC,
+    dir: &Path,
+    options: &HeaderOptions,
+) -> Result<(), anyhow::Error> {
+    // the Arduino IDE only opens sketches named after their folder
+    let sketch_name = dir
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{} isn't a folder name", dir.display()))?;
+    std::fs::create_dir_all(dir)?;
+
+    let header = format!("{}.h", options.name);
+    generate_c_header(abc, &dir.join(&header), options)?;
+
+    let sketch = format!(
+        SKETCH_TEMPLATE!(),
+        title = abc.header('T').unwrap_or(&options.name),
+        header = header,
+        name = options.name,
+    );
+    std::fs::write(dir.join(sketch_name).with_extension("ino"), sketch)?;
+
+    Ok(())
+}
+
+/// A Rust module with the same statics as `static_from_file!`,
+/// `name` for the first voice, `name_VOICE_<n>` for the others and `name_VOICES`.
+/// It only depends on `ard-r-sound-base`.
+pub fn rust_module(abc: &ABC, name: &str) -> String {
+    let mut names = Vec::new();
+    let mut statics = String::new();
+    for index in 0..abc.voice_count() {
+        let voice_name = match index {
+            0 => name.to_string(),
+            _ => format!("{}_VOICE_{}", name, index),
+        };
+        statics.push_str(&rust_static(&voice_name, &abc.voice(index).unwrap()));
+        names.push(format!("&{}", voice_name));
+    }
+
+    format!(
+        RUST_TEMPLATE!(),
+        title = abc.header('T').unwrap_or(name),
+        statics = statics,
+        name = name,
+        voice_count = names.len(),
+        names = names.join(", "),
+    )
+}
+
+/// `pub static name: OptimizedStatic<..>` holding the first voice of `abc`
+pub fn rust_static(name: &str, abc: &ABC) -> String {
+    let optimized = Optimized::from(abc);
+
+    let uniques: String = optimized
+        .uniques
+        .iter()
+        .map(|note| format!("\n            {},", rust_note(note)))
+        .collect();
+    let list = optimized
+        .list
+        .chunks(16)
+        .map(|line| {
+            let line: Vec<String> = line.iter().map(usize::to_string).collect();
+            format!("            {},", line.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        RUST_STATIC_TEMPLATE!(),
+        name = name,
+        uniques_len = optimized.uniques.len(),
+        list_len = optimized.list.len(),
+        uniques = uniques,
+        list = list,
+    )
+}
+
+pub fn generate_rust_module(abc: &ABC, file: &Path, name: &str) -> Result<(), anyhow::Error> {
+    std::fs::write(file, rust_module(abc, name))?;
+
+    Ok(())
+}
+
+/// `note` as a Rust expression, with the types of the note in scope
+fn rust_note(note: &Note) -> String {
+    let pitch = match &note.pitch {
+        PitchOrRest::Pitch { class, octave } => format!(
+            "PitchOrRest::Pitch {{ class: PitchClass::{:?}, octave: {} }}",
+            class, octave
+        ),
+        PitchOrRest::Rest => "PitchOrRest::Rest".to_string(),
+    };
+
+    format!(
+        "Note {{ pitch: {}, length: Length::{:?}, dynamic: Dynamic::{:?} }}",
+        pitch, note.length, note.dynamic
+    )
+}
+
+/// Half steps from the A at 440 Hz, and the frequency rounded to Hz.
+/// `None` if it doesn't fit.
+fn pitch_and_frequency(pitch: &PitchOrRest) -> Option<(i8, u16)> {
+    const MIDDLE_A_FREQUENCY: f64 = 440.0;
+
+    match pitch {
+        PitchOrRest::Pitch { class, octave } => {
+            let half_steps = *octave as i32 * 12 + class.half_steps_from_a() as i32;
+            let frequency = MIDDLE_A_FREQUENCY * 2f64.powf(half_steps as f64 / 12.);
+            let half_steps: i8 = half_steps.try_into().ok()?;
+            match half_steps == C_REST || frequency >= u16::MAX as f64 {
+                true => None,
+                false => Some((half_steps, frequency.round() as u16)),
+            }
+        }
+        PitchOrRest::Rest => Some((C_REST, 0)),
+    }
+}
+
+/// Pitch of a rest in the C header
+const C_REST: i8 = 127;
+
+/// `length` of notes with length `unit` as a reduced fraction of a whole note.
+/// `None` if it doesn't fit.
+fn whole_note_fraction(length: &Length, unit: (u32, u32)) -> Option<(u16, u16)> {
+    let (num, den) = match length {
+        Length::Unit => unit,
+        Length::Multiple(m) => (unit.0.checked_mul(*m)?, unit.1),
+        Length::Division(d) => (unit.0, unit.1.checked_mul(*d)?),
+    };
+
+    let gcd = gcd(num, den);
+    Some(((num / gcd).try_into().ok()?, (den / gcd).try_into().ok()?))
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_abc;
+
+    #[test]
+    fn header_has_rational_lengths_and_tempo() {
+        let abc = parse_abc("T:Test\nL:1/8\nQ:3/8=60\nM:6/8\nK:C\nA3 z/2 c,2|\n").unwrap();
+        let header = c_header(
+            &abc,
+            &HeaderOptions::named_after(Path::new("my song.abc"), true),
+        )
+        .unwrap();
+
+        assert!(header.contains("// Test, generated by ard-r-sound"));
+        assert!(header.contains("static const struct ArdSoundNote my_song_notes[] PROGMEM = {"));
+        // A for 3/8 of a whole note, a rest for 1/16 and C for 1/4
+        assert!(header.contains("{    0,   440,  3,   8, 5 },"));
+        assert!(header.contains("{  127,     0,  1,  16, 5 },"));
+        assert!(header.contains("{   -9,   262,  1,   4, 5 },"));
+        assert!(header.contains("    0, 1, 2,\n"));
+        assert!(header.contains("    1,\n    60,\n    3,\n    8,\n    6,\n    8,\n"));
+        assert!(!header.contains("note.h"));
+    }
+
+    #[test]
+    fn names_are_c_identifiers() {
+        let name = |path| HeaderOptions::named_after(Path::new(path), false).name;
+        assert_eq!(name("songs/Mary-Lamb.abc"), "mary_lamb");
+        assert_eq!(name("1.abc"), "song_1");
+        assert_eq!(name("_.abc"), "song__");
+    }
+
+    #[test]
+    fn rust_module_has_every_voice() {
+        let abc = parse_abc("T:Test\nL:1/4\nK:C\nV:1\nA2 z|\nV:2\nc,/2|\n").unwrap();
+        let module = rust_module(&abc, "SONG");
+
+        assert!(module.contains("pub static SONG: ard_r_sound_base::OptimizedStatic<2, 2> = {"));
+        assert!(module.contains(
+            "Note { pitch: PitchOrRest::Pitch { class: PitchClass::A, octave: 0 }, \
+             length: Length::Multiple(2), dynamic: Dynamic::MezzoForte },"
+        ));
+        assert!(module.contains("Note { pitch: PitchOrRest::Rest, length: Length::Unit,"));
+        assert!(module.contains("length: Length::Division(2),"));
+        assert!(module.contains("pub static SONG_VOICE_1: ard_r_sound_base::OptimizedStatic<1, 1>"));
+        assert!(module.contains("[&SONG, &SONG_VOICE_1];"));
+    }
+}
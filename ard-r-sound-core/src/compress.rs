@@ -0,0 +1,155 @@
+//! Phrase compression of songs, see `ard_r_sound_base::phrases`.
+
+use std::collections::HashMap;
+
+use crate::abc::phrases::{MAX_HEIGHT, MAX_SYMBOLS};
+use crate::codegen::Optimized;
+
+/// The note indexes of a song, compressed with rules standing for
+/// repeated pairs of notes and rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phrases {
+    /// Number of unique notes, the first rule's symbol
+    pub uniques: usize,
+    /// Pairs of symbols, each rule only refers to earlier ones
+    pub rules: Vec<[u8; 2]>,
+    /// The song, as symbols
+    pub list: Vec<u8>,
+}
+
+impl Phrases {
+    /// Compress `list`, indexes of `uniques` unique notes, with as many rules as
+    /// make it smaller. Rules are only added while they can be given a symbol.
+    pub fn compress(uniques: usize, list: &[usize]) -> Result<Self, anyhow::Error> {
+        if uniques > MAX_SYMBOLS {
+            anyhow::bail!(
+                "{} unique notes, but at most {} fit in a byte",
+                uniques,
+                MAX_SYMBOLS
+            );
+        }
+
+        let mut phrases = Phrases {
+            uniques,
+            rules: Vec::new(),
+            list: list.iter().map(|&index| index as u8).collect(),
+        };
+        // height of each symbol, unique notes are 0
+        let mut heights = vec![0; uniques];
+
+        while uniques + phrases.rules.len() < MAX_SYMBOLS {
+            let pair = match phrases.most_repeated_pair(&heights) {
+                Some(pair) => pair,
+                None => break,
+            };
+
+            let symbol = (uniques + phrases.rules.len()) as u8;
+            phrases.rules.push(pair);
+            heights.push(1 + heights[pair[0] as usize].max(heights[pair[1] as usize]));
+            phrases.replace(pair, symbol);
+        }
+
+        // a pair that repeats only twice saves as many bytes as its rule takes,
+        // it only pays off as part of a longer phrase that repeats
+        if phrases.encoded_len() >= list.len() {
+            phrases.rules.clear();
+            phrases.list = list.iter().map(|&index| index as u8).collect();
+        }
+
+        Ok(phrases)
+    }
+
+    /// The pair of symbols that occurs most often without overlapping,
+    /// the first one to occur of those. `None` if no pair repeats.
+    fn most_repeated_pair(&self, heights: &[usize]) -> Option<[u8; 2]> {
+        // count and first position of each pair
+        let mut counts: HashMap<[u8; 2], (usize, usize)> = HashMap::new();
+        // a counted pair and where an overlapping copy of it would start
+        let mut previous = None;
+        for (position, pair) in self.list.windows(2).enumerate() {
+            let pair = [pair[0], pair[1]];
+            // in a run like `a a a` only the first `a a` counts
+            if previous == Some((pair, position)) {
+                continue;
+            }
+            counts.entry(pair).or_insert((0, position)).0 += 1;
+            previous = Some((pair, position + 1));
+        }
+
+        counts
+            .into_iter()
+            .filter(|(pair, (count, _))| {
+                *count >= 2 && pair.iter().all(|&half| heights[half as usize] < MAX_HEIGHT)
+            })
+            .max_by_key(|(_, (count, position))| (*count, std::cmp::Reverse(*position)))
+            .map(|(pair, _)| pair)
+    }
+
+    /// Replace every occurrence of `pair` with `symbol`, from left to right.
+    fn replace(&mut self, pair: [u8; 2], symbol: u8) {
+        let mut list = Vec::with_capacity(self.list.len());
+        let mut i = 0;
+        while i < self.list.len() {
+            if self.list[i..].starts_with(&pair) {
+                list.push(symbol);
+                i += 2;
+            } else {
+                list.push(self.list[i]);
+                i += 1;
+            }
+        }
+        self.list = list;
+    }
+
+    /// Bytes taken by the rules and the list
+    pub fn encoded_len(&self) -> usize {
+        2 * self.rules.len() + self.list.len()
+    }
+
+    /// The rules as bytes, for `ard_r_sound_base::phrases::Expand`
+    pub fn rule_bytes(&self) -> Vec<u8> {
+        self.rules.iter().flatten().copied().collect()
+    }
+}
+
+impl TryFrom<&Optimized<'_>> for Phrases {
+    type Error = anyhow::Error;
+
+    fn try_from(optimized: &Optimized<'_>) -> Result<Self, Self::Error> {
+        Phrases::compress(optimized.uniques.len(), &optimized.list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abc::phrases::Expand;
+
+    fn round_trip(uniques: usize, list: &[usize]) -> Phrases {
+        let phrases = Phrases::compress(uniques, list).unwrap();
+        let rules = phrases.rule_bytes();
+        let expanded: Vec<usize> = Expand::new(&phrases.list[..], &rules[..], uniques)
+            .map(usize::from)
+            .collect();
+        assert_eq!(expanded, list);
+        phrases
+    }
+
+    #[test]
+    fn repeated_phrases_compress() {
+        let bar = [0, 1, 2, 3, 4, 4, 4, 5];
+        let song: Vec<usize> = bar.iter().cycle().take(bar.len() * 6).copied().collect();
+
+        let phrases = round_trip(6, &song);
+        assert!(phrases.encoded_len() < song.len() / 2);
+    }
+
+    #[test]
+    fn no_repeats_stay_uncompressed() {
+        let phrases = round_trip(5, &[0, 1, 2, 3, 4, 0, 2, 4, 1, 3]);
+        assert!(phrases.rules.is_empty());
+
+        round_trip(1, &[0; 100]);
+        round_trip(0, &[]);
+    }
+}
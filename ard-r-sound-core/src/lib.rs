@@ -0,0 +1,13 @@
+// ABC parsing and code generation, without audio or serial dependencies,
+// shared by the desktop crate and the proc macros
+
+// export modules
+pub mod abc;
+pub mod parser;
+pub mod parse_tree;
+pub mod codegen;
+pub mod compress;
+pub mod build_songs;
+
+// re-export everything in base
+pub use ard_r_sound_base::*;
@@ -2,6 +2,8 @@
 pub(crate) struct NoteParse {
     pub(crate) pitch: Pitch,
     pub(crate) length: Length,
+    /// the dynamic in effect when the note was parsed
+    pub(crate) dynamic: crate::abc::Dynamic,
 }
 
 impl TryInto<crate::abc::Note> for NoteParse {
@@ -11,6 +13,7 @@ impl TryInto<crate::abc::Note> for NoteParse {
         Ok(crate::abc::Note {
             pitch: self.pitch.try_into()?,
             length: self.length.try_into()?,
+            dynamic: self.dynamic,
         })
     }
 }
@@ -1,9 +1,10 @@
+use std::ops::Range;
 use std::path::Path;
 
 use anyhow::anyhow;
-use tracing::info;
 use pest::{iterators::Pair, Parser};
 use pest_derive::Parser;
+use tracing::info;
 
 use crate::{abc, parse_tree};
 
@@ -17,6 +18,42 @@ pub fn parse_abc_file(file_path: &Path) -> Result<abc::ABC, anyhow::Error> {
     parse_abc(&raw_file)
 }
 
+/// The tunes of a file with several of them, each starting with its `X:` field.
+/// Anything before the first `X:` field is left out,
+/// a file without any is a single tune.
+pub fn tunes(file_str: &str) -> Vec<&str> {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for line in file_str.split_inclusive('\n') {
+        if line.starts_with("X:") {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+
+    if starts.is_empty() {
+        return vec![file_str];
+    }
+
+    let ends = starts.iter().skip(1).copied().chain([file_str.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(&start, end)| &file_str[start..end])
+        .collect()
+}
+
+/// Where in its input a `parse_abc()` error is, and what went wrong there.
+/// `None` for errors that aren't syntax errors.
+pub fn syntax_error(error: &anyhow::Error) -> Option<(Range<usize>, String)> {
+    let error = error.downcast_ref::<pest::error::Error<Rule>>()?;
+    let range = match error.location {
+        pest::error::InputLocation::Pos(pos) => pos..pos + 1,
+        pest::error::InputLocation::Span((start, end)) => start..end,
+    };
+    Some((range, error.variant.message().into_owned()))
+}
+
 pub fn parse_abc(file_str: &str) -> Result<abc::ABC, anyhow::Error> {
     let entire = ABCParser::parse(Rule::Entire, file_str)?
         .next()
@@ -24,7 +61,11 @@ pub fn parse_abc(file_str: &str) -> Result<abc::ABC, anyhow::Error> {
 
     let mut version: Option<abc::Version> = None;
     let mut headers = abc::Headers::new();
-    let mut notes: Vec<abc::Note> = Vec::new();
+    let mut voices: Vec<abc::Voice> = Vec::new();
+    // dynamics apply to every later note of the same voice, until the next one
+    let mut dynamics: Vec<abc::Dynamic> = Vec::new();
+    // index into `voices` of the voice that notes are added to
+    let mut current: Option<usize> = None;
 
     for in_entire in entire.into_inner() {
         match in_entire.as_rule() {
@@ -59,7 +100,7 @@ pub fn parse_abc(file_str: &str) -> Result<abc::ABC, anyhow::Error> {
                             info!("parsed info line as: key: {:?}, val: {:?}", key, val);
 
                             //make sure we have an entry in our hashtable for the char
-                            headers.entry(key).or_insert_with(Vec::new);
+                            headers.entry(key).or_default();
                             headers.get_mut(&key).unwrap().push(val.to_string());
                         }
                         _ => info!("invalid information field: {:?}", inner.as_str()),
@@ -71,11 +112,55 @@ pub fn parse_abc(file_str: &str) -> Result<abc::ABC, anyhow::Error> {
                 let body = in_entire.into_inner();
                 info!("body:");
                 for rules in body {
-                    let parse: parse_tree::NoteParse = parse_note(rules)?;
+                    if matches!(rules.as_rule(), Rule::Voice) {
+                        let id = rules
+                            .into_inner()
+                            .find(|r| matches!(r.as_rule(), Rule::VoiceId))
+                            .unwrap()
+                            .as_str();
+                        info!("switching to voice {:?}", id);
+                        current = Some(
+                            match voices.iter().position(|v| v.id.as_deref() == Some(id)) {
+                                Some(index) => index,
+                                None => {
+                                    voices.push(abc::Voice {
+                                        id: Some(id.to_string()),
+                                        ..Default::default()
+                                    });
+                                    dynamics.push(abc::Dynamic::default());
+                                    voices.len() - 1
+                                }
+                            },
+                        );
+                        continue;
+                    }
+
+                    // anything before the first voice field goes into an unnamed voice
+                    let index = *current.get_or_insert_with(|| {
+                        voices.push(abc::Voice::default());
+                        dynamics.push(abc::Dynamic::default());
+                        voices.len() - 1
+                    });
+                    let voice = &mut voices[index];
+
+                    if matches!(rules.as_rule(), Rule::Bar) {
+                        // remember where the bar line is, relative to the notes
+                        voice.bar_lines.push(voice.notes.len());
+                        continue;
+                    }
+                    if matches!(rules.as_rule(), Rule::Decoration) {
+                        let name = rules.into_inner().next().unwrap().as_str();
+                        match abc::Dynamic::try_from(name) {
+                            Ok(d) => dynamics[index] = d,
+                            Err(_) => info!("ignoring unsupported decoration: {:?}", name),
+                        }
+                        continue;
+                    }
+                    let parse: parse_tree::NoteParse = parse_note(rules, dynamics[index])?;
                     info!("parsed note is: {:?}", parse);
                     let note: abc::Note = parse.try_into()?;
                     info!("real note is: {:?}", note);
-                    notes.push(note);
+                    voice.notes.push(note);
                 }
                 info!("done with body\n");
             }
@@ -83,11 +168,16 @@ pub fn parse_abc(file_str: &str) -> Result<abc::ABC, anyhow::Error> {
         }
     }
 
-    Ok(abc::ABC {
+    let mut voices = voices.into_iter();
+    let first = voices.next().unwrap_or_default();
+
+    Ok(abc::ABC::new(
         version,
         headers,
-        notes,
-    })
+        first.notes,
+        first.bar_lines,
+        voices.collect(),
+    ))
 }
 
 fn parse_version(version: &str) -> Result<abc::Version, anyhow::Error> {
@@ -120,7 +210,10 @@ fn parse_information<'a>(key: &'a str, val: &'a str) -> Result<(char, &'a str),
     Ok((key, val))
 }
 
-fn parse_note(note: Pair<Rule>) -> Result<parse_tree::NoteParse, anyhow::Error> {
+fn parse_note(
+    note: Pair<Rule>,
+    dynamic: abc::Dynamic,
+) -> Result<parse_tree::NoteParse, anyhow::Error> {
     info!("start note parse");
 
     let note_components: Vec<_> = note.into_inner().collect();
@@ -139,7 +232,11 @@ fn parse_note(note: Pair<Rule>) -> Result<parse_tree::NoteParse, anyhow::Error>
         .next()
         .unwrap()?;
 
-    let note = parse_tree::NoteParse { pitch, length };
+    let note = parse_tree::NoteParse {
+        pitch,
+        length,
+        dynamic,
+    };
 
     info!("end note parse");
 
@@ -226,3 +323,30 @@ fn parse_note_length(note_length: &str) -> Result<parse_tree::Length, anyhow::Er
         },
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn several_tunes() {
+        let file = "%abc-2.1\nX:1\nT:First\nK:C\nCDE|\n\nX:2\nT:Second\nK:C\nG,A,|\n";
+
+        let split = tunes(file);
+        assert_eq!(split.len(), 2);
+        assert_eq!(parse_abc(split[0]).unwrap().notes.len(), 3);
+        assert_eq!(parse_abc(split[1]).unwrap().notes.len(), 2);
+
+        assert_eq!(tunes("K:C\nCDE|\n"), vec!["K:C\nCDE|\n"]);
+    }
+
+    #[test]
+    fn syntax_error_location() {
+        let error = parse_abc("K:C\nCD#E|\n").unwrap_err();
+        let (range, _) = syntax_error(&error).unwrap();
+        assert_eq!(range.start, 6);
+
+        // a jingle doesn't need any header
+        assert_eq!(parse_abc("C2 z G").unwrap().notes.len(), 3);
+    }
+}
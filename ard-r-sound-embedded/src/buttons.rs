@@ -0,0 +1,230 @@
+use ard_r_sound_base::debounce::Debouncer;
+use arduino_hal::pac::{PORTB, PORTC, PORTD};
+use avr_device::interrupt::Mutex;
+use core::cell::{Cell, RefCell};
+
+use crate::peripherals::Peripherals;
+use crate::scheduler;
+
+/// What a button does.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PlayPause,
+    NextSong,
+    PreviousSong,
+    TempoUp,
+    TempoDown,
+}
+
+/// Number of `Action`s
+const ACTIONS: usize = 5;
+
+/// A pin a button is wired to, as its port and bit, e.g. `ButtonPin::C(0)` for A0.
+/// Buttons connect the pin to GND, the internal pull-up keeps it high otherwise.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ButtonPin {
+    /// D8 to D13
+    B(u8),
+    /// A0 to A5
+    C(u8),
+    /// D0 to D7
+    D(u8),
+}
+
+impl ButtonPin {
+    const fn mask(self) -> u8 {
+        match self {
+            ButtonPin::B(bit) | ButtonPin::C(bit) | ButtonPin::D(bit) => 1 << bit,
+        }
+    }
+
+    /// Is this a pin of the Uno that `Peripherals` doesn't use?
+    const fn is_free(self) -> bool {
+        match self {
+            // D9 to D11 are driven by the clocks, D13 is the LED
+            ButtonPin::B(bit) => matches!(bit, 0 | 4),
+            ButtonPin::C(bit) => bit <= 5,
+            // D0 and D1 are the serial port, D5 is the buzzer
+            ButtonPin::D(bit) => matches!(bit, 2..=4 | 6 | 7),
+        }
+    }
+
+    fn is_pressed(self) -> bool {
+        // only the bits of button pins are touched, which `Peripherals` doesn't own
+        let pins = unsafe {
+            match self {
+                ButtonPin::B(_) => (*PORTB::ptr()).pinb.read().bits(),
+                ButtonPin::C(_) => (*PORTC::ptr()).pinc.read().bits(),
+                ButtonPin::D(_) => (*PORTD::ptr()).pind.read().bits(),
+            }
+        };
+        pins & self.mask() == 0
+    }
+
+    /// Make the pin an input with pull-up, and enable its pin change interrupt.
+    fn setup(self, periphs: &mut Peripherals) {
+        let mask = self.mask();
+        let set = |bits: u8| bits | mask;
+        let clear = |bits: u8| bits & !mask;
+
+        unsafe {
+            match self {
+                ButtonPin::B(_) => {
+                    let port = &*PORTB::ptr();
+                    port.ddrb.modify(|r, w| w.bits(clear(r.bits())));
+                    port.portb.modify(|r, w| w.bits(set(r.bits())));
+                    periphs.exint.pcmsk0.modify(|r, w| w.bits(set(r.bits())));
+                    periphs.exint.pcicr.modify(|r, w| w.bits(r.bits() | 0b001));
+                }
+                ButtonPin::C(_) => {
+                    let port = &*PORTC::ptr();
+                    port.ddrc.modify(|r, w| w.bits(clear(r.bits())));
+                    port.portc.modify(|r, w| w.bits(set(r.bits())));
+                    periphs.exint.pcmsk1.modify(|r, w| w.bits(set(r.bits())));
+                    periphs.exint.pcicr.modify(|r, w| w.bits(r.bits() | 0b010));
+                }
+                ButtonPin::D(_) => {
+                    let port = &*PORTD::ptr();
+                    port.ddrd.modify(|r, w| w.bits(clear(r.bits())));
+                    port.portd.modify(|r, w| w.bits(set(r.bits())));
+                    periphs.exint.pcmsk2.modify(|r, w| w.bits(set(r.bits())));
+                    periphs.exint.pcicr.modify(|r, w| w.bits(r.bits() | 0b100));
+                }
+            }
+        }
+    }
+}
+
+/// The pin of each button, `None` for buttons that aren't wired up.
+pub struct Config {
+    pub play_pause: Option<ButtonPin>,
+    pub next_song: Option<ButtonPin>,
+    pub previous_song: Option<ButtonPin>,
+    pub tempo_up: Option<ButtonPin>,
+    pub tempo_down: Option<ButtonPin>,
+}
+
+impl Config {
+    const fn buttons(&self) -> [(Action, Option<ButtonPin>); ACTIONS] {
+        [
+            (Action::PlayPause, self.play_pause),
+            (Action::NextSong, self.next_song),
+            (Action::PreviousSong, self.previous_song),
+            (Action::TempoUp, self.tempo_up),
+            (Action::TempoDown, self.tempo_down),
+        ]
+    }
+
+    /// Is every button on a free pin of its own?
+    /// Meant for a `const` assertion next to the configuration.
+    pub const fn is_valid(&self) -> bool {
+        let buttons = self.buttons();
+        let mut i = 0;
+        while i < ACTIONS {
+            if let Some(pin) = buttons[i].1 {
+                if !pin.is_free() {
+                    return false;
+                }
+
+                let mut j = i + 1;
+                while j < ACTIONS {
+                    if let Some(other) = buttons[j].1 {
+                        if same_pin(pin, other) {
+                            return false;
+                        }
+                    }
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+        true
+    }
+}
+
+/// `PartialEq` isn't usable in a `const fn`
+const fn same_pin(a: ButtonPin, b: ButtonPin) -> bool {
+    match (a, b) {
+        (ButtonPin::B(a), ButtonPin::B(b))
+        | (ButtonPin::C(a), ButtonPin::C(b))
+        | (ButtonPin::D(a), ButtonPin::D(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Set up by `setup()`
+static CONFIG: Mutex<Cell<Option<&'static Config>>> = Mutex::new(Cell::new(None));
+
+/// Global variable for the buttons, sampled by the pin change interrupts.
+static BUTTONS: Mutex<RefCell<[Debouncer; ACTIONS]>> =
+    Mutex::new(RefCell::new([Debouncer::new(); ACTIONS]));
+
+/// Start watching the buttons in `config`.
+/// Debouncing uses `scheduler::millis()`, so playback must have been started.
+pub fn setup(periphs: &mut Peripherals, config: &'static Config) {
+    for pin in config.buttons().into_iter().filter_map(|(_, pin)| pin) {
+        pin.setup(periphs);
+    }
+
+    avr_device::interrupt::free(|cs| CONFIG.borrow(cs).set(Some(config)));
+}
+
+/// The action of a button that was just pressed, if any.
+/// Should be called regularly, see `Debouncer::poll()`.
+pub fn poll() -> Option<Action> {
+    let now = scheduler::millis();
+    let config = avr_device::interrupt::free(|cs| CONFIG.borrow(cs).get())?;
+
+    avr_device::interrupt::free(|cs| {
+        let mut buttons = BUTTONS.borrow(cs).borrow_mut();
+        buttons
+            .iter_mut()
+            .zip(config.buttons())
+            .find_map(|(button, (action, _))| button.poll(now).then_some(action))
+    })
+}
+
+/// Is any button still bouncing, or waiting for `poll()`?
+pub fn is_bouncing() -> bool {
+    avr_device::interrupt::free(|cs| {
+        BUTTONS
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .any(|button| !button.is_settled())
+    })
+}
+
+/// Sample every button, called from every pin change interrupt.
+fn sample() {
+    avr_device::interrupt::free(|cs| {
+        let config = match CONFIG.borrow(cs).get() {
+            Some(config) => config,
+            None => return,
+        };
+        let now = scheduler::millis();
+
+        let mut buttons = BUTTONS.borrow(cs).borrow_mut();
+        for (button, (_, pin)) in buttons.iter_mut().zip(config.buttons()) {
+            if let Some(pin) = pin {
+                button.sample(pin.is_pressed(), now);
+            }
+        }
+    });
+}
+
+/// Interrupt service routines for pin changes on port B, C and D.
+#[avr_device::interrupt(atmega328p)]
+fn PCINT0() {
+    sample();
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn PCINT1() {
+    sample();
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn PCINT2() {
+    sample();
+}
@@ -2,69 +2,102 @@
 #![no_main]
 // enable interrupts
 #![feature(abi_avr_interrupt)]
+// enable inline assembly, see `power::sleep()`
+#![feature(asm_experimental_arch)]
 
-use ard_r_sound_base::Note;
+use buttons::ButtonPin;
 
+/// module containing the debounced control buttons.
+mod buttons;
 /// module containing logic to set up peripherals.
 mod peripherals;
+/// module containing the sleep modes.
+mod power;
 /// module containing wrappers to serialize data.
 mod print_wrappers;
-/// module containing timer duration logic.
-mod timer_duration;
-
-ard_r_sound_macros::static_from_file! {OPTIMIZED, ../misc/example_abcs/mary.abc}
+/// module containing the serial command handler.
+mod remote;
+/// module containing the interrupt driven note scheduler.
+mod scheduler;
+/// module containing songs uploaded over serial.
+mod storage;
+
+ard_r_sound_macros::include_song!(
+    SONG,
+    "../misc/example_abcs/mary.abc",
+    encoding = phrases,
+    storage = progmem
+);
+
+/// How the buzzer signal is generated, see `peripherals::ToneMode`
+const TONE_MODE: peripherals::ToneMode = peripherals::ToneMode::Software;
+
+/// Play the default song of the EEPROM library at boot (see `ard-r-sound library`),
+/// falls back to `SONG` if the EEPROM doesn't hold a library.
+const BOOT_FROM_LIBRARY: bool = true;
+
+/// Play the boot song once and then power down until a button or serial
+/// command wakes the Arduino up, e.g. for battery-powered builds.
+/// Otherwise the boot song repeats forever.
+const PLAY_ONCE: bool = false;
+
+/// Pins of the control buttons, each wired between its pin and GND.
+/// `None` leaves a button out.
+const BUTTONS: buttons::Config = buttons::Config {
+    play_pause: Some(ButtonPin::C(0)),    // A0
+    next_song: Some(ButtonPin::C(1)),     // A1
+    previous_song: Some(ButtonPin::C(2)), // A2
+    tempo_up: Some(ButtonPin::C(3)),      // A3
+    tempo_down: Some(ButtonPin::C(4)),    // A4
+};
+const _: () = assert!(
+    BUTTONS.is_valid(),
+    "buttons must be on free pins of their own"
+);
 
 #[arduino_hal::entry]
 fn main() -> ! {
-    unsafe { peripherals::init() };
+    unsafe { peripherals::init(TONE_MODE) };
 
     let periphs = unsafe { peripherals::get() };
 
     print_song(periphs);
 
-    const BPM: f32 = 60.;
-    const SINGLE_BEAT_SECS: f32 = BPM / 60.;
+    ufmt::uwriteln!(&mut periphs.serial, "playing song").unwrap();
+
+    let mut remote = remote::Remote::new(periphs, &SONG_VOICES);
+
+    scheduler::set_tempo(SONG_TEMPO);
 
-    periphs.setup_clock();
+    // notes of every voice are advanced from the TIMER0 interrupt from here on
+    remote.play_at_boot(periphs, BOOT_FROM_LIBRARY, !PLAY_ONCE);
 
-    ufmt::uwriteln!(&mut periphs.serial, "clock setup complete, playing song").unwrap();
+    buttons::setup(periphs, &BUTTONS);
 
     loop {
-        // loop over all notes
-        for (_i, unique_index) in OPTIMIZED.list.iter().enumerate() {
-            let note: &Note = OPTIMIZED.uniques.get(*unique_index).unwrap();
-            let freq = frequency(note);
-
-            // the current note should be played for this many seconds
-            let note_seconds_total = match note.length {
-                ard_r_sound_base::Length::Unit => SINGLE_BEAT_SECS,
-                ard_r_sound_base::Length::Multiple(m) => SINGLE_BEAT_SECS * m as f32,
-                ard_r_sound_base::Length::Division(d) => SINGLE_BEAT_SECS / d as f32,
-            };
-
-            // ufmt::uwriteln!(
-            //     &mut periphs.serial,
-            //     "note #{}: uniq={}, freq={}, secs={}",
-            //     _i,
-            //     unique_index,
-            //     print_wrappers::F32Wrapper(freq.unwrap_or(0f32)),
-            //     print_wrappers::F32Wrapper(note_seconds_total)
-            // )
-            // .unwrap();
-
-            match freq {
-                Some(freq) => {
-                    periphs.set_frequency(freq).unwrap();
-                    arduino_hal::delay_us((note_seconds_total * 1_000_000.) as u32);
-                    periphs.disable_clock();
-                }
-                None => {
-                    periphs.disable_clock();
-                    arduino_hal::delay_us((note_seconds_total * 1_000_000.) as u32);
-                }
+        // playback is entirely interrupt driven, so only commands are handled here
+        remote.poll(periphs);
+
+        if let Some(action) = buttons::poll() {
+            remote.press(periphs, action);
+        }
+
+        // sleep until the next interrupt, the clocks have to keep running
+        // while playing or debouncing, but not once the song is over
+        let slept = power::sleep(periphs, || {
+            if remote.has_input() {
+                None
+            } else if scheduler::progress().playing || buttons::is_bouncing() || remote.is_awake() {
+                Some(power::SleepMode::Idle)
+            } else {
+                Some(power::SleepMode::PowerDown)
             }
+        });
+
+        if slept == Some(power::SleepMode::PowerDown) {
+            // might have been serial data, which takes a moment to receive
+            remote.keep_awake();
         }
-        periphs.disable_clock();
     }
 }
 
@@ -98,39 +131,22 @@ fn panic(panic_info: &core::panic::PanicInfo) -> ! {
     loop {}
 }
 
-// calculate frequency of a note
-pub fn frequency(note: &Note) -> Option<f32> {
-    const MIDDLE_A_FREQUENCY: f32 = 440.0;
-    // can't do a const powi here
-    const TWELFTH_ROOT_OF_TWO: f32 = 1.059_463_1; // 12th root of 2 or 2^(1/12)
-
-    match note.pitch {
-        ard_r_sound_base::PitchOrRest::Pitch { class, octave } => {
-            let half_steps_away = (octave as i32 * 12) + class.half_steps_from_a() as i32;
-
-            let frequency =
-                MIDDLE_A_FREQUENCY * micromath::F32Ext::powi(TWELFTH_ROOT_OF_TWO, half_steps_away);
-
-            Some(frequency)
-        }
-        ard_r_sound_base::PitchOrRest::Rest => None,
-    }
-}
-
 fn print_song(periphs: &mut peripherals::Peripherals) {
     // print uniques
-    for (i, unique) in OPTIMIZED.uniques.iter().enumerate() {
-        ufmt::uwriteln!(
-            &mut periphs.serial,
-            "unique #{}: {}",
-            i,
-            print_wrappers::NoteWrapper(unique)
-        )
-        .unwrap()
+    for i in 0..SONG.uniques.len() {
+        if let Some(Ok(unique)) = SONG.uniques.load_at(i).map(|unique| unique.unpack()) {
+            ufmt::uwriteln!(
+                &mut periphs.serial,
+                "unique #{}: {}",
+                i,
+                print_wrappers::NoteWrapper(&unique)
+            )
+            .unwrap()
+        }
     }
 
-    // print note list
-    for (i, index) in OPTIMIZED.list.iter().enumerate() {
+    // print note list, expanding the compressed phrases
+    for (i, index) in SONG.indexes().enumerate() {
         ufmt::uwriteln!(&mut periphs.serial, "list #{}: {}", i, index).unwrap();
     }
 }
@@ -1,11 +1,17 @@
-use arduino_hal::simple_pwm::Prescaler;
+use ard_r_sound_base::timer_duration::{
+    ActiveInactiveTimerDurations, Prescaler, Timer, TimerDuration, TimerError, ToneState,
+};
+use ard_r_sound_player::{
+    atmega328p::{Timer1Tone, TonePins},
+    ToneOutput,
+};
 use arduino_hal::{
     clock::MHz16,
     hal::{
-        port::{PB5, PD0, PD1, PD5},
+        port::{PB1, PB2, PB3, PB5, PD0, PD1, PD5},
         Usart,
     },
-    pac::USART0,
+    pac::{TC1, USART0},
     port::{
         mode::{Input, Output},
         Pin,
@@ -14,19 +20,40 @@ use arduino_hal::{
 
 use core::mem::MaybeUninit;
 
+/// Number of buzzers that can play at the same time, see `set_voice_frequency()`
+pub const VOICES: usize = 2;
+
 /// struct representing all peripherals that we might want to access
 pub struct Peripherals {
     pub serial: Usart<USART0, Pin<Input, PD0>, Pin<Output, PD1>, MHz16>,
     pub led: Pin<Output, PB5>,
     pub buzzer: Pin<Output, PD5>,
-    pub clock: arduino_hal::hal::pac::TC1,
+    /// OC1A, toggled by the clock itself in hardware tone modes
+    pub tone_pin_a: Pin<Output, PB1>,
+    /// OC1B, toggled by the clock itself in hardware tone modes
+    pub tone_pin_b: Pin<Output, PB2>,
+    /// generates the buzzer signal, depending on the tone mode
+    pub clock: Clock,
+    /// clock used by the note scheduler
+    pub note_clock: arduino_hal::hal::pac::TC0,
+    /// OC2A, toggled by `second_clock` for the second voice
+    pub second_buzzer: Pin<Output, PB3>,
+    /// clock for the second voice
+    pub second_clock: arduino_hal::hal::pac::TC2,
+    /// holds a song uploaded over serial across resets, see `storage`
+    pub eeprom: arduino_hal::Eeprom,
+    /// pin change interrupts of the buttons, see `buttons`
+    pub exint: arduino_hal::pac::EXINT,
+    /// sleep mode control, see `power`
+    pub cpu: arduino_hal::pac::CPU,
 }
 
 /// global variable, since we may need to access them inside the panic handler
 static mut PERIPHS: MaybeUninit<Peripherals> = MaybeUninit::<_>::uninit();
 
-/// initialize peripherals, *must* be called *once* before `get()`
-pub unsafe fn init() {
+/// initialize peripherals, the buzzer signal is generated using `tone_mode`.
+/// *must* be called *once* before `get()`
+pub unsafe fn init(tone_mode: ToneMode) {
     let peripherals = {
         let dp = arduino_hal::Peripherals::take().unwrap();
 
@@ -38,7 +65,22 @@ pub unsafe fn init() {
 
         let buzzer = pins.d5.into_output();
 
-        let clock = dp.TC1;
+        // only driven by the clock once a hardware tone mode connects them
+        let tone_pin_a = pins.d9.into_output();
+        let tone_pin_b = pins.d10.into_output();
+
+        let clock = Clock::new(dp.TC1, tone_mode);
+
+        let note_clock = dp.TC0;
+
+        let second_buzzer = pins.d11.into_output();
+        let second_clock = dp.TC2;
+
+        let eeprom = arduino_hal::Eeprom::new(dp.EEPROM);
+
+        let exint = dp.EXINT;
+
+        let cpu = dp.CPU;
 
         // enable interrupts
         unsafe { avr_device::interrupt::enable() };
@@ -47,7 +89,15 @@ pub unsafe fn init() {
             serial,
             led,
             buzzer,
+            tone_pin_a,
+            tone_pin_b,
             clock,
+            note_clock,
+            second_buzzer,
+            second_clock,
+            eeprom,
+            exint,
+            cpu,
         }
     };
 
@@ -55,37 +105,73 @@ pub unsafe fn init() {
     PERIPHS.write(peripherals);
 }
 
-impl Peripherals {
-    /// Set up the clock for buzzer output.
-    /// Only needs to be run once, but it's OK to run it multiple times.
-    pub fn setup_clock(&self) {
-        // set up clock
-        // CTC (clear timer on compare match) mode
-        // WGM10 WGM11 to both 0
-        self.clock.tccr1a.write(|w| w.wgm1().bits(0b00));
-        // WGM12 WGM13 to 1, 0
-        self.clock.tccr1b.write(|w| w.wgm1().bits(0b01));
-
-        // TODO: decide if this helps at all vs just toggling in the interrupt
-        //       (would need to switch to pin 9)
-        // set compare output mode on OC1A (pin 9) to toggle
-        // COM1A0 to 1, COM1A1 to 0
-        // self.clock.tccr1a.write(|w| w.com1a().bits(0b01));
-
-        // enable interrupt on output control pin
-        self.clock.timsk1.write(|w| w.ocie1a().set_bit());
+/// How the buzzer signal is generated by TIMER1.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ToneMode {
+    /// `TIMER1_COMPA` toggles the buzzer on D5 between short active and long
+    /// inactive durations.
+    Software,
+    /// The clock toggles OC1A (D9) on every compare match.
+    /// 50% duty cycle, no interrupts and no jitter.
+    HardwareA,
+    /// The clock toggles OC1B (D10) on every compare match.
+    HardwareB,
+    /// The clock toggles both OC1A (D9) and OC1B (D10) in antiphase,
+    /// for a buzzer wired between the two pins (twice the voltage swing).
+    HardwareBoth,
+}
+
+/// TIMER1, set up for a tone mode.
+pub enum Clock {
+    /// `ToneMode::Software`, driven by `TIMER1_COMPA`
+    Software(TC1),
+    /// the hardware tone modes, the clock toggles the pins itself
+    Hardware(Timer1Tone),
+}
+
+impl Clock {
+    /// Set up `clock` for buzzer output using `mode`, silent until the first note.
+    fn new(clock: TC1, mode: ToneMode) -> Self {
+        let pins = match mode {
+            ToneMode::Software => {
+                // CTC (clear timer on compare match) mode
+                // WGM10 WGM11 to both 0
+                clock.tccr1a.write(|w| w.wgm1().bits(0b00));
+                // WGM12 WGM13 to 1, 0
+                clock.tccr1b.write(|w| w.wgm1().bits(0b01));
+                // enable interrupt on output control pin
+                clock.timsk1.write(|w| w.ocie1a().set_bit());
+
+                return Clock::Software(clock);
+            }
+            ToneMode::HardwareA => TonePins::A,
+            ToneMode::HardwareB => TonePins::B,
+            ToneMode::HardwareBoth => TonePins::Both,
+        };
+
+        Clock::Hardware(Timer1Tone::new(clock, pins))
     }
+}
 
+impl Peripherals {
     /// Disable buzzer clock.
-    pub fn disable_clock(&self) {
-        // disable clock
-        self.clock.tccr1b.write(|w| w.cs1().no_clock());
+    pub fn disable_clock(&mut self) {
+        let clock = match &mut self.clock {
+            Clock::Hardware(tone) => return tone.silence(0),
+            Clock::Software(clock) => clock,
+        };
+
+        // disable clock, keeping the waveform generation mode
+        clock.tccr1b.modify(|_, w| w.cs1().no_clock());
 
         // write output compare register
-        self.clock.ocr1a.write(|w| w.bits(u16::MAX));
+        clock.ocr1a.write(|w| w.bits(u16::MAX));
 
         // write zero to counter
-        self.clock.tcnt1.write(|w| w.bits(0));
+        clock.tcnt1.write(|w| w.bits(0));
+
+        // don't leave the buzzer high if we stopped in the active part of the cycle
+        self.buzzer.set_low();
 
         // tear down global state
         avr_device::interrupt::free(|cs| {
@@ -97,66 +183,145 @@ impl Peripherals {
 
     /// Set the frequency for the buzzer.
     /// Also starts the buzzer and begins the active/inactive cycle for the
-    /// buzzer via interrupts.
-    pub fn set_frequency(&mut self, frequency: f32) -> Result<(), ()> {
-        // try to split frequency into active and inactive timer durations
-        let durations =
-            crate::timer_duration::ActiveInactiveTimerDurations::try_from_frequency(frequency)?;
+    /// buzzer via interrupts, with the buzzer active for `duty_cycle` of
+    /// each period (see `Dynamic::duty_cycle()`).
+    /// The hardware tone modes always toggle with a 50% duty cycle.
+    pub fn set_frequency(&mut self, frequency: f32, duty_cycle: f32) -> Result<(), TimerError> {
+        let clock = match &mut self.clock {
+            Clock::Hardware(tone) => return tone.set_frequency(0, frequency, duty_cycle),
+            Clock::Software(clock) => clock,
+        };
 
-        // print over serial
-        // ufmt::uwriteln!(&mut self.serial, "durations: {}", &durations).unwrap();
+        // try to split frequency into active and inactive timer durations
+        let durations = ActiveInactiveTimerDurations::try_from_frequency(frequency, duty_cycle)?;
 
         // set up global state
         avr_device::interrupt::free(|cs| {
             let state = AUDIO_STATE.borrow(cs);
 
             // set active time duration
-            self.set_timer_durations(&durations.active);
+            set_timer_durations(clock, &durations.active);
 
             // raise pin high
             self.buzzer.set_high();
 
             // set state
-            state.set(Some(AudioState {
-                active: true,
-                timer_durations: durations,
-            }));
+            state.set(Some(ToneState::start(durations)));
         });
 
         Ok(())
     }
 
-    /// Write registers with timer duration data.
-    fn set_timer_durations(&mut self, duration: &crate::timer_duration::TimerDuration) {
+    /// Set the frequency of the buzzer for `voice`.
+    /// Voice 0 is the buzzer driven by `clock` (see `set_frequency()`),
+    /// voice 1 is the buzzer on D11, toggled by `second_clock`
+    /// with a 50% duty cycle.
+    pub fn set_voice_frequency(
+        &mut self,
+        voice: usize,
+        frequency: f32,
+        duty_cycle: f32,
+    ) -> Result<(), TimerError> {
+        match voice {
+            0 => self.set_frequency(frequency, duty_cycle),
+            _ => self.set_second_frequency(frequency),
+        }
+    }
+
+    /// Silence the buzzer for `voice`.
+    pub fn disable_voice(&mut self, voice: usize) {
+        match voice {
+            0 => self.disable_clock(),
+            _ => self.disable_second_clock(),
+        }
+    }
+
+    /// Toggle OC2A (D11) at `frequency`, in hardware like the hardware tone modes.
+    fn set_second_frequency(&mut self, frequency: f32) -> Result<(), TimerError> {
+        let half_period = TimerDuration::try_from_half_period_on(frequency, &Timer::TIMER2)?;
+
         // write zero to counter
-        self.clock.tcnt1.write(|w| w.bits(0));
+        self.second_clock.tcnt2.write(|w| w.bits(0));
+
+        // TIMER2 counts to at most 256 ticks, so the compare value fits a byte
+        self.second_clock
+            .ocr2a
+            .write(|w| w.bits(half_period.compare_value() as u8));
 
-        // set up output compare register
-        self.clock.ocr1a.write(|w| w.bits(duration.ticks()));
+        // CTC mode, toggle OC2A on compare match
+        self.second_clock
+            .tccr2a
+            .write(|w| w.wgm2().ctc().com2a().match_toggle());
 
         // set running and prescaler
-        self.clock.tccr1b.write(|w| match &duration.prescale() {
-            Prescaler::Direct => w.cs1().direct(),
-            Prescaler::Prescale8 => w.cs1().prescale_8(),
-            Prescaler::Prescale64 => w.cs1().prescale_64(),
-            Prescaler::Prescale256 => w.cs1().prescale_256(),
-            Prescaler::Prescale1024 => w.cs1().prescale_1024(),
-        });
+        self.second_clock
+            .tccr2b
+            .write(|w| match half_period.prescale() {
+                Prescaler::Direct => w.cs2().direct(),
+                Prescaler::Prescale8 => w.cs2().prescale_8(),
+                Prescaler::Prescale32 => w.cs2().prescale_32(),
+                Prescaler::Prescale64 => w.cs2().prescale_64(),
+                Prescaler::Prescale128 => w.cs2().prescale_128(),
+                Prescaler::Prescale256 => w.cs2().prescale_256(),
+                Prescaler::Prescale1024 => w.cs2().prescale_1024(),
+            });
+
+        Ok(())
+    }
+
+    /// Stop `second_clock` and disconnect OC2A.
+    fn disable_second_clock(&mut self) {
+        self.second_clock.tccr2b.write(|w| w.cs2().no_clock());
+        self.second_clock
+            .tccr2a
+            .write(|w| w.wgm2().ctc().com2a().disconnected());
+        self.second_clock.tcnt2.write(|w| w.bits(0));
+        self.second_buzzer.set_low();
     }
 }
 
-/// Global variable for audio state.
-static AUDIO_STATE: avr_device::interrupt::Mutex<core::cell::Cell<Option<AudioState>>> =
-    avr_device::interrupt::Mutex::new(core::cell::Cell::new(None));
+/// Write registers of the software tone mode's clock with timer duration data.
+fn set_timer_durations(clock: &TC1, duration: &TimerDuration) {
+    // write zero to counter
+    clock.tcnt1.write(|w| w.bits(0));
+
+    // set up output compare register
+    clock.ocr1a.write(|w| w.bits(duration.compare_value()));
+
+    // set running and prescaler, keeping the waveform generation mode
+    clock.tccr1b.modify(|_, w| match duration.prescale() {
+        Prescaler::Direct => w.cs1().direct(),
+        Prescaler::Prescale8 => w.cs1().prescale_8(),
+        Prescaler::Prescale64 => w.cs1().prescale_64(),
+        Prescaler::Prescale256 => w.cs1().prescale_256(),
+        Prescaler::Prescale1024 => w.cs1().prescale_1024(),
+        // only TIMER2 has these, they are never picked for TIMER1
+        Prescaler::Prescale32 | Prescaler::Prescale128 => w.cs1().no_clock(),
+    });
+}
 
-/// Represents the current audio state and timer durations.
-struct AudioState {
-    /// are we active right now?
-    active: bool,
-    /// the durations of the active and inactive states
-    timer_durations: crate::timer_duration::ActiveInactiveTimerDurations,
+/// Both buzzers, voice 0 on `clock` and voice 1 on `second_clock`
+impl ToneOutput for Peripherals {
+    type Error = TimerError;
+
+    fn set_frequency(
+        &mut self,
+        voice: usize,
+        frequency: f32,
+        duty_cycle: f32,
+    ) -> Result<(), TimerError> {
+        self.set_voice_frequency(voice, frequency, duty_cycle)
+    }
+
+    fn silence(&mut self, voice: usize) {
+        self.disable_voice(voice)
+    }
 }
 
+/// Global variable for audio state.
+static AUDIO_STATE: avr_device::interrupt::Mutex<core::cell::Cell<Option<ToneState>>> =
+    avr_device::interrupt::Mutex::new(core::cell::Cell::new(None));
+
 /// Interrupt service routine for timer1 comparison.
 /// Switches between active and inactive buzzer state.
 #[avr_device::interrupt(atmega328p)]
@@ -165,31 +330,19 @@ fn TIMER1_COMPA() {
         let periphs = unsafe { get() };
         let state_cell = AUDIO_STATE.borrow(cs);
         if let Some(mut state) = state_cell.take() {
-            match state.active {
-                true => {
-                    // we are active, we must go inactive
-                    state.active = false;
-
-                    // set up timers for inactive
-                    periphs.set_timer_durations(&state.timer_durations.inactive);
-
-                    // set pin low
-                    periphs.buzzer.set_low();
-                }
-                false => {
-                    // we are inactive, we must go active
-                    state.active = true;
-
-                    // set up timers for active
-                    periphs.set_timer_durations(&state.timer_durations.inactive);
-
-                    // set pin high
-                    periphs.buzzer.set_high();
-                }
+            state.advance();
+
+            // set up timers for the state we just entered
+            if let Clock::Software(clock) = &periphs.clock {
+                set_timer_durations(clock, state.duration());
+            }
+
+            match state.is_active() {
+                true => periphs.buzzer.set_high(),
+                false => periphs.buzzer.set_low(),
             }
 
             // update the state
-            // TODO: split state so that we only have to write 1 boolean here?
             state_cell.set(Some(state));
         }
     });
@@ -0,0 +1,70 @@
+use crate::peripherals::Peripherals;
+
+/// Bit of the serial RX pin (PD0) in `PCMSK2`
+const RX_PIN_MASK: u8 = 1 << 0;
+
+/// How deeply to sleep.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SleepMode {
+    /// Only the CPU stops, the clocks keep playing notes
+    /// and any interrupt wakes it up again.
+    Idle,
+    /// Everything stops but the pin change interrupts,
+    /// so only a button or incoming serial data wakes it up.
+    /// The serial byte that wakes it up is lost.
+    PowerDown,
+}
+
+/// Sleep in the mode chosen by `mode` until an interrupt, or don't sleep at all
+/// if it returns `None`.
+/// `mode` is called with interrupts disabled, so that an interrupt that
+/// leaves work for the main loop can't sneak in between choosing to sleep
+/// and sleeping. Returns the mode that was slept in.
+pub fn sleep(
+    periphs: &mut Peripherals,
+    mode: impl FnOnce() -> Option<SleepMode>,
+) -> Option<SleepMode> {
+    avr_device::interrupt::disable();
+
+    let mode = match mode() {
+        Some(mode) => mode,
+        None => {
+            unsafe { avr_device::interrupt::enable() };
+            return None;
+        }
+    };
+
+    periphs.cpu.smcr.write(|w| match mode {
+        SleepMode::Idle => w.sm().idle().se().set_bit(),
+        SleepMode::PowerDown => w.sm().pdown().se().set_bit(),
+    });
+
+    if mode == SleepMode::PowerDown {
+        // the USART can't wake the CPU from power down, but a start bit on its pin can
+        periphs
+            .exint
+            .pcmsk2
+            .modify(|r, w| unsafe { w.bits(r.bits() | RX_PIN_MASK) });
+        periphs
+            .exint
+            .pcicr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 0b100) });
+    }
+
+    // the instruction after `sei` always runs before any interrupt,
+    // so an interrupt can't fire between enabling interrupts and sleeping
+    unsafe { core::arch::asm!("sei", "sleep") };
+
+    periphs.cpu.smcr.write(|w| w.se().clear_bit());
+
+    if mode == SleepMode::PowerDown {
+        // every bit received would wake it up otherwise.
+        // the pin change interrupt of port D stays enabled for any buttons on it.
+        periphs
+            .exint
+            .pcmsk2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !RX_PIN_MASK) });
+    }
+
+    Some(mode)
+}
@@ -2,6 +2,7 @@ pub struct NoteWrapper<'a>(pub &'a ard_r_sound_base::Note);
 pub struct PitchOrRestWrapper<'a>(pub &'a ard_r_sound_base::PitchOrRest);
 pub struct PitchClassWrapper<'a>(pub &'a ard_r_sound_base::PitchClass);
 pub struct LengthWrapper<'a>(pub &'a ard_r_sound_base::Length);
+pub struct DynamicWrapper<'a>(pub &'a ard_r_sound_base::Dynamic);
 
 impl ufmt::uDisplay for NoteWrapper<'_> {
     fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
@@ -12,6 +13,8 @@ impl ufmt::uDisplay for NoteWrapper<'_> {
         ufmt::uwrite!(f, "{}", PitchOrRestWrapper(&self.0.pitch))?;
         ufmt::uwrite!(f, ", length: ")?;
         ufmt::uwrite!(f, "{}", LengthWrapper(&self.0.length))?;
+        ufmt::uwrite!(f, ", dynamic: ")?;
+        ufmt::uwrite!(f, "{}", DynamicWrapper(&self.0.dynamic))?;
         ufmt::uwrite!(f, " }}")?;
 
         Ok(())
@@ -82,15 +85,26 @@ impl ufmt::uDisplay for LengthWrapper<'_> {
     }
 }
 
-pub struct F32Wrapper(pub f32);
-
-impl ufmt::uDisplay for F32Wrapper {
+impl ufmt::uDisplay for DynamicWrapper<'_> {
     fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
     where
         W: ufmt::uWrite + ?Sized,
     {
-        let base = self.0 as u32;
-        let decimal = self.0 - (self.0 as u32) as f32;
-        ufmt::uwrite!(f, "~({}+{}E-6)", base, (decimal * 1_000_000f32) as u32)
+        ufmt::uwrite!(
+            f,
+            "Dynamic::{}",
+            match self.0 {
+                ard_r_sound_base::Dynamic::Pianissississimo => "pppp",
+                ard_r_sound_base::Dynamic::Pianississimo => "ppp",
+                ard_r_sound_base::Dynamic::Pianissimo => "pp",
+                ard_r_sound_base::Dynamic::Piano => "p",
+                ard_r_sound_base::Dynamic::MezzoPiano => "mp",
+                ard_r_sound_base::Dynamic::MezzoForte => "mf",
+                ard_r_sound_base::Dynamic::Forte => "f",
+                ard_r_sound_base::Dynamic::Fortissimo => "ff",
+                ard_r_sound_base::Dynamic::Fortississimo => "fff",
+                ard_r_sound_base::Dynamic::Fortissississimo => "ffff",
+            }
+        )
     }
 }
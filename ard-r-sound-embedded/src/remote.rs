@@ -0,0 +1,363 @@
+use ard_r_sound_base::protocol::{
+    Command, Frame, FrameDecoder, Response, ResultCode, Status, Storage, MAX_FRAME,
+};
+use ard_r_sound_base::song_bytes::{ByteSong, Bytes};
+use ard_r_sound_base::Song;
+use avr_device::interrupt::Mutex;
+use core::cell::RefCell;
+use embedded_hal::serial::Read;
+
+use crate::buttons::Action;
+use crate::peripherals::{self, Peripherals};
+use crate::{scheduler, storage};
+
+/// How long to stay out of power down after serial data, in milliseconds.
+/// Data arriving in power down only wakes the CPU up, the bytes are lost,
+/// so the host sends a byte to wake it up before its commands.
+const AWAKE_MILLIS: u32 = 1_000;
+
+/// Bytes received by `USART_RX`, until `Remote::poll()` handles them.
+static RECEIVED: Mutex<RefCell<Received>> = Mutex::new(RefCell::new(Received {
+    bytes: [0; RECEIVED_CAPACITY],
+    start: 0,
+    len: 0,
+}));
+
+/// Room for two frames, the host only sends one before waiting for the answer
+const RECEIVED_CAPACITY: usize = 2 * MAX_FRAME;
+
+/// Ring buffer of received bytes
+struct Received {
+    bytes: [u8; RECEIVED_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl Received {
+    /// Drops `byte` if full, the CRC of its frame catches that.
+    fn push(&mut self, byte: u8) {
+        if self.len < RECEIVED_CAPACITY {
+            self.bytes[(self.start + self.len) % RECEIVED_CAPACITY] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % RECEIVED_CAPACITY;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// How much `Action::TempoUp` and `Action::TempoDown` change the tempo, in percent
+const TEMPO_STEP: u16 = 10;
+/// Slowest and fastest tempo the buttons can set, in percent
+const MIN_TEMPO: u16 = 20;
+const MAX_TEMPO: u16 = 400;
+
+/// An upload in progress
+struct Upload {
+    target: Storage,
+    length: usize,
+    /// end of the furthest chunk written, chunks are sent in order
+    received: usize,
+}
+
+/// Answers commands from `ard-r-sound` received over serial,
+/// see `ard_r_sound_base::protocol`.
+pub struct Remote {
+    decoder: FrameDecoder,
+    upload: Option<Upload>,
+    /// the song that is (or was last) played
+    source: Storage,
+    /// index of the song in the EEPROM library, if `source` is EEPROM
+    song: u8,
+    /// whether the current (or last) song repeats
+    repeat: bool,
+    /// voices of the song compiled into the firmware
+    flash: &'static [&'static (dyn Song + Sync)],
+    /// `scheduler::millis()` when the last byte was received
+    active_millis: u32,
+}
+
+impl Remote {
+    /// Start receiving commands, `flash` is played for `Storage::Flash`.
+    pub fn new(periphs: &mut Peripherals, flash: &'static [&'static (dyn Song + Sync)]) -> Self {
+        periphs
+            .serial
+            .listen(arduino_hal::hal::usart::Event::RxComplete);
+
+        Self {
+            decoder: FrameDecoder::new(),
+            upload: None,
+            source: Storage::Flash,
+            song: 0,
+            repeat: false,
+            flash,
+            active_millis: 0,
+        }
+    }
+
+    /// Start playing the default song of the EEPROM library if `from_library`
+    /// and the EEPROM holds an intact library, the flash song otherwise.
+    pub fn play_at_boot(&mut self, periphs: &mut Peripherals, from_library: bool, repeat: bool) {
+        // an invalid default song is caught by `storage::find()`
+        let default_song = storage::LIBRARY.default_song() as u8;
+        if !from_library
+            || self.play(periphs, Storage::Eeprom, default_song, repeat) != ResultCode::Ok
+        {
+            self.play(periphs, Storage::Flash, 0, repeat);
+        }
+    }
+
+    /// Act on a button press, see `buttons`.
+    pub fn press(&mut self, periphs: &mut Peripherals, action: Action) {
+        match action {
+            Action::PlayPause => {
+                let progress = scheduler::progress();
+                if progress.playing || progress.paused {
+                    scheduler::set_paused(periphs, !progress.paused);
+                } else {
+                    // stopped or over, start from the beginning
+                    self.play(periphs, self.source, self.song, self.repeat);
+                }
+            }
+            Action::NextSong => self.skip(periphs, true),
+            Action::PreviousSong => self.skip(periphs, false),
+            Action::TempoUp => {
+                scheduler::set_tempo((scheduler::tempo() + TEMPO_STEP).min(MAX_TEMPO))
+            }
+            Action::TempoDown => {
+                scheduler::set_tempo(scheduler::tempo().saturating_sub(TEMPO_STEP).max(MIN_TEMPO))
+            }
+        }
+    }
+
+    /// Play the next (or previous) song that can be played.
+    /// Songs are ordered as the flash song, the RAM song,
+    /// then every song of the EEPROM library.
+    fn skip(&mut self, periphs: &mut Peripherals, forward: bool) {
+        let library_len = match storage::LIBRARY.validate() {
+            Ok(()) => storage::LIBRARY.len(),
+            Err(_) => 0,
+        };
+        let count = 2 + library_len;
+        let position = match self.source {
+            Storage::Flash => 0,
+            Storage::Ram => 1,
+            Storage::Eeprom => 2 + self.song as usize,
+        };
+
+        // every song but the current one, at most
+        let mut next = position;
+        for _ in 1..count {
+            next = match forward {
+                true => (next + 1) % count,
+                false => (next + count - 1) % count,
+            };
+
+            let (source, song) = match next {
+                0 => (Storage::Flash, 0),
+                1 => (Storage::Ram, 0),
+                _ => (Storage::Eeprom, (next - 2) as u8),
+            };
+            if self.play(periphs, source, song, self.repeat) == ResultCode::Ok {
+                return;
+            }
+        }
+    }
+
+    /// Are there received bytes that `poll()` hasn't handled yet?
+    pub fn has_input(&self) -> bool {
+        avr_device::interrupt::free(|cs| RECEIVED.borrow(cs).borrow().len > 0)
+    }
+
+    /// Should the CPU stay out of power down, to receive the rest of a command?
+    pub fn is_awake(&self) -> bool {
+        self.upload.is_some() || scheduler::millis().wrapping_sub(self.active_millis) < AWAKE_MILLIS
+    }
+
+    /// Stay out of power down for a while, e.g. after something woke the CPU up.
+    pub fn keep_awake(&mut self) {
+        self.active_millis = scheduler::millis();
+    }
+
+    /// Handle every byte received since the last call, doesn't block.
+    pub fn poll(&mut self, periphs: &mut Peripherals) {
+        while let Some(byte) =
+            avr_device::interrupt::free(|cs| RECEIVED.borrow(cs).borrow_mut().pop())
+        {
+            self.keep_awake();
+
+            let response = match self.decoder.push(byte) {
+                Some(Ok(frame)) => self.handle(periphs, &frame),
+                // the kind may be corrupted too, so don't echo it
+                Some(Err(_)) => Response::Ack {
+                    kind: 0,
+                    result: ResultCode::BadFrame,
+                },
+                None => continue,
+            };
+
+            let mut buf = [0; MAX_FRAME];
+            for byte in response.to_frame().encode(&mut buf) {
+                periphs.serial.write_byte(*byte);
+            }
+        }
+    }
+
+    fn handle(&mut self, periphs: &mut Peripherals, frame: &Frame) -> Response {
+        let result = match Command::from_frame(frame) {
+            Ok(Command::QueryStatus) => return Response::Status(self.status()),
+            Ok(command) => self.execute(periphs, command),
+            Err(result) => result,
+        };
+
+        Response::Ack {
+            kind: frame.kind,
+            result,
+        }
+    }
+
+    fn execute(&mut self, periphs: &mut Peripherals, command: Command) -> ResultCode {
+        match command {
+            Command::UploadBegin { target, length } => {
+                let length = length as usize;
+                if target == Storage::Flash {
+                    return ResultCode::BadPayload;
+                }
+                if length > storage::capacity(periphs, target) {
+                    return ResultCode::OutOfSpace;
+                }
+
+                // never change a song while it's played
+                if self.source == target {
+                    scheduler::stop(periphs);
+                }
+                self.upload = Some(Upload {
+                    target,
+                    length,
+                    received: 0,
+                });
+                ResultCode::Ok
+            }
+            Command::UploadData { offset, data } => match &mut self.upload {
+                Some(upload) if offset as usize + data.len() <= upload.length => {
+                    storage::write(periphs, upload.target, offset as usize, data);
+                    // a resent chunk doesn't count twice
+                    upload.received = upload.received.max(offset as usize + data.len());
+                    ResultCode::Ok
+                }
+                Some(_) => ResultCode::BadPayload,
+                None => ResultCode::NotUploading,
+            },
+            Command::UploadEnd => {
+                let valid = match self.upload.take() {
+                    Some(upload) if upload.received < upload.length => {
+                        return ResultCode::NotUploading
+                    }
+                    Some(Upload {
+                        target: Storage::Ram,
+                        ..
+                    }) => is_playable(&storage::RAM_SONG),
+                    // the songs are checked once they're played
+                    Some(Upload {
+                        target: Storage::Eeprom,
+                        ..
+                    }) => storage::LIBRARY.validate().is_ok(),
+                    Some(_) => false,
+                    None => return ResultCode::NotUploading,
+                };
+
+                match valid {
+                    true => ResultCode::Ok,
+                    false => ResultCode::InvalidSong,
+                }
+            }
+            Command::Play {
+                source,
+                song,
+                repeat,
+            } => self.play(periphs, source, song, repeat),
+            Command::Stop => {
+                scheduler::stop(periphs);
+                ResultCode::Ok
+            }
+            Command::SetTempo { percent } => {
+                scheduler::set_tempo(percent);
+                ResultCode::Ok
+            }
+            // answered with a status rather than an ack
+            Command::QueryStatus => ResultCode::UnknownCommand,
+        }
+    }
+
+    /// Play the song in `source`, `song` is its index in the EEPROM library.
+    /// Playback only stops if the song can be played.
+    fn play(
+        &mut self,
+        periphs: &mut Peripherals,
+        source: Storage,
+        song: u8,
+        repeat: bool,
+    ) -> ResultCode {
+        static RAM_VOICES: [&(dyn Song + Sync); 1] = [&storage::RAM_SONG];
+        static EEPROM_VOICES: [&(dyn Song + Sync); 1] = [&storage::EEPROM_SONG];
+
+        let voices: &'static [&'static (dyn Song + Sync)] = match source {
+            Storage::Flash => self.flash,
+            Storage::Ram if is_playable(&storage::RAM_SONG) => &RAM_VOICES,
+            Storage::Eeprom => match storage::find(song as usize) {
+                Ok(entry) => {
+                    scheduler::stop(periphs);
+                    storage::select(entry);
+                    &EEPROM_VOICES
+                }
+                Err(_) => return ResultCode::NoSong,
+            },
+            Storage::Ram => return ResultCode::NoSong,
+        };
+
+        scheduler::stop(periphs);
+        scheduler::start(periphs, voices, repeat);
+        self.source = source;
+        self.song = song;
+        self.repeat = repeat;
+        ResultCode::Ok
+    }
+
+    fn status(&self) -> Status {
+        let progress = scheduler::progress();
+        Status {
+            playing: progress.playing,
+            source: self.source,
+            song: match self.source {
+                Storage::Eeprom => self.song,
+                _ => 0,
+            },
+            note_index: progress.index as u16,
+            note_count: progress.len as u16,
+            tempo_percent: scheduler::tempo(),
+        }
+    }
+}
+
+fn is_playable<B: Bytes>(song: &ByteSong<B>) -> bool {
+    song.validate().is_ok() && !song.is_empty()
+}
+
+/// Interrupt service routine for received serial data.
+/// Buffers the byte, so that no byte is lost while the main loop is busy or asleep.
+#[avr_device::interrupt(atmega328p)]
+fn USART_RX() {
+    avr_device::interrupt::free(|cs| {
+        let periphs = unsafe { peripherals::get() };
+        if let Ok(byte) = periphs.serial.read() {
+            RECEIVED.borrow(cs).borrow_mut().push(byte);
+        }
+    });
+}
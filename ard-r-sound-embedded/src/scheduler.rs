@@ -0,0 +1,110 @@
+use ard_r_sound_base::Song;
+use ard_r_sound_player::{Player, Progress, Timebase};
+use avr_device::interrupt::Mutex;
+use core::cell::{Cell, RefCell};
+
+use crate::peripherals::{self, Peripherals};
+
+/// TIMER0 compare value for a 1 kHz tick: 16 MHz / 64 / (249 + 1) = 1 kHz
+const TICK_COMPARE: u8 = 249;
+
+/// Global variable for the player, polled by `TIMER0_COMPA`.
+static PLAYER: Mutex<RefCell<Option<Player<{ peripherals::VOICES }>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Tempo in percent of the song's tempo, applies from the next note on.
+static TEMPO_PERCENT: Mutex<Cell<u16>> = Mutex::new(Cell::new(100));
+
+/// Milliseconds since `start()` was first called, keeps counting while stopped.
+static MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// The milliseconds counted by TIMER0, the timebase of the player.
+struct Timer0Millis;
+
+impl Timebase for Timer0Millis {
+    fn millis(&self) -> u32 {
+        millis()
+    }
+}
+
+/// Start playing `voices` at the same time from TIMER0 interrupts,
+/// the first notes start right away.
+/// Voice `n` plays on buzzer `n` (see `Peripherals::set_voice_frequency()`),
+/// voices beyond `peripherals::VOICES` are not played.
+pub fn start(periphs: &mut Peripherals, voices: &[&'static (dyn Song + Sync)], repeat: bool) {
+    let mut player = Player::start(voices, repeat, periphs);
+    player.set_tempo(tempo());
+
+    avr_device::interrupt::free(|cs| {
+        PLAYER.borrow(cs).replace(Some(player));
+    });
+
+    // CTC mode, interrupt once per millisecond
+    periphs.note_clock.tccr0a.write(|w| w.wgm0().ctc());
+    periphs.note_clock.ocr0a.write(|w| w.bits(TICK_COMPARE));
+    periphs.note_clock.tcnt0.write(|w| w.bits(0));
+    periphs.note_clock.tccr0b.write(|w| w.cs0().prescale_64());
+    periphs.note_clock.timsk0.write(|w| w.ocie0a().set_bit());
+}
+
+/// Stop playing and silence every buzzer.
+pub fn stop(periphs: &mut Peripherals) {
+    match avr_device::interrupt::free(|cs| PLAYER.borrow(cs).replace(None)) {
+        Some(player) => player.stop(periphs),
+        None => {
+            for voice in 0..peripherals::VOICES {
+                periphs.disable_voice(voice);
+            }
+        }
+    }
+}
+
+/// Pause or resume playback, does nothing once stopped.
+pub fn set_paused(periphs: &mut Peripherals, paused: bool) {
+    avr_device::interrupt::free(|cs| {
+        if let Some(player) = PLAYER.borrow(cs).borrow_mut().as_mut() {
+            player.set_paused(periphs, paused);
+        }
+    });
+}
+
+/// Play at `percent` of the song's tempo, from the next note on.
+pub fn set_tempo(percent: u16) {
+    avr_device::interrupt::free(|cs| {
+        TEMPO_PERCENT.borrow(cs).set(percent.max(1));
+        if let Some(player) = PLAYER.borrow(cs).borrow_mut().as_mut() {
+            player.set_tempo(percent);
+        }
+    });
+}
+
+pub fn tempo() -> u16 {
+    avr_device::interrupt::free(|cs| TEMPO_PERCENT.borrow(cs).get())
+}
+
+/// Milliseconds since playback first started, for timing outside of playback.
+pub fn millis() -> u32 {
+    avr_device::interrupt::free(|cs| MILLIS.borrow(cs).get())
+}
+
+pub fn progress() -> Progress {
+    avr_device::interrupt::free(|cs| match PLAYER.borrow(cs).borrow().as_ref() {
+        Some(player) => player.progress(),
+        None => Progress::default(),
+    })
+}
+
+/// Interrupt service routine for timer0 comparison, fires every millisecond.
+/// Starts the next note of each voice once its current one is over.
+#[avr_device::interrupt(atmega328p)]
+fn TIMER0_COMPA() {
+    avr_device::interrupt::free(|cs| {
+        let millis = MILLIS.borrow(cs);
+        millis.set(millis.get().wrapping_add(1));
+
+        let periphs = unsafe { peripherals::get() };
+        if let Some(player) = PLAYER.borrow(cs).borrow_mut().as_mut() {
+            player.poll(periphs, &Timer0Millis);
+        }
+    });
+}
@@ -0,0 +1,88 @@
+use ard_r_sound_base::library::{Entry, Library, LibraryError, Window};
+use ard_r_sound_base::protocol::Storage;
+use ard_r_sound_base::song_bytes::{ByteSong, Bytes};
+use avr_device::interrupt::Mutex;
+use core::cell::Cell;
+
+use crate::peripherals::{self, Peripherals};
+
+/// Size of the RAM song buffer, a quarter of the ATmega328P's RAM
+pub const RAM_CAPACITY: usize = 512;
+
+/// Song uploaded to RAM, lost on reset.
+/// Only written while it isn't being played, see `remote`.
+static mut RAM: [u8; RAM_CAPACITY] = [0; RAM_CAPACITY];
+
+/// Reads the song uploaded to RAM
+pub struct RamBytes;
+
+impl Bytes for RamBytes {
+    fn byte(&self, index: usize) -> Option<u8> {
+        // a copy of one byte, never a reference into `RAM`
+        (index < RAM_CAPACITY).then(|| unsafe { RAM[index] })
+    }
+}
+
+/// Reads the library uploaded to EEPROM
+#[derive(Clone, Copy)]
+pub struct EepromBytes;
+
+impl Bytes for EepromBytes {
+    fn byte(&self, index: usize) -> Option<u8> {
+        let eeprom = &unsafe { peripherals::get() }.eeprom;
+        (index < eeprom.capacity() as usize).then(|| eeprom.read_byte(index as u16))
+    }
+}
+
+/// Start and length of the library song played by `EEPROM_SONG`, see `select()`
+static SELECTED: Mutex<Cell<(usize, usize)>> = Mutex::new(Cell::new((0, 0)));
+
+/// Reads the selected song of the EEPROM library
+pub struct SelectedBytes;
+
+impl Bytes for SelectedBytes {
+    fn byte(&self, index: usize) -> Option<u8> {
+        let (start, len) = avr_device::interrupt::free(|cs| SELECTED.borrow(cs).get());
+        Window::new(EepromBytes, start, len).byte(index)
+    }
+}
+
+pub static RAM_SONG: ByteSong<RamBytes> = ByteSong::new(RamBytes);
+pub static LIBRARY: Library<EepromBytes> = Library::new(EepromBytes);
+pub static EEPROM_SONG: ByteSong<SelectedBytes> = ByteSong::new(SelectedBytes);
+
+/// Where song `index` of the EEPROM library is, if the library and the song are intact.
+pub fn find(index: usize) -> Result<Entry, LibraryError> {
+    LIBRARY.validate()?;
+    LIBRARY.validate_song(index)?;
+    LIBRARY.entry(index)
+}
+
+/// Make `EEPROM_SONG` play the library song at `entry`, see `find()`.
+/// Must not be called while `EEPROM_SONG` is played.
+pub fn select(entry: Entry) {
+    avr_device::interrupt::free(|cs| SELECTED.borrow(cs).set((entry.offset, entry.len)));
+}
+
+/// How many bytes can be uploaded to `target`, 0 for flash.
+pub fn capacity(periphs: &Peripherals, target: Storage) -> usize {
+    match target {
+        Storage::Flash => 0,
+        Storage::Ram => RAM_CAPACITY,
+        Storage::Eeprom => periphs.eeprom.capacity() as usize,
+    }
+}
+
+/// Write `data` to `target` at `offset`, which must fit in `capacity()`.
+pub fn write(periphs: &mut Peripherals, target: Storage, offset: usize, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        let address = offset + i;
+        match target {
+            Storage::Flash => {}
+            Storage::Ram => unsafe { RAM[address] = *byte },
+            // skip unchanged bytes, writes are slow and wear the EEPROM out
+            Storage::Eeprom if periphs.eeprom.read_byte(address as u16) == *byte => {}
+            Storage::Eeprom => periphs.eeprom.write_byte(address as u16, *byte),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Arguments of `include_song!`
+
+use proc_macro2::Span;
+use syn::parse::{Parse, ParseStream};
+
+use crate::Encoding;
+
+/// An option and where it was set, for errors about its value.
+/// Options that aren't set point at the path.
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    fn new(value: T, span: Span) -> Self {
+        Self { value, span }
+    }
+}
+
+pub struct IncludeArgs {
+    pub var_name: syn::Ident,
+    /// relative to `CARGO_MANIFEST_DIR`
+    pub path: syn::LitStr,
+    /// percent of the written tempo
+    pub tempo: Spanned<u16>,
+    /// half steps
+    pub transpose: Spanned<i32>,
+    pub tune: Spanned<usize>,
+    pub encoding: Encoding,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Storage {
+    Ram,
+    ProgMem,
+}
+
+/// One of `choices`, written as an identifier
+fn choice<T: Copy>(input: ParseStream, choices: &[(&str, T)]) -> syn::Result<Spanned<T>> {
+    let ident: syn::Ident = input.parse()?;
+    match choices.iter().find(|(name, _)| ident == name) {
+        Some((_, value)) => Ok(Spanned::new(*value, ident.span())),
+        None => {
+            let names: Vec<String> = choices
+                .iter()
+                .map(|(name, _)| format!("`{}`", name))
+                .collect();
+            Err(syn::Error::new(
+                ident.span(),
+                format!("expected one of {}", names.join(", ")),
+            ))
+        }
+    }
+}
+
+fn integer<T>(input: ParseStream) -> syn::Result<Spanned<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let literal: syn::LitInt = input.parse()?;
+    Ok(Spanned::new(literal.base10_parse()?, literal.span()))
+}
+
+impl Parse for IncludeArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let var_name = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let path: syn::LitStr = input.parse()?;
+
+        let unset = path.span();
+        let mut tempo = Spanned::new(100, unset);
+        let mut transpose = Spanned::new(0, unset);
+        let mut tune = Spanned::new(0, unset);
+        let mut encoding = Spanned::new(Encoding::Optimized, unset);
+        let mut storage = Spanned::new(Storage::Ram, unset);
+
+        let mut seen = Vec::new();
+        while !input.is_empty() {
+            input.parse::<syn::Token![,]>()?;
+            // trailing comma
+            if input.is_empty() {
+                break;
+            }
+
+            let key: syn::Ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            if seen.contains(&key) {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("`{}` is set more than once", key),
+                ));
+            }
+
+            match key.to_string().as_str() {
+                "tempo" => {
+                    tempo = integer(input)?;
+                    if tempo.value == 0 {
+                        return Err(syn::Error::new(tempo.span, "tempo must be above 0"));
+                    }
+                }
+                "transpose" => {
+                    let minus = input.parse::<Option<syn::Token![-]>>()?;
+                    transpose = integer(input)?;
+                    if minus.is_some() {
+                        transpose.value = -transpose.value;
+                    }
+                }
+                "tune" => tune = integer(input)?,
+                "encoding" => {
+                    encoding = choice(
+                        input,
+                        &[
+                            ("optimized", Encoding::Optimized),
+                            ("packed", Encoding::Packed),
+                            ("phrases", Encoding::Phrases),
+                        ],
+                    )?
+                }
+                "storage" => {
+                    storage = choice(
+                        input,
+                        &[("ram", Storage::Ram), ("progmem", Storage::ProgMem)],
+                    )?
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!(
+                            "unknown option `{}`, expected `tempo`, `transpose`, `tune`, \
+                             `encoding` or `storage`",
+                            key
+                        ),
+                    ))
+                }
+            }
+            seen.push(key);
+        }
+
+        let encoding = match (encoding.value, storage.value) {
+            (encoding, Storage::Ram) => encoding,
+            (Encoding::Packed, Storage::ProgMem) => Encoding::PackedProgMem,
+            (Encoding::Phrases, Storage::ProgMem) => Encoding::PhrasesProgMem,
+            _ => {
+                return Err(syn::Error::new(
+                    storage.span,
+                    "`storage = progmem` needs `encoding = packed` or `encoding = phrases`",
+                ))
+            }
+        };
+
+        Ok(IncludeArgs {
+            var_name,
+            path,
+            tempo,
+            transpose,
+            tune,
+            encoding,
+        })
+    }
+}
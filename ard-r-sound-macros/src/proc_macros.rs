@@ -1,27 +1,102 @@
-use ard_r_sound_base::{Length, Note, PitchClass, PitchOrRest};
-use ard_r_sound_lib::{codegen::Optimized, parser::parse_abc_file};
+use ard_r_sound_base::packed::{PackedNote, MAX_UNIQUES};
+use ard_r_sound_base::{Dynamic, Length, Note, PitchClass, PitchOrRest};
+use ard_r_sound_core::compress::Phrases;
+use ard_r_sound_core::parser::{self, parse_abc_file};
+use ard_r_sound_core::{abc::ABC, codegen::Optimized};
 use proc_macro2::TokenStream;
 use quote::ToTokens;
 
+mod options;
+
+/// Type of the generated statics
+#[derive(Clone, Copy)]
+pub(crate) enum Encoding {
+    /// `OptimizedStatic`, the default
+    Optimized,
+    /// `PackedStatic`
+    Packed,
+    /// `PackedProgMem`
+    PackedProgMem,
+    /// `PhrasesStatic`
+    Phrases,
+    /// `PhrasesProgMem`
+    PhrasesProgMem,
+}
+
+impl syn::parse::Parse for Encoding {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let ident: syn::Ident = input.parse()?;
+        match ident.to_string().as_str() {
+            "OptimizedStatic" => Ok(Encoding::Optimized),
+            "PackedStatic" => Ok(Encoding::Packed),
+            "PackedProgMem" => Ok(Encoding::PackedProgMem),
+            "PhrasesStatic" => Ok(Encoding::Phrases),
+            "PhrasesProgMem" => Ok(Encoding::PhrasesProgMem),
+            _ => Err(syn::Error::new(
+                ident.span(),
+                "expected `OptimizedStatic`, `PackedStatic`, `PackedProgMem`, \
+                 `PhrasesStatic` or `PhrasesProgMem`",
+            )),
+        }
+    }
+}
+
 struct Args {
     var_name: syn::Ident,
+    encoding: Encoding,
     filename: String,
+    /// The tokens of the filename, to point errors at
+    path: TokenStream,
 }
 
 impl syn::parse::Parse for Args {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let var_name = input.parse()?;
+        let encoding = match input.parse::<Option<syn::Token![:]>>()? {
+            Some(_) => input.parse()?,
+            None => Encoding::Optimized,
+        };
         input.parse::<syn::Token![,]>()?;
 
         // the rest can be any token, they are all concatenated into a filename string
+        let path = input.parse::<TokenStream>()?;
         let mut filename = String::new();
-        for token in input.parse::<TokenStream>()? {
+        for token in path.clone() {
             filename.push_str(&token.to_string());
         }
 
         Ok(Args {
             var_name,
+            encoding,
             filename,
+            path,
+        })
+    }
+}
+
+/// Arguments of `abc!`, a name with an optional type like `static_from_file!`,
+/// and the ABC notation
+struct InlineArgs {
+    var_name: syn::Ident,
+    encoding: Encoding,
+    abc: syn::LitStr,
+}
+
+impl syn::parse::Parse for InlineArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let var_name = input.parse()?;
+        let encoding = match input.parse::<Option<syn::Token![:]>>()? {
+            Some(_) => input.parse()?,
+            None => Encoding::Optimized,
+        };
+        input.parse::<syn::Token![,]>()?;
+        let abc = input.parse()?;
+        input.parse::<Option<syn::Token![,]>>()?;
+
+        Ok(InlineArgs {
+            var_name,
+            encoding,
+            abc,
         })
     }
 }
@@ -30,16 +105,19 @@ struct NoteWrapper<'a>(&'a Note);
 struct PitchOrRestWrapper<'a>(&'a PitchOrRest);
 struct PitchClassWrapper<'a>(&'a PitchClass);
 struct LengthWrapper<'a>(&'a Length);
+struct DynamicWrapper<'a>(&'a Dynamic);
 
 impl<'a> ToTokens for NoteWrapper<'a> {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let pitch = PitchOrRestWrapper(&self.0.pitch);
         let length = LengthWrapper(&self.0.length);
+        let dynamic = DynamicWrapper(&self.0.dynamic);
 
         tokens.extend(quote::quote! {
             Note {
                 pitch: #pitch,
                 length: #length,
+                dynamic: #dynamic,
             }
         })
     }
@@ -102,6 +180,27 @@ impl<'a> ToTokens for LengthWrapper<'a> {
     }
 }
 
+impl<'a> ToTokens for DynamicWrapper<'a> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let inner = match self.0 {
+            Dynamic::Pianissississimo => quote::quote!(Pianissississimo),
+            Dynamic::Pianississimo => quote::quote!(Pianississimo),
+            Dynamic::Pianissimo => quote::quote!(Pianissimo),
+            Dynamic::Piano => quote::quote!(Piano),
+            Dynamic::MezzoPiano => quote::quote!(MezzoPiano),
+            Dynamic::MezzoForte => quote::quote!(MezzoForte),
+            Dynamic::Forte => quote::quote!(Forte),
+            Dynamic::Fortissimo => quote::quote!(Fortissimo),
+            Dynamic::Fortississimo => quote::quote!(Fortississimo),
+            Dynamic::Fortissississimo => quote::quote!(Fortissississimo),
+        };
+
+        tokens.extend(quote::quote! {
+            Dynamic::#inner
+        })
+    }
+}
+
 fn note_to_token(note: &Note) -> TokenStream {
     let wrapper = NoteWrapper(note);
 
@@ -115,31 +214,21 @@ fn notes_to_tokens(notes: Vec<&Note>) -> Vec<TokenStream> {
         .collect::<Vec<_>>()
 }
 
-#[proc_macro]
-pub fn static_from_file(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let args = syn::parse_macro_input!(input as Args);
-
-    // println!("file: {:?}", args.filename);
-
-    let abc = parse_abc_file(std::path::Path::new(&args.filename)).unwrap();
-
-    let optimized = Optimized::from(&abc);
+/// `static` item for one voice of a song
+fn optimized_static(var_name: &syn::Ident, abc: &ABC) -> TokenStream {
+    let optimized = Optimized::from(abc);
 
     let uniques = optimized.uniques;
     let list = optimized.list;
 
-    // println!("{:?}", abc.notes);
-
     let uniques_len = uniques.len();
     let list_len = list.len();
 
     let uniques = notes_to_tokens(uniques);
 
-    let var_name = args.var_name;
-
-    let quoted = quote::quote! {
+    quote::quote! {
         static #var_name: ard_r_sound_base::OptimizedStatic<#uniques_len, #list_len> = {
-            use ard_r_sound_base::{OptimizedStatic, Note, PitchOrRest, PitchClass, Length};
+            use ard_r_sound_base::{OptimizedStatic, Note, PitchOrRest, PitchClass, Length, Dynamic};
 
             OptimizedStatic {
                 uniques: [
@@ -150,11 +239,375 @@ pub fn static_from_file(input: proc_macro::TokenStream) -> proc_macro::TokenStre
                 ]
             }
         };
-    };
+    }
+}
+
+/// The unique notes of `optimized` packed into bits,
+/// for the `static` of type `type_name` named `var_name`
+fn packed_uniques(
+    var_name: &syn::Ident,
+    optimized: &Optimized<'_>,
+    type_name: &str,
+) -> syn::Result<Vec<u16>> {
+    if optimized.uniques.len() > MAX_UNIQUES {
+        return Err(syn::Error::new(
+            var_name.span(),
+            format!(
+                "{} unique notes don't fit in a `{}`, at most {} do",
+                optimized.uniques.len(),
+                type_name,
+                MAX_UNIQUES
+            ),
+        ));
+    }
+
+    optimized
+        .uniques
+        .iter()
+        .map(|note| {
+            PackedNote::pack(note)
+                .map(|packed| packed.bits())
+                .map_err(|e| {
+                    syn::Error::new(var_name.span(), format!("can't pack {:?}: {}", note, e))
+                })
+        })
+        .collect()
+}
+
+/// `static` item for one voice of a song, with bit-packed notes,
+/// kept in program memory if `progmem`
+fn packed_static(var_name: &syn::Ident, abc: &ABC, progmem: bool) -> syn::Result<TokenStream> {
+    let optimized = Optimized::from(abc);
+    let uniques = packed_uniques(var_name, &optimized, "PackedStatic")?;
+    let list = optimized.list.iter().map(|&index| index as u8);
+
+    let uniques_len = uniques.len();
+    let list_len = optimized.list.len();
+
+    if progmem {
+        return Ok(quote::quote! {
+            static #var_name: ard_r_sound_base::packed::PackedProgMem<#uniques_len, #list_len> = {
+                use ard_r_sound_base::packed::{PackedProgMem, PackedNote};
+                use ard_r_sound_base::progmem::ProgMem;
+
+                #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+                static UNIQUES: ProgMem<[PackedNote; #uniques_len]> = unsafe {
+                    ProgMem::new([
+                        #(PackedNote::from_bits(#uniques)),*
+                    ])
+                };
+                #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+                static LIST: ProgMem<[u8; #list_len]> = unsafe {
+                    ProgMem::new([
+                        #(#list),*
+                    ])
+                };
+
+                PackedProgMem {
+                    uniques: &UNIQUES,
+                    list: &LIST,
+                }
+            };
+        });
+    }
+
+    Ok(quote::quote! {
+        static #var_name: ard_r_sound_base::packed::PackedStatic<#uniques_len, #list_len> = {
+            use ard_r_sound_base::packed::{PackedStatic, PackedNote};
+
+            PackedStatic {
+                uniques: [
+                    #(PackedNote::from_bits(#uniques)),*
+                ],
+                list: [
+                    #(#list),*
+                ]
+            }
+        };
+    })
+}
+
+/// `static` item for one voice of a song, with bit-packed notes and repeated
+/// phrases compressed, kept in program memory if `progmem`
+fn phrases_static(var_name: &syn::Ident, abc: &ABC, progmem: bool) -> syn::Result<TokenStream> {
+    let optimized = Optimized::from(abc);
+    let uniques = packed_uniques(var_name, &optimized, "PhrasesStatic")?;
+    let phrases = Phrases::try_from(&optimized)
+        .map_err(|e| syn::Error::new(var_name.span(), format!("can't compress phrases: {}", e)))?;
+    let rules = phrases.rule_bytes();
+    let list = &phrases.list;
+
+    let uniques_len = uniques.len();
+    let rules_len = rules.len();
+    let list_len = list.len();
+    let len = optimized.list.len();
+
+    if progmem {
+        return Ok(quote::quote! {
+            static #var_name: ard_r_sound_base::phrases::PhrasesProgMem<#uniques_len, #rules_len, #list_len> = {
+                use ard_r_sound_base::packed::PackedNote;
+                use ard_r_sound_base::phrases::PhrasesProgMem;
+                use ard_r_sound_base::progmem::ProgMem;
+
+                #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+                static UNIQUES: ProgMem<[PackedNote; #uniques_len]> = unsafe {
+                    ProgMem::new([
+                        #(PackedNote::from_bits(#uniques)),*
+                    ])
+                };
+                #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+                static RULES: ProgMem<[u8; #rules_len]> = unsafe {
+                    ProgMem::new([
+                        #(#rules),*
+                    ])
+                };
+                #[cfg_attr(target_arch = "avr", link_section = ".progmem.data")]
+                static LIST: ProgMem<[u8; #list_len]> = unsafe {
+                    ProgMem::new([
+                        #(#list),*
+                    ])
+                };
 
-    let output = quoted;
+                PhrasesProgMem {
+                    uniques: &UNIQUES,
+                    rules: &RULES,
+                    list: &LIST,
+                    len: #len,
+                }
+            };
+        });
+    }
+
+    Ok(quote::quote! {
+        static #var_name: ard_r_sound_base::phrases::PhrasesStatic<#uniques_len, #rules_len, #list_len> = {
+            use ard_r_sound_base::packed::PackedNote;
+            use ard_r_sound_base::phrases::PhrasesStatic;
+
+            PhrasesStatic {
+                uniques: [
+                    #(PackedNote::from_bits(#uniques)),*
+                ],
+                rules: [
+                    #(#rules),*
+                ],
+                list: [
+                    #(#list),*
+                ],
+                len: #len,
+            }
+        };
+    })
+}
+
+/// `static` items for every voice of `abc`: `NAME` for the first voice,
+/// `NAME_VOICE_<n>` for every further voice `n`, and `NAME_VOICES` listing all of them.
+fn song_statics(var_name: &syn::Ident, abc: &ABC, encoding: Encoding) -> syn::Result<TokenStream> {
+    let mut names = Vec::new();
+    let mut statics = Vec::new();
+    for index in 0..abc.voice_count() {
+        let name = match index {
+            0 => var_name.clone(),
+            _ => quote::format_ident!("{}_VOICE_{}", var_name, index),
+        };
+        let voice = abc.voice(index).unwrap();
+        statics.push(match encoding {
+            Encoding::Optimized => optimized_static(&name, &voice),
+            Encoding::Packed => packed_static(&name, &voice, false)?,
+            Encoding::PackedProgMem => packed_static(&name, &voice, true)?,
+            Encoding::Phrases => phrases_static(&name, &voice, false)?,
+            Encoding::PhrasesProgMem => phrases_static(&name, &voice, true)?,
+        });
+        names.push(name);
+    }
+
+    let voices_name = quote::format_ident!("{}_VOICES", var_name);
+    let voice_count = names.len();
+
+    Ok(quote::quote! {
+        #(#statics)*
+
+        /// Every voice of the song, to be played at the same time
+        #[allow(dead_code)]
+        static #voices_name: [&(dyn ard_r_sound_base::Song + Sync); #voice_count] = [
+            #(&#names),*
+        ];
+    })
+}
+
+/// Generates `static NAME: OptimizedStatic<..>` with the first voice of the file.
+/// Every further voice `n` gets its own `static NAME_VOICE_<n>`,
+/// and `static NAME_VOICES` lists all of them, starting with `NAME`.
+/// `static_from_file!{NAME: PackedStatic, file}` generates `PackedStatic`s instead,
+/// which take a fraction of the flash, and `static_from_file!{NAME: PackedProgMem, file}`
+/// keeps them in program memory so that they don't take up SRAM.
+/// `PhrasesStatic` and `PhrasesProgMem` also compress repeated phrases,
+/// see `ard_r_sound_base::phrases`.
+#[proc_macro]
+pub fn static_from_file(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let args = syn::parse_macro_input!(input as Args);
+
+    // println!("file: {:?}", args.filename);
+
+    let output = parse_abc_file(std::path::Path::new(&args.filename))
+        .map_err(|e| syn::Error::new_spanned(&args.path, format!("{}: {}", args.filename, e)))
+        .and_then(|abc| song_statics(&args.var_name, &abc, args.encoding))
+        .unwrap_or_else(syn::Error::into_compile_error);
 
     // println!("output: {:?}", output.to_string());
 
     proc_macro::TokenStream::from(output)
 }
+
+/// Generates the same statics as `static_from_file!`, from a file at a path
+/// relative to the crate's `Cargo.toml`, with options:
+///
+/// ```ignore
+/// include_song!(SONG, "songs/mary.abc", tempo = 120, transpose = -2, tune = 0,
+///               encoding = packed, storage = progmem);
+/// ```
+///
+/// - `tempo`: percent of the written tempo, generated as `const NAME_TEMPO: u16` (100)
+/// - `transpose`: half steps to transpose every note by, down if negative (0)
+/// - `tune`: index of the tune in a file with several `X:` fields (0)
+/// - `encoding`: `optimized` for `OptimizedStatic`, `packed` for `PackedStatic`,
+///   `phrases` for `PhrasesStatic` (`optimized`)
+/// - `storage`: `ram` or `progmem`, which needs `encoding = packed` or `phrases` (`ram`)
+///
+/// The crate is rebuilt whenever the file changes.
+#[proc_macro]
+pub fn include_song(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let args = syn::parse_macro_input!(input as options::IncludeArgs);
+
+    let output = match include(&args) {
+        Ok(output) => output,
+        Err(e) => e.into_compile_error(),
+    };
+
+    proc_macro::TokenStream::from(output)
+}
+
+fn include(args: &options::IncludeArgs) -> syn::Result<TokenStream> {
+    let error = |message: String| syn::Error::new(args.path.span(), message);
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| error("`CARGO_MANIFEST_DIR` isn't set, is this built by cargo?".into()))?;
+    let path = std::path::Path::new(&manifest_dir).join(args.path.value());
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| error(format!("{} isn't valid UTF-8", path.display())))?;
+
+    let file =
+        std::fs::read_to_string(&path).map_err(|e| error(format!("{}: {}", path.display(), e)))?;
+
+    let tunes = parser::tunes(&file);
+    let tune = tunes.get(args.tune.value).ok_or_else(|| {
+        syn::Error::new(
+            args.tune.span,
+            format!("{} only holds {} tunes", path.display(), tunes.len()),
+        )
+    })?;
+
+    let mut abc =
+        parser::parse_abc(tune).map_err(|e| error(format!("{}: {}", path.display(), e)))?;
+    abc.transpose(args.transpose.value)
+        .map_err(|e| syn::Error::new(args.transpose.span, e))?;
+
+    let statics = song_statics(&args.var_name, &abc, args.encoding)?;
+    let tempo_name = quote::format_ident!("{}_TEMPO", args.var_name);
+    let tempo = args.tempo.value;
+
+    Ok(quote::quote! {
+        // rebuild whenever the file changes
+        const _: &[u8] = include_bytes!(#path_str);
+
+        #statics
+
+        /// Tempo to play the song at, in percent of its written tempo
+        #[allow(dead_code)]
+        const #tempo_name: u16 = #tempo;
+    })
+}
+
+/// Generates the same statics as `static_from_file!` from ABC notation written
+/// in a string literal, for jingles too short for a file of their own:
+///
+/// ```ignore
+/// abc!(BEEP, "L:1/16\nc2 z g");
+/// abc!(ERROR: PackedProgMem, "C,4 z C,4");
+/// ```
+///
+/// Invalid notation is a compile error. On nightly compilers it points at
+/// where the notation went wrong inside a literal without escapes
+/// (write the lines out instead of `\n`). Stable compilers can't point
+/// inside a literal, so there it always points at the whole literal.
+#[proc_macro]
+pub fn abc(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let args = syn::parse_macro_input!(input as InlineArgs);
+
+    let output = parser::parse_abc(&args.abc.value())
+        .map_err(|e| inline_error(&args.abc, &e))
+        .and_then(|abc| song_statics(&args.var_name, &abc, args.encoding));
+
+    proc_macro::TokenStream::from(output.unwrap_or_else(syn::Error::into_compile_error))
+}
+
+/// `error` from parsing the ABC notation in `literal`,
+/// pointing at where it went wrong inside the literal if possible
+fn inline_error(literal: &syn::LitStr, error: &anyhow::Error) -> syn::Error {
+    match parser::syntax_error(error) {
+        Some((range, message)) => {
+            let span = literal_subspan(literal, range).unwrap_or_else(|| literal.span());
+            syn::Error::new(span, format!("invalid ABC notation: {}", message))
+        }
+        None => syn::Error::new(literal.span(), error),
+    }
+}
+
+/// Span of the bytes at `range` of the value of `literal`.
+/// Only possible for literals without escapes, and on nightly compilers.
+fn literal_subspan(
+    literal: &syn::LitStr,
+    range: std::ops::Range<usize>,
+) -> Option<proc_macro2::Span> {
+    let value = literal.value();
+    let source = literal.token().to_string();
+
+    // the value starts after the `"` of `"..."`, `r"..."` or `r#"..."#`
+    let start = source.find('"')? + 1;
+    if source.get(start..start + value.len())? != value {
+        return None;
+    }
+
+    // an error at the end of the input points at its last byte
+    let end = range.end.min(value.len());
+    let range = range.start.min(end.saturating_sub(1))..end;
+    literal
+        .token()
+        .subspan(start + range.start..start + range.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line, first column and column after the end of `span`
+    fn location(span: proc_macro2::Span) -> (usize, usize, usize) {
+        (span.start().line, span.start().column, span.end().column)
+    }
+
+    fn error_location(source: &str) -> (usize, usize, usize) {
+        let literal: syn::LitStr = syn::parse_str(source).unwrap();
+        let error = parser::parse_abc(&literal.value()).unwrap_err();
+        location(inline_error(&literal, &error).span())
+    }
+
+    #[test]
+    fn errors_point_into_literals_without_escapes() {
+        // the `(` on the second line of a multi-line literal
+        assert_eq!(error_location("\"K:C\nc2 z G(\""), (2, 6, 7));
+        assert_eq!(error_location("r#\"K:C\nc2 z G(\"#"), (2, 6, 7));
+
+        // the whole literal, since `\n` isn't as long as the newline it stands for
+        assert_eq!(error_location("\"K:C\\nc2 z G(\""), (1, 0, 14));
+    }
+}
@@ -0,0 +1,139 @@
+use ard_r_sound_base::{Cursor, Length, Note, PitchClass, PitchOrRest, Song};
+use ard_r_sound_macros::{abc, include_song, static_from_file};
+
+include_song!(MARY, "../misc/example_abcs/mary.abc");
+include_song!(
+    SCALE_DOWN,
+    "tests/songs/two_tunes.abc",
+    tempo = 150,
+    transpose = -12
+);
+include_song!(
+    DUET,
+    "tests/songs/two_tunes.abc",
+    tune = 1,
+    encoding = packed
+);
+include_song!(
+    DUET_PROGMEM,
+    "tests/songs/two_tunes.abc",
+    tune = 1,
+    encoding = packed,
+    storage = progmem,
+);
+include_song!(
+    MARY_PHRASES,
+    "../misc/example_abcs/mary.abc",
+    encoding = phrases
+);
+include_song!(
+    DUET_PHRASES,
+    "tests/songs/two_tunes.abc",
+    tune = 1,
+    encoding = phrases,
+    storage = progmem
+);
+
+abc!(BEEP, "L:1/4\nK:C\nc2 z G");
+abc!(PACKED_BEEP: PackedStatic, "L:1/4\nK:C\nc2 z G");
+abc!(CANON, "L:1/4\nK:C\nV:1\nC D E|\nV:2\nz C D|\nV:3\nz z C|\n");
+
+// rustc runs from the workspace root, the path is relative to it
+static_from_file!(MARY_FROM_FILE: PackedProgMem, misc/example_abcs/mary.abc);
+
+fn notes(song: &dyn Song) -> Vec<Note> {
+    (0..song.len())
+        .map(|index| song.note(index).unwrap())
+        .collect()
+}
+
+/// Every note of `song`, the way `Player` plays them
+fn played(song: &dyn Song) -> Vec<Note> {
+    let mut cursor = Cursor::default();
+    std::iter::from_fn(|| song.next_note(&mut cursor)).collect()
+}
+
+fn pitch(note: &Note) -> (PitchClass, i8) {
+    match note.pitch {
+        PitchOrRest::Pitch { class, octave } => (class, octave),
+        PitchOrRest::Rest => panic!("{:?} is a rest", note),
+    }
+}
+
+#[test]
+fn include_song_defaults() {
+    assert_eq!(MARY.len(), 32);
+    assert_eq!(pitch(&MARY.note(0).unwrap()).0, PitchClass::E);
+    assert_eq!(MARY_TEMPO, 100);
+    assert_eq!(MARY_VOICES.len(), 1);
+    assert_eq!(notes(MARY_VOICES[0]), notes(&MARY));
+}
+
+#[test]
+fn include_song_tempo_and_transpose() {
+    assert_eq!(SCALE_DOWN_TEMPO, 150);
+    assert_eq!(SCALE_DOWN.len(), 8);
+
+    assert_eq!(pitch(&SCALE_DOWN.note(0).unwrap()), (PitchClass::C, -1));
+    assert_eq!(pitch(&SCALE_DOWN.note(7).unwrap()), (PitchClass::C, 0));
+}
+
+#[test]
+fn include_song_tune_and_voices() {
+    assert_eq!(DUET_VOICES.len(), 2);
+    assert_eq!(DUET.len(), 3);
+    assert_eq!(DUET_VOICE_1.len(), 2);
+    assert_eq!(DUET.note(0).unwrap().length, Length::Multiple(2));
+    assert_eq!(DUET_VOICE_1.note(0).unwrap().length, Length::Multiple(4));
+    assert_eq!(pitch(&DUET.note(0).unwrap()), (PitchClass::C, 1));
+    assert_eq!(pitch(&DUET_VOICE_1.note(1).unwrap()), (PitchClass::C, -1));
+}
+
+#[test]
+fn include_song_progmem_matches_packed() {
+    assert_eq!(DUET_PROGMEM_VOICES.len(), DUET_VOICES.len());
+    for (progmem, packed) in DUET_PROGMEM_VOICES.iter().zip(DUET_VOICES) {
+        assert_eq!(notes(*progmem), notes(packed));
+    }
+}
+
+#[test]
+fn include_song_phrases_round_trip() {
+    assert_eq!(MARY_PHRASES.len(), MARY.len());
+    assert!(MARY_PHRASES.list.len() + MARY_PHRASES.rules.len() < MARY.len());
+    assert_eq!(notes(&MARY_PHRASES), notes(&MARY));
+    assert_eq!(played(&MARY_PHRASES), notes(&MARY));
+
+    assert_eq!(DUET_PHRASES_VOICES.len(), DUET_VOICES.len());
+    for (phrases, packed) in DUET_PHRASES_VOICES.iter().zip(DUET_VOICES) {
+        assert_eq!(played(*phrases), notes(packed));
+    }
+}
+
+#[test]
+fn abc_inline() {
+    assert_eq!(BEEP.len(), 3);
+    assert_eq!(BEEP.note(1).unwrap().pitch, PitchOrRest::Rest);
+    assert_eq!(notes(&PACKED_BEEP), notes(&BEEP));
+    assert_eq!(BEEP_VOICES.len(), 1);
+}
+
+#[test]
+fn abc_voices() {
+    assert_eq!(CANON_VOICES.len(), 3);
+    assert_eq!(notes(CANON_VOICES[0]), notes(&CANON));
+    assert_eq!(notes(CANON_VOICES[1]), notes(&CANON_VOICE_1));
+    assert_eq!(notes(CANON_VOICES[2]), notes(&CANON_VOICE_2));
+    assert_eq!(CANON_VOICE_2.note(2).unwrap(), CANON.note(0).unwrap());
+}
+
+#[test]
+fn static_from_file_matches_include_song() {
+    assert_eq!(notes(&MARY_FROM_FILE), notes(&MARY));
+}
+
+#[test]
+fn compile_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
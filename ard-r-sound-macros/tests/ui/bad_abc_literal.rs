@@ -0,0 +1,5 @@
+use ard_r_sound_macros::abc;
+
+abc!(BEEP, "K:C\nc2 z G(");
+
+fn main() {}
@@ -0,0 +1,5 @@
+use ard_r_sound_macros::static_from_file;
+
+static_from_file!(SONG, no/such/song.abc);
+
+fn main() {}
@@ -0,0 +1,5 @@
+use ard_r_sound_macros::include_song;
+
+include_song!(SONG, "../misc/example_abcs/mary.abc", speed = 120);
+
+fn main() {}
@@ -0,0 +1,192 @@
+//! `ToneOutput` and `Timebase` with TIMER1 of the ATmega328P, for any board
+//! built around it (Uno, Nano, Pro Mini, ...).
+//!
+//! Both take ownership of TIMER1 from `avr-device`, so they can only be used
+//! one at a time: a board playing with `Timer1Tone` needs another timer for
+//! its `Timebase` and vice versa.
+
+use core::cell::Cell;
+
+use ard_r_sound_base::timer_duration::{Prescaler, TimerDuration, TimerError};
+use avr_device::{atmega328p::TC1, interrupt::Mutex};
+
+use crate::{Timebase, ToneOutput};
+
+/// TIMER1 compare value for a 1 kHz tick: 16 MHz / 64 / (249 + 1) = 1 kHz
+const TICK_COMPARE: u16 = 249;
+
+/// The output compare pins of TIMER1 that a `Timer1Tone` toggles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TonePins {
+    /// OC1A, D9 on the Uno
+    A,
+    /// OC1B, D10 on the Uno
+    B,
+    /// OC1A and OC1B in antiphase, for a buzzer wired between the two pins
+    /// (twice the voltage swing).
+    Both,
+}
+
+/// Plays voice 0 by toggling its pins on every compare match of TIMER1,
+/// with a 50% duty cycle and without any interrupts.
+/// Any other voice is ignored.
+pub struct Timer1Tone {
+    clock: TC1,
+    pins: TonePins,
+}
+
+impl Timer1Tone {
+    /// Play on `pins`, silent until the first note.
+    /// The pins must already be outputs, e.g. `pins.d9.into_output()`
+    /// with `arduino-hal`, and are low while silent.
+    pub fn new(clock: TC1, pins: TonePins) -> Self {
+        let mut tone = Self { clock, pins };
+        tone.silence(0);
+
+        // CTC (clear timer on compare match) mode, counting up to OCR1A
+        // WGM10 WGM11 to both 0
+        tone.clock.tccr1a.write(|w| w.wgm1().bits(0b00));
+        // WGM12 WGM13 to 1, 0
+        tone.clock.tccr1b.write(|w| w.wgm1().bits(0b01));
+        // the pins are toggled by the clock, no interrupt needed
+        tone.clock.timsk1.write(|w| w.ocie1a().clear_bit());
+
+        // OC1A and OC1B toggle on the same compare match, so forcing
+        // one of them once keeps them in antiphase from then on.
+        // forcing only acts on connected pins, disconnecting keeps the state.
+        if pins == TonePins::Both {
+            tone.connect(true);
+            tone.clock.tccr1c.write(|w| w.foc1b().set_bit());
+            tone.connect(false);
+        }
+
+        tone
+    }
+
+    /// Silence the pins and give TIMER1 back.
+    pub fn release(mut self) -> TC1 {
+        self.silence(0);
+        self.clock
+    }
+
+    /// Connect or disconnect the pins from the clock.
+    /// Disconnected pins go back to their (low) port value.
+    fn connect(&self, connect: bool) {
+        // toggle on compare match is COM1x0 = 1, COM1x1 = 0
+        let (a, b) = match (connect, self.pins) {
+            (false, _) => (0b00, 0b00),
+            (true, TonePins::A) => (0b01, 0b00),
+            (true, TonePins::B) => (0b00, 0b01),
+            (true, TonePins::Both) => (0b01, 0b01),
+        };
+
+        self.clock
+            .tccr1a
+            .write(|w| w.wgm1().bits(0b00).com1a().bits(a).com1b().bits(b));
+    }
+}
+
+impl ToneOutput for Timer1Tone {
+    type Error = TimerError;
+
+    fn set_frequency(
+        &mut self,
+        voice: usize,
+        frequency: f32,
+        _duty_cycle: f32,
+    ) -> Result<(), Self::Error> {
+        if voice != 0 {
+            return Ok(());
+        }
+
+        // each compare match is half a period
+        let half_period = TimerDuration::try_from_half_period(frequency)?;
+
+        // write zero to counter
+        self.clock.tcnt1.write(|w| w.bits(0));
+        self.clock
+            .ocr1a
+            .write(|w| w.bits(half_period.compare_value()));
+        // OC1B toggles when the counter passes OCR1B, which must not be
+        // above the top of the count (OCR1A) to toggle at all
+        self.clock
+            .ocr1b
+            .write(|w| w.bits(half_period.compare_value()));
+        start_clock(&self.clock, half_period.prescale());
+        self.connect(true);
+
+        Ok(())
+    }
+
+    fn silence(&mut self, voice: usize) {
+        if voice != 0 {
+            return;
+        }
+
+        // disable clock, keeping the waveform generation mode
+        self.clock.tccr1b.modify(|_, w| w.cs1().no_clock());
+        self.connect(false);
+        self.clock.tcnt1.write(|w| w.bits(0));
+    }
+}
+
+/// Milliseconds counted by `Timer1Timebase::tick()`
+static MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Counts milliseconds with the compare match interrupt of TIMER1.
+/// The application's `TIMER1_COMPA` interrupt must call `tick()`:
+///
+/// ```ignore
+/// #[avr_device::interrupt(atmega328p)]
+/// fn TIMER1_COMPA() {
+///     Timer1Timebase::tick();
+/// }
+/// ```
+pub struct Timer1Timebase {
+    _clock: TC1,
+}
+
+impl Timer1Timebase {
+    /// Interrupt once per millisecond from now on, interrupts must be
+    /// enabled for the count to run.
+    pub fn start(clock: TC1) -> Self {
+        // CTC mode, see `Timer1Tone::new()`
+        clock.tccr1a.write(|w| w.wgm1().bits(0b00));
+        clock.tccr1b.write(|w| w.wgm1().bits(0b01));
+        clock.tcnt1.write(|w| w.bits(0));
+        clock.ocr1a.write(|w| w.bits(TICK_COMPARE));
+        // don't count a compare match from before
+        clock.tifr1.write(|w| w.ocf1a().set_bit());
+        clock.timsk1.write(|w| w.ocie1a().set_bit());
+        start_clock(&clock, Prescaler::Prescale64);
+
+        Self { _clock: clock }
+    }
+
+    /// Count a millisecond, call from the `TIMER1_COMPA` interrupt.
+    pub fn tick() {
+        avr_device::interrupt::free(|cs| {
+            let millis = MILLIS.borrow(cs);
+            millis.set(millis.get().wrapping_add(1));
+        });
+    }
+}
+
+impl Timebase for Timer1Timebase {
+    fn millis(&self) -> u32 {
+        avr_device::interrupt::free(|cs| MILLIS.borrow(cs).get())
+    }
+}
+
+/// Run `clock` with `prescale`, keeping the waveform generation mode.
+fn start_clock(clock: &TC1, prescale: Prescaler) {
+    clock.tccr1b.modify(|_, w| match prescale {
+        Prescaler::Direct => w.cs1().direct(),
+        Prescaler::Prescale8 => w.cs1().prescale_8(),
+        Prescaler::Prescale64 => w.cs1().prescale_64(),
+        Prescaler::Prescale256 => w.cs1().prescale_256(),
+        Prescaler::Prescale1024 => w.cs1().prescale_1024(),
+        // only TIMER2 has these, they are never picked for TIMER1
+        Prescaler::Prescale32 | Prescaler::Prescale128 => w.cs1().no_clock(),
+    });
+}
@@ -0,0 +1,113 @@
+//! Song playback for any board.
+//!
+//! `Player` steps through every voice of a song, and only talks to the
+//! hardware through two traits: a `ToneOutput` plays a frequency on each
+//! buzzer, and a `Timebase` tells the time.
+//! `atmega328p` implements them with TIMER1 of the ATmega328P through `avr-device`,
+//! and `mock` implements them on the host, so playback can be unit tested.
+#![no_std]
+
+use ard_r_sound_base::{Note, PitchOrRest};
+
+#[cfg(feature = "atmega328p")]
+pub mod atmega328p;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod player;
+
+pub use player::{Player, Progress};
+
+/// Buzzers that play one frequency at a time each, one for every voice of a song
+pub trait ToneOutput {
+    /// Why a frequency can't be played, e.g. `TimerError`
+    type Error;
+
+    /// Play `frequency` in Hz on the buzzer of `voice` until it is changed,
+    /// driven high for `duty_cycle` of each period if the output can
+    /// (see `Dynamic::duty_cycle()`).
+    /// Voices without a buzzer are ignored.
+    fn set_frequency(
+        &mut self,
+        voice: usize,
+        frequency: f32,
+        duty_cycle: f32,
+    ) -> Result<(), Self::Error>;
+
+    /// Silence the buzzer of `voice`
+    fn silence(&mut self, voice: usize);
+}
+
+/// A clock counting milliseconds
+pub trait Timebase {
+    /// Milliseconds since the clock started, wrapping around
+    fn millis(&self) -> u32;
+}
+
+/// The frequency of `note` in Hz, `None` for a rest
+pub fn frequency(note: &Note) -> Option<f32> {
+    const MIDDLE_A_FREQUENCY: f32 = 440.0;
+    // 2^(n/12) for each half step of an octave, `powf` isn't in `core`
+    const HALF_STEP_RATIOS: [f32; 12] = [
+        1.0,
+        1.059_463_1,
+        1.122_462,
+        1.189_207_1,
+        1.259_921,
+        1.334_839_8,
+        core::f32::consts::SQRT_2,
+        1.498_307_1,
+        1.587_401,
+        1.681_792_9,
+        1.781_797_4,
+        1.887_748_6,
+    ];
+
+    match note.pitch {
+        PitchOrRest::Pitch { class, octave } => {
+            let half_steps = octave as i32 * 12 + class.half_steps_from_a() as i32;
+
+            let mut frequency =
+                MIDDLE_A_FREQUENCY * HALF_STEP_RATIOS[half_steps.rem_euclid(12) as usize];
+            let octaves = half_steps.div_euclid(12);
+            for _ in 0..octaves.unsigned_abs() {
+                match octaves > 0 {
+                    true => frequency *= 2.,
+                    false => frequency /= 2.,
+                }
+            }
+
+            Some(frequency)
+        }
+        PitchOrRest::Rest => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ard_r_sound_base::{Dynamic, Length, PitchClass};
+
+    #[test]
+    fn frequencies() {
+        let note = |class, octave| Note {
+            pitch: PitchOrRest::Pitch { class, octave },
+            length: Length::Unit,
+            dynamic: Dynamic::default(),
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+
+        assert_eq!(frequency(&note(PitchClass::A, 0)), Some(440.));
+        assert_eq!(frequency(&note(PitchClass::A, -2)), Some(110.));
+        assert!(close(frequency(&note(PitchClass::C, 0)).unwrap(), 523.25));
+        assert!(close(
+            frequency(&note(PitchClass::GSharpAFlat, -1)).unwrap(),
+            415.30
+        ));
+
+        let rest = Note {
+            pitch: PitchOrRest::Rest,
+            ..note(PitchClass::A, 0)
+        };
+        assert_eq!(frequency(&rest), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! A `ToneOutput` and `Timebase` that only record what they are asked to do,
+//! to test playback on the host.
+
+use core::cell::Cell;
+
+use crate::{Timebase, ToneOutput};
+
+/// Most changes a `MockOutput` keeps track of
+pub const LOG_CAPACITY: usize = 64;
+
+/// A clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct MockTimebase {
+    millis: Cell<u32>,
+}
+
+impl MockTimebase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, millis: u32) {
+        self.millis.set(self.millis.get().wrapping_add(millis));
+    }
+}
+
+impl Timebase for MockTimebase {
+    fn millis(&self) -> u32 {
+        self.millis.get()
+    }
+}
+
+/// A frequency set or a buzzer silenced: when, on which voice and
+/// the new frequency, `None` if silenced
+pub type Change = (u32, usize, Option<f32>);
+
+/// `VOICES` buzzers, remembering what each one plays and every change,
+/// timed by `timebase`
+#[derive(Debug)]
+pub struct MockOutput<'a, const VOICES: usize> {
+    timebase: &'a MockTimebase,
+    frequencies: [Option<f32>; VOICES],
+    log: [Change; LOG_CAPACITY],
+    log_len: usize,
+}
+
+impl<'a, const VOICES: usize> MockOutput<'a, VOICES> {
+    /// Silent buzzers
+    pub fn new(timebase: &'a MockTimebase) -> Self {
+        Self {
+            timebase,
+            frequencies: [None; VOICES],
+            log: [(0, 0, None); LOG_CAPACITY],
+            log_len: 0,
+        }
+    }
+
+    /// What each buzzer plays right now
+    pub fn frequencies(&self) -> [Option<f32>; VOICES] {
+        self.frequencies
+    }
+
+    /// Every change so far, in order
+    pub fn log(&self) -> &[Change] {
+        &self.log[..self.log_len]
+    }
+
+    fn record(&mut self, voice: usize, frequency: Option<f32>) {
+        assert!(
+            self.log_len < LOG_CAPACITY,
+            "more than {} changes",
+            LOG_CAPACITY
+        );
+        self.log[self.log_len] = (self.timebase.millis(), voice, frequency);
+        self.log_len += 1;
+
+        if let Some(current) = self.frequencies.get_mut(voice) {
+            *current = frequency;
+        }
+    }
+}
+
+/// Frequencies a buzzer can't play
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidFrequency;
+
+impl<const VOICES: usize> ToneOutput for MockOutput<'_, VOICES> {
+    type Error = InvalidFrequency;
+
+    /// Only positive frequencies can be played, like on a timer
+    fn set_frequency(
+        &mut self,
+        voice: usize,
+        frequency: f32,
+        _duty_cycle: f32,
+    ) -> Result<(), Self::Error> {
+        if !(frequency > 0. && frequency.is_finite()) {
+            return Err(InvalidFrequency);
+        }
+
+        self.record(voice, Some(frequency));
+        Ok(())
+    }
+
+    fn silence(&mut self, voice: usize) {
+        self.record(voice, None);
+    }
+}
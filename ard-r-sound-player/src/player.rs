@@ -0,0 +1,406 @@
+//! Stepping through the notes of every voice of a song.
+
+use ard_r_sound_base::{Cursor, Length, Note, Song};
+
+use crate::{frequency, Timebase, ToneOutput};
+
+/// Length of a `Length::Unit` note in microseconds (one beat at 60 BPM).
+const UNIT_MICROS: u32 = 1_000_000;
+
+/// One voice of the song, played on its own buzzer.
+struct Track {
+    song: &'static (dyn Song + Sync),
+    /// the next note to start
+    cursor: Cursor,
+    /// when the current note ends, in microseconds since the song (re)started.
+    /// kept in microseconds so that rounding to milliseconds never accumulates.
+    song_micros: u64,
+    /// `millis` at which the next note starts
+    next_note_millis: u32,
+    finished: bool,
+}
+
+/// Plays up to `VOICES` voices of a song at the same time, voice `n` on
+/// buzzer `n` of a `ToneOutput`. All voices count from the same start,
+/// so they stay in sync.
+///
+/// `poll()` starts the notes that are due, it should be called about
+/// once per millisecond, e.g. from a timer interrupt.
+pub struct Player<const VOICES: usize> {
+    tracks: [Option<Track>; VOICES],
+    /// start the song over once every voice is done
+    repeat: bool,
+    /// notes don't advance while paused
+    paused: bool,
+    /// percent of the song's tempo, applies from the next note on
+    tempo_percent: u16,
+    /// milliseconds of playback since the player was started, not counting pauses
+    millis: u32,
+    /// `Timebase::millis()` at the last `poll()`
+    last_poll: Option<u32>,
+    /// `millis` at which the song (re)started
+    song_start_millis: u32,
+}
+
+impl<const VOICES: usize> Player<VOICES> {
+    /// Play `voices`, the first notes start on the first `poll()`.
+    /// Voices beyond `VOICES` are not played.
+    /// Every buzzer starts silent, including those without a voice.
+    pub fn start(
+        voices: &[&'static (dyn Song + Sync)],
+        repeat: bool,
+        output: &mut impl ToneOutput,
+    ) -> Self {
+        let mut tracks: [Option<Track>; VOICES] = core::array::from_fn(|_| None);
+        for (track, song) in tracks.iter_mut().zip(voices) {
+            *track = Some(Track {
+                song: *song,
+                cursor: Cursor::default(),
+                song_micros: 0,
+                next_note_millis: 0,
+                finished: song.is_empty(),
+            });
+        }
+
+        for voice in 0..VOICES {
+            output.silence(voice);
+        }
+
+        Self {
+            tracks,
+            repeat,
+            paused: false,
+            tempo_percent: 100,
+            millis: 0,
+            last_poll: None,
+            song_start_millis: 0,
+        }
+    }
+
+    /// Start the next note of each voice whose current one is over.
+    pub fn poll(&mut self, output: &mut impl ToneOutput, timebase: &impl Timebase) {
+        let now = timebase.millis();
+        let elapsed = match self.last_poll {
+            Some(last_poll) => now.wrapping_sub(last_poll),
+            None => 0,
+        };
+        self.last_poll = Some(now);
+
+        if self.paused {
+            return;
+        }
+        self.millis = self.millis.wrapping_add(elapsed);
+
+        for voice in 0..VOICES {
+            let due = match &self.tracks[voice] {
+                Some(track) => {
+                    !track.finished
+                        && self.millis.wrapping_sub(track.next_note_millis) < u32::MAX / 2
+                }
+                None => false,
+            };
+
+            if due {
+                self.start_next_note(voice, output);
+            }
+        }
+
+        if self.repeat && self.is_finished() {
+            self.restart(output);
+        }
+    }
+
+    /// Start playing the next note of `voice` and schedule the one after it.
+    fn start_next_note(&mut self, voice: usize, output: &mut impl ToneOutput) {
+        let song_start_millis = self.song_start_millis;
+        let tempo_percent = self.tempo_percent;
+        let track = match &mut self.tracks[voice] {
+            Some(track) => track,
+            None => return,
+        };
+
+        let note = match track.song.next_note(&mut track.cursor) {
+            Some(note) => note,
+            None => {
+                output.silence(voice);
+                track.finished = true;
+                return;
+            }
+        };
+
+        play_note(output, voice, &note);
+
+        track.song_micros += note_micros(&note) as u64 * 100 / tempo_percent as u64;
+        track.next_note_millis = song_start_millis.wrapping_add((track.song_micros / 1000) as u32);
+    }
+
+    /// Silence every buzzer while paused, and play the interrupted notes again on resume.
+    pub fn set_paused(&mut self, output: &mut impl ToneOutput, paused: bool) {
+        self.paused = paused;
+
+        for voice in 0..VOICES {
+            let note = match &self.tracks[voice] {
+                Some(track) if !paused && !track.finished => {
+                    let index = track.cursor.index().checked_sub(1);
+                    index.and_then(|i| track.song.note(i))
+                }
+                _ => None,
+            };
+
+            match note {
+                Some(note) => play_note(output, voice, &note),
+                None => output.silence(voice),
+            }
+        }
+    }
+
+    /// Play at `percent` of the song's tempo, from the next note on.
+    pub fn set_tempo(&mut self, percent: u16) {
+        self.tempo_percent = percent.max(1);
+    }
+
+    pub fn tempo(&self) -> u16 {
+        self.tempo_percent
+    }
+
+    /// Have all voices played their last note?
+    pub fn is_finished(&self) -> bool {
+        self.tracks.iter().flatten().all(|track| track.finished)
+    }
+
+    /// Stop playing and silence every buzzer.
+    pub fn stop(self, output: &mut impl ToneOutput) {
+        for voice in 0..VOICES {
+            output.silence(voice);
+        }
+    }
+
+    /// Start every voice over from its first note.
+    fn restart(&mut self, output: &mut impl ToneOutput) {
+        self.song_start_millis = self.millis;
+
+        for voice in 0..VOICES {
+            if let Some(track) = &mut self.tracks[voice] {
+                track.cursor = Cursor::default();
+                track.song_micros = 0;
+                track.next_note_millis = self.millis;
+                // an empty voice would otherwise restart the song every poll
+                track.finished = track.song.is_empty();
+            }
+
+            self.start_next_note(voice, output);
+        }
+    }
+
+    /// Progress of the first voice
+    pub fn progress(&self) -> Progress {
+        match &self.tracks[..] {
+            [Some(track), ..] => Progress {
+                playing: !self.is_finished() && !self.paused,
+                paused: self.paused,
+                index: track.cursor.index(),
+                len: track.song.len(),
+            },
+            _ => Progress::default(),
+        }
+    }
+}
+
+/// Progress of the first voice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// there are notes left to play, and playback isn't paused
+    pub playing: bool,
+    pub paused: bool,
+    /// index of the next note
+    pub index: usize,
+    pub len: usize,
+}
+
+/// Play `note` on the buzzer of `voice` until the next call.
+fn play_note(output: &mut impl ToneOutput, voice: usize, note: &Note) {
+    match frequency(note) {
+        // a note we can't generate is played as a rest rather than stopping the song
+        Some(freq) => {
+            if output
+                .set_frequency(voice, freq, note.dynamic.duty_cycle())
+                .is_err()
+            {
+                output.silence(voice);
+            }
+        }
+        None => output.silence(voice),
+    }
+}
+
+/// How long a note lasts, in microseconds.
+fn note_micros(note: &Note) -> u32 {
+    match note.length {
+        Length::Unit => UNIT_MICROS,
+        Length::Multiple(m) => UNIT_MICROS.saturating_mul(m),
+        Length::Division(d) => UNIT_MICROS / d.max(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockOutput, MockTimebase};
+    use ard_r_sound_base::packed::PackedNote;
+    use ard_r_sound_base::phrases::PhrasesStatic;
+    use ard_r_sound_base::{Dynamic, OptimizedStatic, PitchClass, PitchOrRest};
+
+    const fn note(pitch: PitchOrRest, length: Length) -> Note {
+        Note {
+            pitch,
+            length,
+            dynamic: Dynamic::MezzoForte,
+        }
+    }
+
+    const A: PitchOrRest = PitchOrRest::Pitch {
+        class: PitchClass::A,
+        octave: 0,
+    };
+
+    /// A for a beat, a rest for half a beat and A for two beats
+    const MELODY_NOTES: [Note; 3] = [
+        note(A, Length::Unit),
+        note(PitchOrRest::Rest, Length::Division(2)),
+        note(A, Length::Multiple(2)),
+    ];
+
+    static MELODY: OptimizedStatic<3, 3> = OptimizedStatic {
+        uniques: MELODY_NOTES,
+        list: [0, 1, 2],
+    };
+
+    /// A low A for three beats
+    static BASS: OptimizedStatic<1, 1> = OptimizedStatic {
+        uniques: [note(
+            PitchOrRest::Pitch {
+                class: PitchClass::A,
+                octave: -1,
+            },
+            Length::Multiple(3),
+        )],
+        list: [0],
+    };
+
+    /// `MELODY` twice: rule 3 is its first two notes, rule 4 all three
+    static MELODY_TWICE: PhrasesStatic<3, 4, 2> = PhrasesStatic {
+        uniques: [
+            PackedNote::from_bits(48 << 9 | 5),
+            PackedNote::from_bits(0x7F << 9 | 0x11 << 4 | 5),
+            PackedNote::from_bits(48 << 9 | 1 << 4 | 5),
+        ],
+        rules: [0, 1, 3, 2],
+        list: [4, 4],
+        len: 6,
+    };
+
+    /// `MELODY` twice, uncompressed
+    static MELODY_TWICE_UNCOMPRESSED: OptimizedStatic<3, 6> = OptimizedStatic {
+        uniques: MELODY_NOTES,
+        list: [0, 1, 2, 0, 1, 2],
+    };
+
+    /// Poll once per millisecond for `millis`
+    fn run<const VOICES: usize>(
+        player: &mut Player<VOICES>,
+        output: &mut MockOutput<'_, VOICES>,
+        timebase: &MockTimebase,
+        millis: u32,
+    ) {
+        for _ in 0..millis {
+            player.poll(output, timebase);
+            timebase.advance(1);
+        }
+    }
+
+    #[test]
+    fn voices_play_in_sync() {
+        let timebase = MockTimebase::new();
+        let mut output = MockOutput::<2>::new(&timebase);
+        let mut player = Player::start(&[&MELODY, &BASS], false, &mut output);
+
+        run(&mut player, &mut output, &timebase, 1);
+        assert_eq!(output.frequencies(), [Some(440.), Some(220.)]);
+
+        run(&mut player, &mut output, &timebase, 1000);
+        assert_eq!(output.frequencies(), [None, Some(220.)]);
+        assert_eq!(player.progress().index, 2);
+
+        run(&mut player, &mut output, &timebase, 500);
+        assert_eq!(output.frequencies(), [Some(440.), Some(220.)]);
+
+        run(&mut player, &mut output, &timebase, 2000);
+        assert_eq!(output.frequencies(), [None, None]);
+        assert!(player.is_finished());
+        assert!(!player.progress().playing);
+
+        // every note change, with the time it happened
+        assert_eq!(
+            output.log(),
+            [
+                (0, 0, None),
+                (0, 1, None),
+                (0, 0, Some(440.)),
+                (0, 1, Some(220.)),
+                (1000, 0, None),
+                (1500, 0, Some(440.)),
+                (3000, 1, None),
+                (3500, 0, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn pause_tempo_and_repeat() {
+        let timebase = MockTimebase::new();
+        let mut output = MockOutput::<1>::new(&timebase);
+        let mut player = Player::start(&[&MELODY], true, &mut output);
+        player.set_tempo(200);
+
+        run(&mut player, &mut output, &timebase, 1);
+        player.set_paused(&mut output, true);
+        assert_eq!(output.frequencies(), [None]);
+
+        // doesn't advance while paused, however late the next poll is
+        timebase.advance(10_000);
+        run(&mut player, &mut output, &timebase, 1);
+        assert_eq!(player.progress().index, 1);
+
+        // the interrupted note plays again, and ends half a beat in
+        player.set_paused(&mut output, false);
+        assert_eq!(output.frequencies(), [Some(440.)]);
+        run(&mut player, &mut output, &timebase, 500);
+        assert_eq!(player.progress().index, 2);
+
+        // the whole song takes 1750 ms at double tempo, then starts over
+        run(&mut player, &mut output, &timebase, 1250);
+        assert_eq!(player.progress().index, 1);
+        assert_eq!(output.frequencies(), [Some(440.)]);
+    }
+
+    #[test]
+    fn phrases_play_like_uncompressed() {
+        assert_eq!(
+            MELODY_TWICE.uniques.map(|note| note.unpack()),
+            MELODY_NOTES.map(Ok)
+        );
+
+        let log = |song: &'static (dyn Song + Sync)| {
+            let timebase = MockTimebase::new();
+            let mut output = MockOutput::<1>::new(&timebase);
+            let mut player = Player::start(&[song], false, &mut output);
+            run(&mut player, &mut output, &timebase, 8000);
+            assert!(player.is_finished());
+            output.log().to_vec()
+        };
+
+        let played = log(&MELODY_TWICE);
+        assert_eq!(played.len(), 8);
+        assert_eq!(played, log(&MELODY_TWICE_UNCOMPRESSED));
+    }
+}
@@ -1,9 +1,19 @@
 use std::path::PathBuf;
 
+use ard_r_sound_lib::{codegen, export, player, protocol};
+
 #[derive(clap::Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    #[arg(id = "input", help = "Input ABC file path")]
-    input_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+
+    #[arg(
+        id = "input",
+        required_unless_present = "list-devices",
+        help = "Input ABC file path"
+    )]
+    input_file: Option<PathBuf>,
 
     #[arg(short = 'o', long = "output", id = "output", help = "Output file path")]
     output_file: Option<PathBuf>,
@@ -11,8 +21,225 @@ pub struct Args {
     #[arg(value_enum, short = 'f', help = "Output file format")]
     format: Option<FileFormat>,
 
+    #[arg(
+        long = "progmem",
+        help = "Keep the song in program memory (with `-f header` or `-f sketch`)"
+    )]
+    progmem: bool,
+
     #[arg(short = 'v', help = "Print verbose debug information")]
     verbose: bool,
+
+    #[arg(
+        short = 'i',
+        long = "interactive",
+        help = "Control playback from the terminal (with `-f play`)"
+    )]
+    interactive: bool,
+
+    #[arg(
+        long = "list-devices",
+        id = "list-devices",
+        help = "List audio hosts and output devices, then exit"
+    )]
+    list_devices: bool,
+
+    #[arg(
+        long = "host",
+        help = "Audio host to play on (`null` discards audio, e.g. for CI)"
+    )]
+    host: Option<String>,
+
+    #[arg(long = "device", help = "Output device name or index to play on")]
+    device: Option<String>,
+
+    #[arg(
+        long = "sample-rate",
+        default_value_t = 44_100,
+        value_parser = clap::value_parser!(u32).range(export::MIN_SAMPLE_RATE as i64..=export::MAX_SAMPLE_RATE as i64),
+        help = "Sample rate in Hz for raw and WAV output"
+    )]
+    sample_rate: u32,
+
+    #[arg(
+        value_enum,
+        long = "bit-depth",
+        default_value = "16",
+        help = "Sample format for raw and WAV output"
+    )]
+    bit_depth: BitDepth,
+
+    #[arg(
+        long = "channels",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of channels for raw and WAV output"
+    )]
+    channels: u16,
+
+    #[arg(
+        value_enum,
+        long = "endianness",
+        default_value = "little",
+        help = "Byte order for raw output"
+    )]
+    endianness: Endianness,
+
+    #[arg(
+        value_enum,
+        long = "signedness",
+        default_value = "signed",
+        help = "Integer signedness for raw output"
+    )]
+    signedness: Signedness,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Subcommand {
+    #[command(flatten)]
+    Serial(Command),
+    #[command(about = "Report how much repeated phrases compress each song")]
+    Stats {
+        #[arg(required = true, help = "Input ABC file paths")]
+        input_files: Vec<PathBuf>,
+    },
+}
+
+// Control an Arduino running the firmware over serial
+#[derive(clap::Subcommand)]
+pub enum Command {
+    #[command(about = "Upload a song to the Arduino and play it")]
+    Send {
+        #[arg(help = "Input ABC file path")]
+        input_file: PathBuf,
+
+        #[command(flatten)]
+        port: Port,
+
+        #[arg(
+            value_enum,
+            long = "target",
+            default_value = "ram",
+            help = "Where to store the song (EEPROM keeps it across resets, replacing the library)"
+        )]
+        target: Target,
+
+        #[arg(long = "repeat", help = "Start the song over once it ends")]
+        repeat: bool,
+
+        #[arg(long = "tempo", help = "Play at this percentage of the song's tempo")]
+        tempo: Option<u16>,
+
+        #[arg(long = "no-play", help = "Only upload the song")]
+        no_play: bool,
+    },
+    #[command(about = "Play a song stored on the Arduino")]
+    Play {
+        #[command(flatten)]
+        port: Port,
+
+        #[arg(
+            value_enum,
+            long = "source",
+            default_value = "flash",
+            help = "Which song to play"
+        )]
+        source: Source,
+
+        #[arg(
+            long = "song",
+            default_value_t = 0,
+            help = "Index of the song in the EEPROM library"
+        )]
+        song: u8,
+
+        #[arg(long = "repeat", help = "Start the song over once it ends")]
+        repeat: bool,
+    },
+    #[command(
+        about = "Build an EEPROM image holding several songs, and/or upload it",
+        group(clap::ArgGroup::new("destination").required(true).multiple(true).args(["output", "port"]))
+    )]
+    Library {
+        #[arg(required = true, help = "Input ABC file paths, in library order")]
+        input_files: Vec<PathBuf>,
+
+        #[arg(short = 'o', long = "output", help = "Output EEPROM image path")]
+        output: Option<PathBuf>,
+
+        #[arg(
+            long = "port",
+            help = "Serial port of an Arduino to upload the image to"
+        )]
+        port: Option<String>,
+
+        #[arg(
+            long = "default",
+            default_value_t = 0,
+            help = "Index of the song played at boot"
+        )]
+        default_song: usize,
+    },
+    #[command(about = "Stop playback on the Arduino")]
+    Stop {
+        #[command(flatten)]
+        port: Port,
+    },
+    #[command(about = "Change the tempo of playback on the Arduino")]
+    Tempo {
+        #[arg(
+            value_parser = clap::value_parser!(u16).range(1..),
+            help = "Percentage of the song's tempo"
+        )]
+        percent: u16,
+
+        #[command(flatten)]
+        port: Port,
+    },
+    #[command(about = "Print what the Arduino is playing")]
+    Status {
+        #[command(flatten)]
+        port: Port,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct Port {
+    #[arg(long = "port", help = "Serial port of the Arduino, e.g. /dev/ttyACM0")]
+    pub port: String,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum Target {
+    Ram,
+    Eeprom,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum Source {
+    #[value(help = "The song compiled into the firmware")]
+    Flash,
+    Ram,
+    Eeprom,
+}
+
+impl From<Target> for protocol::Storage {
+    fn from(target: Target) -> Self {
+        match target {
+            Target::Ram => protocol::Storage::Ram,
+            Target::Eeprom => protocol::Storage::Eeprom,
+        }
+    }
+}
+
+impl From<Source> for protocol::Storage {
+    fn from(source: Source) -> Self {
+        match source {
+            Source::Flash => protocol::Storage::Flash,
+            Source::Ram => protocol::Storage::Ram,
+            Source::Eeprom => protocol::Storage::Eeprom,
+        }
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, Copy)]
@@ -23,11 +250,43 @@ pub enum FileFormat {
     Wav,
     #[value(help = "Play to speakers")]
     Play,
-    #[value(help = "Generate C header for Arduino", )]
+    #[value(help = "Generate C header for Arduino")]
     Header,
+    #[value(help = "Generate Arduino IDE sketch folder playing the song")]
+    Sketch,
+    #[value(help = "Generate Rust module holding the song as an `OptimizedStatic`")]
+    Rust,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum BitDepth {
+    #[value(name = "8", help = "8-bit integer")]
+    Eight,
+    #[value(name = "16", help = "16-bit integer")]
+    Sixteen,
+    #[value(name = "24", help = "24-bit integer")]
+    TwentyFour,
+    #[value(name = "32f", help = "32-bit float")]
+    ThirtyTwoFloat,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum Signedness {
+    Signed,
+    Unsigned,
 }
 
 impl Args {
+    pub fn command(&self) -> Option<&Subcommand> {
+        self.command.as_ref()
+    }
+
     pub fn output_file(&self) -> Result<&std::path::Path, anyhow::Error> {
         match &self.output_file {
             Some(o) => Ok(o),
@@ -35,11 +294,62 @@ impl Args {
         }
     }
 
-    pub fn input_file(&self) -> PathBuf {
-        self.input_file.clone()
+    pub fn input_file(&self) -> Result<PathBuf, anyhow::Error> {
+        self.input_file
+            .clone()
+            .ok_or(anyhow::anyhow!("must specify input file"))
     }
 
     pub fn file_format(&self) -> Option<&FileFormat> {
         self.format.as_ref()
     }
+
+    /// Options for `-f header` and `-f sketch`, naming the song after the input file
+    pub fn header_options(&self) -> Result<codegen::HeaderOptions, anyhow::Error> {
+        Ok(codegen::HeaderOptions::named_after(
+            &self.input_file()?,
+            self.progmem,
+        ))
+    }
+
+    /// Name of the song's statics for `-f rust`, after the input file
+    pub fn static_name(&self) -> Result<String, anyhow::Error> {
+        Ok(self.header_options()?.name.to_uppercase())
+    }
+
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn list_devices(&self) -> bool {
+        self.list_devices
+    }
+
+    pub fn output_selection(&self) -> player::OutputSelection {
+        player::OutputSelection {
+            host: self.host.clone(),
+            device: self.device.clone(),
+        }
+    }
+
+    pub fn export_config(&self) -> export::ExportConfig {
+        export::ExportConfig {
+            sample_rate: self.sample_rate,
+            bit_depth: match self.bit_depth {
+                BitDepth::Eight => export::BitDepth::Eight,
+                BitDepth::Sixteen => export::BitDepth::Sixteen,
+                BitDepth::TwentyFour => export::BitDepth::TwentyFour,
+                BitDepth::ThirtyTwoFloat => export::BitDepth::ThirtyTwoFloat,
+            },
+            channels: self.channels,
+            endianness: match self.endianness {
+                Endianness::Little => export::Endianness::Little,
+                Endianness::Big => export::Endianness::Big,
+            },
+            signedness: match self.signedness {
+                Signedness::Signed => export::Signedness::Signed,
+                Signedness::Unsigned => export::Signedness::Unsigned,
+            },
+        }
+    }
 }
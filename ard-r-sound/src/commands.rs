@@ -0,0 +1,95 @@
+//! Subcommands that control an Arduino over serial
+
+use ard_r_sound_lib::library;
+use ard_r_sound_lib::protocol::Storage;
+use ard_r_sound_lib::remote::{Device, BOOT_DELAY};
+use ard_r_sound_lib::{codegen, parser};
+use tracing::warn;
+
+use crate::args::{Command, Target};
+
+pub fn run(command: &Command) -> Result<(), anyhow::Error> {
+    match command {
+        Command::Send {
+            input_file,
+            port,
+            target,
+            repeat,
+            tempo,
+            no_play,
+        } => {
+            let abc = parser::parse_abc_file(input_file)?;
+            if abc.voice_count() > 1 {
+                warn!("only the first voice is sent");
+            }
+            let song = match target {
+                Target::Ram => codegen::to_song_bytes(&abc)?,
+                // the EEPROM always holds a library, of one song here
+                Target::Eeprom => codegen::to_library(&[abc], 0)?,
+            };
+
+            let mut device = Device::open(&port.port, BOOT_DELAY)?;
+            device.upload((*target).into(), &song)?;
+            println!("uploaded {} bytes", song.len());
+
+            if let Some(percent) = tempo {
+                device.set_tempo(*percent)?;
+            }
+            if !no_play {
+                device.play((*target).into(), 0, *repeat)?;
+            }
+        }
+        Command::Play {
+            port,
+            source,
+            song,
+            repeat,
+        } => Device::open(&port.port, BOOT_DELAY)?.play((*source).into(), *song, *repeat)?,
+        Command::Library {
+            input_files,
+            output,
+            port,
+            default_song,
+        } => {
+            let abcs = input_files
+                .iter()
+                .map(|file| parser::parse_abc_file(file))
+                .collect::<Result<Vec<_>, _>>()?;
+            let image = codegen::to_library(&abcs, *default_song)?;
+            println!(
+                "library of {} songs is {} of {} bytes",
+                abcs.len(),
+                image.len(),
+                library::CAPACITY
+            );
+
+            if let Some(output) = output {
+                std::fs::write(output, &image)?;
+            }
+            if let Some(port) = port {
+                Device::open(port, BOOT_DELAY)?.upload(Storage::Eeprom, &image)?;
+            }
+        }
+        Command::Stop { port } => Device::open(&port.port, BOOT_DELAY)?.stop()?,
+        Command::Tempo { percent, port } => {
+            Device::open(&port.port, BOOT_DELAY)?.set_tempo(*percent)?
+        }
+        Command::Status { port } => {
+            let status = Device::open(&port.port, BOOT_DELAY)?.status()?;
+            println!(
+                "{} {} song, note {}/{}, tempo {}%",
+                if status.playing { "playing" } else { "stopped" },
+                match status.source {
+                    Storage::Flash => "flash".to_string(),
+                    Storage::Ram => "RAM".to_string(),
+                    Storage::Eeprom => format!("EEPROM library #{}", status.song),
+                },
+                status.note_index,
+                status.note_count,
+                status.tempo_percent
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,534 @@
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::abc::ABC;
+use crate::player::AudioGenerator;
+
+/// Lowest supported export sample rate, in Hz
+pub const MIN_SAMPLE_RATE: u32 = 8_000;
+/// Highest supported export sample rate, in Hz
+pub const MAX_SAMPLE_RATE: u32 = 192_000;
+
+/// Number of frames rendered and written at a time
+const BLOCK_FRAMES: usize = 4096;
+
+/// WAV format tag for integer PCM
+const WAV_FORMAT_PCM: u16 = 1;
+/// WAV format tag for IEEE float
+const WAV_FORMAT_IEEE_FLOAT: u16 = 3;
+/// WAV format tag for a format given by the GUID in the extended `fmt ` chunk
+const WAV_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// The GUID of the extensible formats after the two bytes of their format tag
+const WAV_SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Settings for rendering a song to an audio file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportConfig {
+    /// Samples per second, per channel
+    pub sample_rate: u32,
+    pub bit_depth: BitDepth,
+    /// Number of interleaved channels, each channel gets the same signal
+    pub channels: u16,
+    /// Byte order of raw PCM output (WAV is always little-endian)
+    pub endianness: Endianness,
+    /// Signedness of raw integer PCM output
+    /// (WAV is unsigned for 8-bit and signed otherwise)
+    pub signedness: Signedness,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44_100,
+            bit_depth: BitDepth::Sixteen,
+            channels: 1,
+            endianness: Endianness::Little,
+            signedness: Signedness::Signed,
+        }
+    }
+}
+
+impl ExportConfig {
+    /// Check that the settings describe a format that can be written.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&self.sample_rate) {
+            return Err(anyhow!(
+                "sample rate must be between {} and {} Hz, got {}",
+                MIN_SAMPLE_RATE,
+                MAX_SAMPLE_RATE,
+                self.sample_rate
+            ));
+        }
+
+        if self.channels == 0 {
+            return Err(anyhow!("must have at least 1 channel"));
+        }
+
+        // a single frame's size has to fit in the WAV block alignment field
+        if self.channels as usize * self.bit_depth.bytes() > u16::MAX as usize {
+            return Err(anyhow!("too many channels: {}", self.channels));
+        }
+
+        if self.bit_depth == BitDepth::ThirtyTwoFloat && self.signedness == Signedness::Unsigned {
+            return Err(anyhow!("32-bit float samples cannot be unsigned"));
+        }
+
+        Ok(())
+    }
+
+    /// Settings actually used for WAV files, which fix byte order and signedness.
+    fn for_wav(&self) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            signedness: match self.bit_depth {
+                BitDepth::Eight => Signedness::Unsigned,
+                _ => Signedness::Signed,
+            },
+            ..*self
+        }
+    }
+
+    /// Append a single sample in the range `-1.0..=1.0` to `bytes`.
+    fn encode_sample(&self, sample: f32, bytes: &mut Vec<u8>) {
+        let sample = sample.clamp(-1., 1.);
+
+        let (raw, width): (u32, usize) = match self.bit_depth {
+            BitDepth::ThirtyTwoFloat => (sample.to_bits(), 4),
+            integer => {
+                let bits = integer.bits() as u32;
+                let max = ((1i64 << (bits - 1)) - 1) as f32;
+                let mut value = (sample * max).round() as i64;
+                if self.signedness == Signedness::Unsigned {
+                    value += 1i64 << (bits - 1);
+                }
+                // truncate to the low bits, giving two's complement for negatives
+                (value as u32, integer.bytes())
+            }
+        };
+
+        let le = raw.to_le_bytes();
+        match self.endianness {
+            Endianness::Little => bytes.extend_from_slice(&le[..width]),
+            Endianness::Big => bytes.extend(le[..width].iter().rev()),
+        }
+    }
+}
+
+/// Sample format of exported audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+    TwentyFour,
+    ThirtyTwoFloat,
+}
+
+impl BitDepth {
+    pub fn bits(&self) -> u16 {
+        match self {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+            BitDepth::TwentyFour => 24,
+            BitDepth::ThirtyTwoFloat => 32,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bits() as usize / 8
+    }
+}
+
+/// Byte order of multi-byte samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Whether integer samples are centered on zero or on half their range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signedness {
+    Signed,
+    Unsigned,
+}
+
+/// Number of frames needed to play all of `abc` at the sample rate of `config`
+fn total_frames(abc: &ABC, config: &ExportConfig) -> usize {
+    (config.sample_rate as f64 * abc.total_playtime_secs()) as usize
+}
+
+/// Render the song in blocks of `BLOCK_FRAMES` and write the encoded samples.
+/// Returns the number of bytes written.
+fn write_samples<W: Write>(
+    abc: ABC,
+    writer: &mut W,
+    config: &ExportConfig,
+) -> Result<u64, anyhow::Error> {
+    let channels = config.channels as usize;
+    let total_frames = total_frames(&abc, config);
+
+    let mut audio_generator = AudioGenerator::new(abc, config.sample_rate, channels);
+
+    let mut block = vec![0f32; BLOCK_FRAMES * channels];
+    let mut bytes = Vec::with_capacity(block.len() * config.bit_depth.bytes());
+    let mut frames_written = 0;
+    let mut bytes_written = 0;
+
+    while frames_written < total_frames {
+        // only render what is left of the song in the last block
+        let frames = usize::min(BLOCK_FRAMES, total_frames - frames_written);
+        let block = &mut block[..frames * channels];
+
+        audio_generator.fill_output(block);
+
+        bytes.clear();
+        for sample in block.iter() {
+            config.encode_sample(*sample, &mut bytes);
+        }
+
+        writer.write_all(&bytes)?;
+
+        frames_written += frames;
+        bytes_written += bytes.len() as u64;
+    }
+
+    Ok(bytes_written)
+}
+
+/// Write raw PCM audio (no header) to a file.
+pub fn write_as_raw(abc: ABC, filename: &Path, config: &ExportConfig) -> Result<(), anyhow::Error> {
+    config.validate()?;
+
+    let mut file = BufWriter::new(std::fs::File::create(filename)?);
+
+    write_samples(abc, &mut file, config)?;
+
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Write a WAV file, streaming the samples to disk as they are rendered.
+/// Endianness and signedness of `config` are ignored, since WAV fixes them.
+pub fn write_as_wav(abc: ABC, filename: &Path, config: &ExportConfig) -> Result<(), anyhow::Error> {
+    config.for_wav().validate()?;
+    // don't leave an empty file behind for a song that can't be written
+    wav_data_size(&abc, &config.for_wav())?;
+
+    let mut file = BufWriter::new(std::fs::File::create(filename)?);
+
+    write_wav(abc, &mut file, config)?;
+
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Write a WAV file to `writer`, nothing is written if the song is too long.
+fn write_wav<W: Write>(
+    abc: ABC,
+    writer: &mut W,
+    config: &ExportConfig,
+) -> Result<(), anyhow::Error> {
+    let config = config.for_wav();
+
+    let data_size = wav_data_size(&abc, &config)?;
+    write_wav_header(writer, &config, data_size)?;
+
+    let written = write_samples(abc, writer, &config)?;
+    debug_assert_eq!(written, data_size as u64);
+
+    // chunks must be an even number of bytes long
+    if data_size % 2 == 1 {
+        writer.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+/// Size of the samples of `abc` in the `data` chunk,
+/// known before rendering since every frame has the same size
+fn wav_data_size(abc: &ABC, config: &ExportConfig) -> Result<u32, anyhow::Error> {
+    let frame_size = config.channels as u64 * config.bit_depth.bytes() as u64;
+    let data_size = total_frames(abc, config) as u64 * frame_size;
+
+    // the RIFF size field also has to fit the headers and padding
+    u32::try_from(data_size)
+        .ok()
+        .filter(|size| *size < u32::MAX - wav_header_len(config))
+        .ok_or(anyhow!("song is too long to fit in a WAV file"))
+}
+
+/// Whether the `fmt ` chunk needs the `WAVE_FORMAT_EXTENSIBLE` layout,
+/// which is required for more than 2 channels or integer samples of more than 16 bits.
+fn is_extensible(config: &ExportConfig) -> bool {
+    config.channels > 2 || config.bit_depth == BitDepth::TwentyFour
+}
+
+/// Format tag of the samples, in the `fmt ` chunk or its subformat GUID
+fn wav_format(config: &ExportConfig) -> u16 {
+    match config.bit_depth {
+        BitDepth::ThirtyTwoFloat => WAV_FORMAT_IEEE_FLOAT,
+        _ => WAV_FORMAT_PCM,
+    }
+}
+
+/// Length of the `fmt ` chunk's content
+fn wav_fmt_len(config: &ExportConfig) -> u32 {
+    if is_extensible(config) {
+        // cbSize, valid bits, channel mask and subformat GUID
+        16 + 2 + 22
+    } else if wav_format(config) != WAV_FORMAT_PCM {
+        // an empty cbSize
+        16 + 2
+    } else {
+        16
+    }
+}
+
+/// Every format but plain PCM needs a `fact` chunk
+fn has_fact_chunk(config: &ExportConfig) -> bool {
+    is_extensible(config) || wav_format(config) != WAV_FORMAT_PCM
+}
+
+/// Number of bytes before the samples
+fn wav_header_len(config: &ExportConfig) -> u32 {
+    let fact_len = match has_fact_chunk(config) {
+        true => 8 + 4,
+        false => 0,
+    };
+    12 + (8 + wav_fmt_len(config)) + fact_len + 8
+}
+
+/// Write the RIFF, `fmt `, `fact` (if needed) and `data` chunk headers of a WAV file.
+fn write_wav_header<W: Write>(
+    writer: &mut W,
+    config: &ExportConfig,
+    data_size: u32,
+) -> Result<(), anyhow::Error> {
+    let block_align = config.channels * config.bit_depth.bytes() as u16;
+    let byte_rate = config.sample_rate * block_align as u32;
+    let padded_data_size = data_size + data_size % 2;
+
+    // RIFF chunk, its size counts everything after this field
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(wav_header_len(config) - 8 + padded_data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    // format chunk
+    let format_tag = match is_extensible(config) {
+        true => WAV_FORMAT_EXTENSIBLE,
+        false => wav_format(config),
+    };
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&wav_fmt_len(config).to_le_bytes())?;
+    writer.write_all(&format_tag.to_le_bytes())?;
+    writer.write_all(&config.channels.to_le_bytes())?;
+    writer.write_all(&config.sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&config.bit_depth.bits().to_le_bytes())?;
+
+    if is_extensible(config) {
+        // size of the extension
+        writer.write_all(&22u16.to_le_bytes())?;
+        // every bit of each sample is used
+        writer.write_all(&config.bit_depth.bits().to_le_bytes())?;
+        // no speaker positions, every channel gets the same signal anyway
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&wav_format(config).to_le_bytes())?;
+        writer.write_all(&WAV_SUBFORMAT_GUID_TAIL)?;
+    } else if wav_fmt_len(config) > 16 {
+        // no extension
+        writer.write_all(&0u16.to_le_bytes())?;
+    }
+
+    // fact chunk, the number of frames
+    if has_fact_chunk(config) {
+        writer.write_all(b"fact")?;
+        writer.write_all(&4u32.to_le_bytes())?;
+        writer.write_all(&(data_size / block_align as u32).to_le_bytes())?;
+    }
+
+    // data chunk, the samples follow directly
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_abc;
+    use std::io::Cursor;
+
+    fn encode(config: &ExportConfig, sample: f32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        config.encode_sample(sample, &mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn integer_samples() {
+        let mut config = ExportConfig::default();
+        assert_eq!(encode(&config, 1.), [0xFF, 0x7F]);
+        assert_eq!(encode(&config, -1.), [0x01, 0x80]);
+
+        config.endianness = Endianness::Big;
+        assert_eq!(encode(&config, -1.), [0x80, 0x01]);
+
+        // unsigned samples are centered on half their range
+        config.signedness = Signedness::Unsigned;
+        assert_eq!(encode(&config, 0.), [0x80, 0x00]);
+
+        config.bit_depth = BitDepth::Eight;
+        assert_eq!(encode(&config, 0.), [0x80]);
+        assert_eq!(encode(&config, 1.), [0xFF]);
+        assert_eq!(encode(&config, -1.), [0x01]);
+
+        // 24-bit samples are packed into 3 bytes
+        config.bit_depth = BitDepth::TwentyFour;
+        config.signedness = Signedness::Signed;
+        assert_eq!(encode(&config, 0.5), [0x40, 0x00, 0x00]);
+        config.endianness = Endianness::Little;
+        assert_eq!(encode(&config, 0.5), [0x00, 0x00, 0x40]);
+        assert_eq!(encode(&config, -1.), [0x01, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn float_samples() {
+        let mut config = ExportConfig {
+            bit_depth: BitDepth::ThirtyTwoFloat,
+            ..Default::default()
+        };
+        assert_eq!(encode(&config, 0.25), 0.25f32.to_le_bytes());
+        // out of range samples are clamped
+        assert_eq!(encode(&config, 2.), 1f32.to_le_bytes());
+        assert_eq!(encode(&config, -3.), (-1f32).to_le_bytes());
+
+        config.endianness = Endianness::Big;
+        assert_eq!(encode(&config, 0.25), 0.25f32.to_be_bytes());
+    }
+
+    /// The chunks of a WAV file after the RIFF header, checking that their
+    /// sizes add up to the RIFF size and the length of the file
+    fn wav_chunks(wav: &[u8]) -> Vec<(&[u8], &[u8])> {
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32_at(wav, 4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..12], b"WAVE");
+
+        let mut chunks = Vec::new();
+        let mut rest = &wav[12..];
+        while !rest.is_empty() {
+            let size = u32_at(rest, 4) as usize;
+            chunks.push((&rest[..4], &rest[8..8 + size]));
+            // chunks are padded to an even length
+            rest = &rest[8 + size + size % 2..];
+        }
+        chunks
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    /// Render a second of audio (4 quarter notes) as a WAV file
+    fn render_wav(config: &ExportConfig) -> Vec<u8> {
+        let abc = parse_abc("CDEF").unwrap();
+        let mut wav = Cursor::new(Vec::new());
+        write_wav(abc, &mut wav, config).unwrap();
+        wav.into_inner()
+    }
+
+    #[test]
+    fn pcm_wav() {
+        let config = ExportConfig {
+            sample_rate: 8000,
+            channels: 2,
+            ..Default::default()
+        };
+        let wav = render_wav(&config);
+
+        let chunks = wav_chunks(&wav);
+        assert_eq!(chunks.len(), 2);
+
+        let (id, fmt) = chunks[0];
+        assert_eq!(id, b"fmt ");
+        assert_eq!(fmt.len(), 16);
+        assert_eq!(u16_at(fmt, 0), WAV_FORMAT_PCM);
+        assert_eq!(u16_at(fmt, 2), 2);
+        assert_eq!(u32_at(fmt, 4), 8000);
+        assert_eq!(u32_at(fmt, 8), 8000 * 4);
+        assert_eq!(u16_at(fmt, 12), 4);
+        assert_eq!(u16_at(fmt, 14), 16);
+
+        let (id, data) = chunks[1];
+        assert_eq!(id, b"data");
+        assert_eq!(data.len(), 8000 * 4);
+        // written in several blocks
+        assert!(data.len() / 4 > BLOCK_FRAMES);
+    }
+
+    #[test]
+    fn extensible_wav() {
+        // an odd number of frames of 3 bytes, so the data chunk is padded
+        let config = ExportConfig {
+            sample_rate: 8001,
+            channels: 3,
+            bit_depth: BitDepth::TwentyFour,
+            // ignored for WAV
+            endianness: Endianness::Big,
+            signedness: Signedness::Unsigned,
+        };
+        let wav = render_wav(&config);
+
+        let chunks = wav_chunks(&wav);
+        assert_eq!(chunks.len(), 3);
+
+        let (id, fmt) = chunks[0];
+        assert_eq!(id, b"fmt ");
+        assert_eq!(fmt.len(), 40);
+        assert_eq!(u16_at(fmt, 0), WAV_FORMAT_EXTENSIBLE);
+        assert_eq!(u16_at(fmt, 12), 9);
+        assert_eq!(u16_at(fmt, 16), 22);
+        assert_eq!(u16_at(fmt, 18), 24);
+        assert_eq!(u16_at(fmt, 24), WAV_FORMAT_PCM);
+        assert_eq!(fmt[26..], WAV_SUBFORMAT_GUID_TAIL);
+
+        let (id, fact) = chunks[1];
+        assert_eq!(id, b"fact");
+        assert_eq!(u32_at(fact, 0), 8001);
+
+        let (id, data) = chunks[2];
+        assert_eq!(id, b"data");
+        assert_eq!(data.len(), 8001 * 9);
+        assert_eq!(wav.len() % 2, 0);
+    }
+
+    #[test]
+    fn too_long_for_wav() {
+        // 12 GB per second
+        let config = ExportConfig {
+            sample_rate: MAX_SAMPLE_RATE,
+            channels: u16::MAX / 4,
+            bit_depth: BitDepth::ThirtyTwoFloat,
+            ..Default::default()
+        };
+        config.validate().unwrap();
+
+        let abc = parse_abc("CDEF").unwrap();
+        let mut wav = Cursor::new(Vec::new());
+        let error = write_wav(abc, &mut wav, &config).unwrap_err();
+        assert!(error.to_string().contains("too long"));
+        assert!(wav.into_inner().is_empty());
+    }
+}
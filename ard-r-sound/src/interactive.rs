@@ -0,0 +1,203 @@
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, queue, terminal};
+
+use ard_r_sound_lib::abc::{bar_of_note, Length, Note, PitchClass, PitchOrRest, ABC};
+use ard_r_sound_lib::player::{self, OutputSelection, Playback};
+
+/// How much `+` and `-` change the tempo multiplier by
+const TEMPO_STEP: f64 = 0.1;
+/// Slowest allowed tempo multiplier
+const MIN_TEMPO: f64 = 0.1;
+/// How often the status line is redrawn
+const REFRESH: Duration = Duration::from_millis(50);
+
+const HELP: &str = "space: pause/resume | left/right: seek bar | [ ]: loop start/end | \
+                    c: clear loop | +/-: tempo | q: quit";
+
+/// Play the song while reading key presses to control playback.
+/// Returns once the song ends or the user quits.
+pub fn run(abc: ABC, selection: &OutputSelection) -> Result<(), anyhow::Error> {
+    let playback = player::play_on(abc.clone(), selection)?;
+
+    let bar_starts = abc.bar_starts();
+
+    let mut stdout = std::io::stdout();
+    let _raw_mode = RawMode::enable()?;
+
+    // raw mode does not translate "\n", so return the cursor manually
+    write!(stdout, "{}\r\n", HELP)?;
+
+    // bar where the next loop region starts, set by `[`
+    let mut loop_start_bar: Option<usize> = None;
+
+    while !playback.is_finished() {
+        draw_status(&mut stdout, &abc, &playback, &bar_starts)?;
+
+        if !event::poll(REFRESH)? {
+            continue;
+        }
+
+        let (code, modifiers) = match event::read()? {
+            Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) => (code, modifiers),
+            _ => continue,
+        };
+
+        let current_note = playback.note_index().unwrap_or(0);
+        let current_bar = bar_of_note(&bar_starts, current_note);
+        // one past the last note of a bar
+        let bar_end = |bar: usize| bar_starts.get(bar + 1).copied().unwrap_or(abc.notes.len());
+
+        match code {
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => playback.stop(),
+            KeyCode::Char('q') | KeyCode::Esc => playback.stop(),
+            KeyCode::Char(' ') => match playback.is_paused() {
+                true => playback.resume(),
+                false => playback.pause(),
+            },
+            KeyCode::Left => {
+                // back to the start of this bar, or to the previous bar from its start
+                let bar = match bar_starts.get(current_bar) {
+                    Some(&start) if current_note > start => current_bar,
+                    _ => current_bar.saturating_sub(1),
+                };
+                if let Some(&start) = bar_starts.get(bar) {
+                    playback.seek(start);
+                }
+            }
+            KeyCode::Right => {
+                if let Some(next) = bar_starts.get(current_bar + 1) {
+                    playback.seek(*next);
+                }
+            }
+            KeyCode::Char('[') => loop_start_bar = Some(current_bar),
+            KeyCode::Char(']') => {
+                let start_bar = loop_start_bar.unwrap_or(0);
+                let (first, last) = (start_bar.min(current_bar), start_bar.max(current_bar));
+                if let Some(&start) = bar_starts.get(first) {
+                    playback.set_loop(Some(start..bar_end(last)));
+                }
+            }
+            KeyCode::Char('c') => {
+                loop_start_bar = None;
+                playback.set_loop(None);
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                playback.set_tempo(playback.tempo() + TEMPO_STEP);
+            }
+            KeyCode::Char('-') => {
+                playback.set_tempo(f64::max(playback.tempo() - TEMPO_STEP, MIN_TEMPO));
+            }
+            _ => {}
+        }
+    }
+
+    draw_status(&mut stdout, &abc, &playback, &bar_starts)?;
+    write!(stdout, "\r\n")?;
+    stdout.flush()?;
+
+    playback.wait()
+}
+
+/// Overwrite the current line with the bar, note, tempo and loop state.
+fn draw_status<W: Write>(
+    out: &mut W,
+    abc: &ABC,
+    playback: &Playback,
+    bar_starts: &[usize],
+) -> Result<(), anyhow::Error> {
+    let mut status = String::new();
+
+    match playback.note_index().filter(|i| *i < abc.notes.len()) {
+        Some(index) => status.push_str(&format!(
+            "bar {}/{} | note {}/{}: {}",
+            bar_of_note(bar_starts, index) + 1,
+            bar_starts.len(),
+            index + 1,
+            abc.notes.len(),
+            describe_note(&abc.notes[index]),
+        )),
+        None => status.push_str("starting"),
+    }
+
+    status.push_str(&format!(" | tempo {:.0}%", playback.tempo() * 100.));
+
+    if let Some(region) = playback.loop_region() {
+        status.push_str(&format!(
+            " | loop bars {}-{}",
+            bar_of_note(bar_starts, region.start) + 1,
+            bar_of_note(bar_starts, region.end - 1) + 1
+        ));
+    }
+
+    if playback.is_paused() {
+        status.push_str(" | paused");
+    }
+
+    queue!(
+        out,
+        cursor::MoveToColumn(0),
+        terminal::Clear(terminal::ClearType::CurrentLine)
+    )?;
+    write!(out, "{}", status)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Short human readable form of a note, like `C#/Db+1 1/2`.
+fn describe_note(note: &Note) -> String {
+    let pitch = match &note.pitch {
+        PitchOrRest::Pitch { class, octave } => {
+            let class = match class {
+                PitchClass::A => "A",
+                PitchClass::ASharpBFlat => "A#/Bb",
+                PitchClass::B => "B",
+                PitchClass::C => "C",
+                PitchClass::CSharpDFlat => "C#/Db",
+                PitchClass::D => "D",
+                PitchClass::DSharpEFlat => "D#/Eb",
+                PitchClass::E => "E",
+                PitchClass::F => "F",
+                PitchClass::FSharpGFlat => "F#/Gb",
+                PitchClass::G => "G",
+                PitchClass::GSharpAFlat => "G#/Ab",
+            };
+            format!("{}{:+}", class, octave)
+        }
+        PitchOrRest::Rest => "rest".to_string(),
+    };
+
+    let length = match note.length {
+        Length::Unit => "1".to_string(),
+        Length::Multiple(m) => m.to_string(),
+        Length::Division(d) => format!("1/{}", d),
+    };
+
+    format!("{} {}", pitch, length)
+}
+
+/// Keeps the terminal in raw mode until dropped.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<Self, anyhow::Error> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Err(e) = terminal::disable_raw_mode() {
+            eprintln!("unable to restore terminal: {}", e);
+        }
+    }
+}
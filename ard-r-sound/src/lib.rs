@@ -1,9 +1,11 @@
-
 // export modules
-pub mod abc;
-pub mod parser;
-pub mod parse_tree;
-pub mod codegen;
+pub use ard_r_sound_core::{abc, codegen, compress, parse_tree, parser};
+#[cfg(feature = "player")]
+pub mod player;
+#[cfg(feature = "player")]
+pub mod export;
+#[cfg(feature = "remote")]
+pub mod remote;
 
 // re-export everything in base
 pub use ard_r_sound_base::*;
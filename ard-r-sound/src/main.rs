@@ -1,29 +1,70 @@
 use clap::Parser;
 use tracing::info;
 
-mod codegen;
-mod parse_tree;
-mod parser;
-mod player;
-mod abc;
+use ard_r_sound_lib::{codegen, export, parser, player};
+
 mod args;
+mod commands;
+mod interactive;
+mod stats;
 
 fn main() -> Result<(), anyhow::Error> {
     let args = args::Args::parse();
 
-    tracing_subscriber::fmt::init();
+    if args.interactive() {
+        // per-note logging would scroll the status line away
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::WARN)
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
+
+    match args.command() {
+        Some(args::Subcommand::Serial(command)) => return commands::run(command),
+        Some(args::Subcommand::Stats { input_files }) => return stats::run(input_files),
+        None => {}
+    }
+
+    if args.list_devices() {
+        for device in player::list_output_devices()? {
+            println!(
+                "{}: {}: {}{}",
+                device.host,
+                device.index,
+                device.name,
+                if device.is_default { " (default)" } else { "" }
+            );
+        }
+        return Ok(());
+    }
 
-    let file_path = args.input_file();
+    let file_path = args.input_file()?;
 
     let abc = parser::parse_abc_file(&file_path)?;
 
     info!("abc is: {:#?}", abc);
 
     match args.file_format() {
-        Some(args::FileFormat::Raw) => player::write_as_raw(abc, args.output_file()?)?,
-        Some(args::FileFormat::Wav) => player::write_as_wav(abc, args.output_file()?)?,
-        Some(args::FileFormat::Play) => player::play(abc)?,
-        Some(args::FileFormat::Header) => codegen::generate_c_header(&abc, args.output_file()?)?,
+        Some(args::FileFormat::Raw) => {
+            export::write_as_raw(abc, args.output_file()?, &args.export_config())?
+        }
+        Some(args::FileFormat::Wav) => {
+            export::write_as_wav(abc, args.output_file()?, &args.export_config())?
+        }
+        Some(args::FileFormat::Play) if args.interactive() => {
+            interactive::run(abc, &args.output_selection())?
+        }
+        Some(args::FileFormat::Play) => player::play_on(abc, &args.output_selection())?.wait()?,
+        Some(args::FileFormat::Header) => {
+            codegen::generate_c_header(&abc, args.output_file()?, &args.header_options()?)?
+        }
+        Some(args::FileFormat::Sketch) => {
+            codegen::generate_sketch(&abc, args.output_file()?, &args.header_options()?)?
+        }
+        Some(args::FileFormat::Rust) => {
+            codegen::generate_rust_module(&abc, args.output_file()?, &args.static_name()?)?
+        }
         None => {}
     }
 
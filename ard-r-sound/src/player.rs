@@ -1,37 +1,134 @@
-use std::io::Write;
-use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::ops::Range;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use anyhow::anyhow;
 use tracing::{info, error};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Sample, SampleFormat, Stream, StreamConfig, StreamError};
+use cpal::{Device, Host, Sample, SampleFormat, Stream, StreamConfig, StreamError};
 
 use crate::abc::ABC;
 
-pub fn play(abc: ABC) -> Result<(), anyhow::Error> {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("no output device available");
+/// Number of callbacks to wait after the last note was generated before
+/// playback counts as finished, so the device gets to play out its buffer.
+const DRAIN_CALLBACKS: u32 = 2;
+
+/// Marker in `Shared::note_index` for "no note yet"
+const NO_NOTE_INDEX: usize = usize::MAX;
+
+/// Name of the host with a single device that discards all audio,
+/// for playing without a sound card (e.g. in CI).
+pub const NULL_HOST: &str = "null";
+
+/// Sample rate of the null device
+const NULL_SAMPLE_RATE: u32 = 44_100;
+/// Frames the null device asks for at a time
+const NULL_BLOCK_FRAMES: usize = 512;
+
+/// Which host and device `play_on` should output to.
+/// `None` picks the default.
+#[derive(Debug, Clone, Default)]
+pub struct OutputSelection {
+    /// Host (audio API) name, as listed by `list_output_devices()`
+    pub host: Option<String>,
+    /// Device name or its index on the host, as listed by `list_output_devices()`
+    pub device: Option<String>,
+}
+
+/// An output device that `play_on` can select.
+#[derive(Debug, Clone)]
+pub struct OutputDevice {
+    pub host: String,
+    pub index: usize,
+    pub name: String,
+    /// Whether this is the host's default output device
+    pub is_default: bool,
+}
+
+/// List output devices on every host available on this system,
+/// followed by the null device.
+pub fn list_output_devices() -> Result<Vec<OutputDevice>, anyhow::Error> {
+    let mut devices = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        let host = cpal::host_from_id(host_id)?;
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+
+        for (index, device) in host.output_devices()?.enumerate() {
+            let name = device.name()?;
+            devices.push(OutputDevice {
+                host: host_id.name().to_string(),
+                index,
+                is_default: Some(&name) == default_name.as_ref(),
+                name,
+            });
+        }
+    }
+
+    devices.push(OutputDevice {
+        host: NULL_HOST.to_string(),
+        index: 0,
+        name: NULL_HOST.to_string(),
+        is_default: true,
+    });
+
+    Ok(devices)
+}
+
+/// Start playing the song through the default output device.
+/// Playback happens on another thread, use the returned handle to control it.
+pub fn play(abc: ABC) -> Result<Playback, anyhow::Error> {
+    play_on(abc, &OutputSelection::default())
+}
+
+/// Start playing the song through the selected output device.
+/// Playback happens on another thread, use the returned handle to control it.
+pub fn play_on(abc: ABC, selection: &OutputSelection) -> Result<Playback, anyhow::Error> {
+    let playtime = abc.total_playtime_secs();
+    let shared = Arc::new(Shared::default());
+
+    let is_null_host = matches!(&selection.host, Some(h) if h.eq_ignore_ascii_case(NULL_HOST));
+    if is_null_host {
+        if let Some(device) = &selection.device {
+            if device != "0" && device != NULL_HOST {
+                return Err(anyhow!("no device {:?} on host {}", device, NULL_HOST));
+            }
+        }
+
+        info!("playtime: {} sec, device: {}", playtime, NULL_HOST);
+
+        let callback = make_data_callback::<f32>(abc, NULL_SAMPLE_RATE, 1, shared.clone());
+
+        return Ok(Playback {
+            output: Output::Null(NullOutput::spawn(callback)),
+            shared,
+            playtime: Duration::from_secs_f64(playtime),
+        });
+    }
+
+    let host = select_host(selection.host.as_deref())?;
+    let device = select_device(&host, selection.device.as_deref())?;
     let output_config = device
         .default_output_config()
-        .expect("no default device output config");
+        .map_err(|e| anyhow!("no usable output config for device: {}", e))?;
 
     let sample_format = output_config.sample_format();
     let config: StreamConfig = output_config.into();
 
-    let playtime = abc.total_playtime_secs();
-
     info!(
-        "playtime: {} sec, device: {:?}, config: {:?}",
+        "playtime: {} sec, host: {:?}, device: {:?}, config: {:?}",
         playtime,
+        host.id(),
         device.name(),
         config
     );
 
     let stream = match sample_format {
-        SampleFormat::F32 => make_stream::<f32>(&device, &config, abc),
-        SampleFormat::I16 => make_stream::<i16>(&device, &config, abc),
-        SampleFormat::U16 => make_stream::<u16>(&device, &config, abc),
+        SampleFormat::F32 => make_stream::<f32>(&device, &config, abc, shared.clone()),
+        SampleFormat::I16 => make_stream::<i16>(&device, &config, abc, shared.clone()),
+        SampleFormat::U16 => make_stream::<u16>(&device, &config, abc, shared.clone()),
     }?;
 
     /*
@@ -47,29 +144,293 @@ pub fn play(abc: ABC) -> Result<(), anyhow::Error> {
     */
     stream.play()?;
 
-    // sleep for some time, since playback is in another thread
-    std::thread::sleep(std::time::Duration::from_secs_f64(playtime));
+    Ok(Playback {
+        output: Output::Cpal(stream),
+        shared,
+        playtime: Duration::from_secs_f64(playtime),
+    })
+}
+
+/// Find a host by (case-insensitive) name, or the default host.
+fn select_host(name: Option<&str>) -> Result<Host, anyhow::Error> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("no audio host named {:?}", name))?;
+
+    Ok(cpal::host_from_id(id)?)
+}
+
+/// Find a device on `host` by index or exact name, or the default device.
+fn select_device(host: &Host, device: Option<&str>) -> Result<Device, anyhow::Error> {
+    let host_name = host.id().name();
+
+    let device = match device {
+        Some(device) => device,
+        None => {
+            return host
+                .default_output_device()
+                .ok_or_else(|| anyhow!("no output device available on host {}", host_name))
+        }
+    };
+
+    let mut devices = host.output_devices()?;
+
+    let found = match device.parse::<usize>() {
+        Ok(index) => devices.nth(index),
+        Err(_) => devices.find(|d| d.name().map(|n| n == device).unwrap_or(false)),
+    };
+
+    found.ok_or_else(|| anyhow!("no output device {:?} on host {}", device, host_name))
+}
+
+/// Where a `Playback` sends its audio.
+enum Output {
+    Cpal(Stream),
+    Null(NullOutput),
+}
+
+/// Runs the data callback on its own thread at the null device's sample rate,
+/// discarding the audio.
+struct NullOutput {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NullOutput {
+    fn spawn<F: FnMut(&mut [f32]) + Send + 'static>(mut callback: F) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
 
-    info!("done sleeping");
+        let thread_running = running.clone();
+        let thread = std::thread::spawn(move || {
+            let mut buffer = [0f32; NULL_BLOCK_FRAMES];
+            let start = Instant::now();
+            let mut frames = 0u64;
 
-    Ok(())
+            while thread_running.load(Ordering::Relaxed) {
+                callback(&mut buffer);
+                frames += NULL_BLOCK_FRAMES as u64;
 
-    // stream gets drop()'d
+                // pace ourselves like a real device would
+                let due = start + Duration::from_secs_f64(frames as f64 / NULL_SAMPLE_RATE as f64);
+                std::thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+        });
+
+        Self {
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop calling the data callback, without waiting for the thread.
+    fn halt(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
 }
 
-struct AudioGenerator {
+impl Drop for NullOutput {
+    fn drop(&mut self) {
+        self.halt();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Handle to a song playing on another thread.
+/// Dropping it stops playback immediately.
+pub struct Playback {
+    output: Output,
+    shared: Arc<Shared>,
+    playtime: Duration,
+}
+
+impl Playback {
+    /// Block until the song has finished (or was stopped).
+    pub fn wait(&self) -> Result<(), anyhow::Error> {
+        let mut done = self.shared.done.lock().unwrap();
+        while !*done {
+            done = self.shared.done_signal.wait(done).unwrap();
+        }
+
+        info!("playback finished at {:?}", self.position());
+
+        match self.shared.error.lock().unwrap().take() {
+            Some(err) => Err(anyhow!("audio stream failed: {}", err)),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop playback, any `wait()` returns right away.
+    pub fn stop(&self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        self.shared.finish();
+
+        // outputting silence is enough, not every backend supports pausing
+        match &self.output {
+            Output::Cpal(stream) => {
+                if let Err(e) = stream.pause() {
+                    info!("unable to pause stream after stopping: {}", e);
+                }
+            }
+            Output::Null(null) => null.halt(),
+        }
+    }
+
+    /// Output silence until `resume()` is called.
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Continue from where `pause()` left off.
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        *self.shared.done.lock().unwrap()
+    }
+
+    /// Where in the song playback is, at its written tempo like `duration()`.
+    /// Follows seeks and loops, and doesn't move while paused.
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f64(f64::from_bits(self.shared.position.load(Ordering::Relaxed)))
+    }
+
+    /// Index into `ABC::notes` of the note currently being played.
+    pub fn note_index(&self) -> Option<usize> {
+        match self.shared.note_index.load(Ordering::Relaxed) {
+            NO_NOTE_INDEX => None,
+            index => Some(index),
+        }
+    }
+
+    /// Total length of the song at its written tempo.
+    pub fn duration(&self) -> Duration {
+        self.playtime
+    }
+
+    /// Jump to the start of the note at `index` into `ABC::notes`.
+    pub fn seek(&self, index: usize) {
+        self.shared.seek_to.store(index, Ordering::Relaxed);
+    }
+
+    /// Change the speed of playback, 1.0 is the written tempo.
+    pub fn set_tempo(&self, tempo: f64) {
+        if tempo.is_finite() && tempo > 0. {
+            self.shared.tempo.store(tempo.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn tempo(&self) -> f64 {
+        f64::from_bits(self.shared.tempo.load(Ordering::Relaxed))
+    }
+
+    /// Repeat the notes in `region` (indexes into `ABC::notes`) until cleared with `None`.
+    pub fn set_loop(&self, region: Option<Range<usize>>) {
+        *self.shared.loop_region.lock().unwrap() = region.filter(|r| !r.is_empty());
+    }
+
+    pub fn loop_region(&self) -> Option<Range<usize>> {
+        self.shared.loop_region.lock().unwrap().clone()
+    }
+}
+
+/// State shared between a `Playback` handle and the audio thread.
+struct Shared {
+    done: Mutex<bool>,
+    done_signal: Condvar,
+    error: Mutex<Option<StreamError>>,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    /// `f64` bits of the position in seconds, see `Playback::position()`
+    position: AtomicU64,
+    note_index: AtomicUsize,
+    /// `f64` bits of the tempo multiplier
+    tempo: AtomicU64,
+    /// Note to jump to, or `NO_NOTE_INDEX`
+    seek_to: AtomicUsize,
+    loop_region: Mutex<Option<Range<usize>>>,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Self {
+            done: Mutex::new(false),
+            done_signal: Condvar::new(),
+            error: Mutex::new(None),
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            position: AtomicU64::new(0f64.to_bits()),
+            note_index: AtomicUsize::new(NO_NOTE_INDEX),
+            tempo: AtomicU64::new(1f64.to_bits()),
+            seek_to: AtomicUsize::new(NO_NOTE_INDEX),
+            loop_region: Mutex::new(None),
+        }
+    }
+}
+
+impl Shared {
+    /// Mark playback as done and wake up anyone waiting on it.
+    fn finish(&self) {
+        *self.done.lock().unwrap() = true;
+        self.done_signal.notify_all();
+    }
+
+    /// Make where `audio_generator` is in the song visible to the `Playback` handle.
+    fn publish(&self, audio_generator: &AudioGenerator) {
+        self.note_index.store(
+            audio_generator.note_index.unwrap_or(NO_NOTE_INDEX),
+            Ordering::Relaxed,
+        );
+        self.position
+            .store(audio_generator.position_secs().to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// How long a note of `length` plays, at `tempo` times the written tempo.
+fn note_secs(length: &crate::abc::Length, tempo: f64) -> f64 {
+    const BPM: f64 = 60.;
+    let single_beat_secs = BPM / 60. / 4. / tempo;
+
+    match length {
+        crate::abc::Length::Unit => single_beat_secs,
+        crate::abc::Length::Multiple(m) => single_beat_secs * *m as f64,
+        crate::abc::Length::Division(d) => single_beat_secs / *d as f64,
+    }
+}
+
+pub(crate) struct AudioGenerator {
     abc: ABC,
+    /// Start of each note in seconds at the written tempo, then the end of the song
+    note_starts: Vec<f64>,
     note_index: Option<usize>,
     samples_played_in_note: u32,
+    /// How much of the current note was played, in seconds at the written tempo,
+    /// added up as it plays since the tempo can change halfway through
+    written_secs_in_note: f64,
     sample_count: u32,
     samples_per_second: u32,
     channels: usize,
+    /// Speed multiplier, 1.0 is the written tempo
+    tempo: f64,
+    /// Notes to repeat, jumping back to the start after the last one
+    loop_region: Option<Range<usize>>,
 }
 
 impl AudioGenerator {
     fn generate_and_tick(&mut self) -> Option<f32> {
         const MIDDLE_A_FREQUENCY: f64 = 440.0;
-        const BPM: f64 = 60.;
         let twelfth_root_of_two: f64 = f64::powf(2., 1. / 12.);
 
         let mut is_new_note = false;
@@ -90,20 +451,15 @@ impl AudioGenerator {
             };
             let current_note = self.abc.notes.get(index)?;
 
-            let single_beat_secs = BPM / 60. / 4.;
-
             // the current note should be played for this many seconds
-            note_seconds = match current_note.length {
-                crate::abc::Length::Unit => single_beat_secs,
-                crate::abc::Length::Multiple(m) => single_beat_secs * m as f64,
-                crate::abc::Length::Division(d) => single_beat_secs / d as f64,
-            };
+            note_seconds = note_secs(&current_note.length, self.tempo);
 
             // the current note should be played for this many samples
             let note_total_samples = (note_seconds * self.samples_per_second as f64) as u32;
 
-            let remaining_fraction = (note_total_samples - self.samples_played_in_note) as f64
-                / note_total_samples as f64;
+            // saturate, since a tempo change can shorten a note that is already playing
+            let remaining_samples = note_total_samples.saturating_sub(self.samples_played_in_note);
+            let remaining_fraction = remaining_samples as f64 / note_total_samples as f64;
 
             // fade out if 90% done with note
             fade_out_modifier = if remaining_fraction > 0.1 {
@@ -117,7 +473,11 @@ impl AudioGenerator {
             if self.samples_played_in_note >= note_total_samples {
                 // go to the next note
                 self.samples_played_in_note = 0;
-                self.note_index = Some(index + 1);
+                self.written_secs_in_note = 0.;
+                self.note_index = Some(match &self.loop_region {
+                    Some(region) if index + 1 == region.end => region.start,
+                    _ => index + 1,
+                });
                 is_new_note = true;
             } else {
                 // this is the note we want
@@ -186,33 +546,119 @@ impl AudioGenerator {
         self.sample_count += 1;
 
         self.samples_played_in_note += 1;
+        self.written_secs_in_note += self.tempo / self.samples_per_second as f64;
 
         Some(amplitude as f32)
     }
 
-    fn new(abc: ABC, samples_per_second: u32, channels: usize) -> Self {
+    pub(crate) fn new(abc: ABC, samples_per_second: u32, channels: usize) -> Self {
+        let mut note_starts = vec![0.];
+        for note in &abc.notes {
+            note_starts.push(note_starts[note_starts.len() - 1] + note_secs(&note.length, 1.));
+        }
+
         Self {
             abc,
+            note_starts,
             note_index: None,
             samples_played_in_note: 0,
+            written_secs_in_note: 0.,
             sample_count: 0,
             samples_per_second,
             channels,
+            tempo: 1.,
+            loop_region: None,
         }
     }
 
-    fn fill_output<T: Sample>(&mut self, output: &mut [T]) {
+    /// Continue playback from the start of the note at `index`.
+    fn seek(&mut self, index: usize) {
+        self.note_index = Some(index);
+        self.samples_played_in_note = 0;
+        self.written_secs_in_note = 0.;
+    }
+
+    /// Where in the song we are, in seconds at the written tempo.
+    fn position_secs(&self) -> f64 {
+        let index = match self.note_index {
+            Some(index) => index.min(self.abc.notes.len()),
+            None => return 0.,
+        };
+        let start = self.note_starts[index];
+        let end = self.note_starts.get(index + 1).copied().unwrap_or(start);
+
+        f64::min(start + self.written_secs_in_note, end)
+    }
+
+    /// Fill `output` with the next frames of the song.
+    /// Returns `true` once the song has run out, the rest is then silence.
+    pub(crate) fn fill_output<T: Sample>(&mut self, output: &mut [T]) -> bool {
+        let mut ended = false;
         // info!("num frames: {}", output.len());
         for frame in output.chunks_mut(self.channels) {
-            let next_value = self.generate_and_tick().unwrap_or({
-                // TODO: oneshot signal to end stream?
-                0f32
-            });
+            let next_value = match self.generate_and_tick() {
+                Some(value) => value,
+                None => {
+                    ended = true;
+                    0f32
+                }
+            };
             // do the same thing for each channel
             for sample in frame.iter_mut() {
                 *sample = Sample::from(&next_value);
             }
         }
+        ended
+    }
+}
+
+/// Build the function that fills each output buffer from the song,
+/// applying any changes requested through the `Playback` handle.
+fn make_data_callback<T: Sample>(
+    abc: ABC,
+    sample_rate: u32,
+    channels: usize,
+    shared: Arc<Shared>,
+) -> impl FnMut(&mut [T]) + Send + 'static {
+    let mut audio_generator = AudioGenerator::new(abc, sample_rate, channels);
+
+    // callbacks left until the device has played everything we generated
+    let mut drain_remaining: Option<u32> = None;
+
+    move |output: &mut [T]| {
+        // pick up changes made through the `Playback` handle
+        audio_generator.tempo = f64::from_bits(shared.tempo.load(Ordering::Relaxed));
+        // never block the audio thread, the region can be picked up next time
+        if let Ok(region) = shared.loop_region.try_lock() {
+            audio_generator.loop_region = region.clone();
+        }
+        let seek_to = shared.seek_to.swap(NO_NOTE_INDEX, Ordering::Relaxed);
+        if seek_to != NO_NOTE_INDEX && drain_remaining.is_none() {
+            audio_generator.seek(seek_to);
+            shared.publish(&audio_generator);
+        }
+
+        let stopped = shared.stopped.load(Ordering::Relaxed);
+        if stopped || drain_remaining.is_some() || shared.paused.load(Ordering::Relaxed) {
+            output.fill(Sample::from(&0f32));
+
+            if let Some(remaining) = drain_remaining.as_mut() {
+                *remaining = remaining.saturating_sub(1);
+                if *remaining == 0 {
+                    shared.finish();
+                }
+            }
+            return;
+        }
+
+        let ended = audio_generator.fill_output(output);
+
+        shared.publish(&audio_generator);
+
+        if ended {
+            info!("end of song, draining output");
+            drain_remaining = Some(DRAIN_CALLBACKS);
+        }
     }
 }
 
@@ -220,6 +666,7 @@ fn make_stream<T: Sample>(
     device: &Device,
     config: &cpal::StreamConfig,
     abc: ABC,
+    shared: Arc<Shared>,
 ) -> Result<Stream, anyhow::Error> {
     let sample_rate = config.sample_rate.0;
     let channels = config.channels as usize;
@@ -231,14 +678,16 @@ fn make_stream<T: Sample>(
         channels
     );
 
-    let mut audio_generator = AudioGenerator::new(abc, sample_rate, channels);
+    let mut fill = make_data_callback::<T>(abc, sample_rate, channels, shared.clone());
 
     let data_callback = move |output: &mut [T], _cb_info: &cpal::OutputCallbackInfo| {
-        audio_generator.fill_output(output);
+        fill(output);
     };
 
-    let error_callback = |err: StreamError| {
+    let error_callback = move |err: StreamError| {
         error!("an error occurred on the output audio stream: {}", err);
+        *shared.error.lock().unwrap() = Some(err);
+        shared.finish();
     };
 
     let stream = device.build_output_stream(config, data_callback, error_callback)?;
@@ -246,56 +695,98 @@ fn make_stream<T: Sample>(
     Ok(stream)
 }
 
-// write output of i16, single channel audio @ 44100 Hz to file
-pub fn write_as_raw(abc: ABC, filename: &Path) -> Result<(), anyhow::Error> {
-    const BUFFER_SIZE: usize = 65535;
-    let mut buffer = [0i16; BUFFER_SIZE];
-    let mut byte_buffer = [0u8; BUFFER_SIZE * 2];
-
-    let sample_rate = 44100;
-    let channels = 1usize;
-
-    let total_samples = (sample_rate as f64 * abc.total_playtime_secs()) as u32;
-    let mut current_samples = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_abc;
 
-    let mut audio_generator = AudioGenerator::new(abc, sample_rate, channels);
-
-    let mut file = std::fs::File::create(filename)?;
-
-    while current_samples < total_samples {
-        audio_generator.fill_output(&mut buffer);
+    /// Four notes of a second each at the written tempo, on the null host
+    fn play_headless() -> Playback {
+        let selection = OutputSelection {
+            host: Some(NULL_HOST.to_string()),
+            device: None,
+        };
+        play_on(parse_abc("C4 D4 E4 F4").unwrap(), &selection).unwrap()
+    }
 
-        // extract bytes
-        for (index, num) in buffer.iter().enumerate() {
-            let bytes = num.to_le_bytes();
-            byte_buffer[index * 2] = bytes[0];
-            byte_buffer[index * 2 + 1] = bytes[1];
+    /// Poll `condition` until it holds, failing after a few seconds
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(5));
         }
-
-        // write entire byte buffer
-        file.write_all(&byte_buffer)?;
-
-        current_samples += BUFFER_SIZE as u32;
     }
 
-    Ok(())
-}
-
-// write as wav file using `wav` crate
-pub fn write_as_wav(abc: ABC, filename: &Path) -> Result<(), anyhow::Error> {
-    let sample_rate = 44100;
-    let channels = 1usize;
-    let header = wav::Header::new(wav::WAV_FORMAT_PCM, channels as u16, sample_rate, 16);
+    fn position_secs(playback: &Playback) -> f64 {
+        playback.position().as_secs_f64()
+    }
 
-    let mut entire_song =
-        vec![0; (sample_rate as f64 * channels as f64 * abc.total_playtime_secs()) as usize];
+    #[test]
+    fn seek_and_pause() {
+        let playback = play_headless();
+        assert_eq!(playback.duration(), Duration::from_secs(4));
+        wait_for(|| playback.note_index() == Some(0));
+
+        playback.seek(2);
+        wait_for(|| playback.note_index() == Some(2));
+        assert!((2.0..3.0).contains(&position_secs(&playback)));
+
+        // neither the note nor the position move while paused
+        playback.pause();
+        std::thread::sleep(Duration::from_millis(50));
+        let position = playback.position();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(playback.is_paused());
+        assert_eq!(playback.position(), position);
+        assert_eq!(playback.note_index(), Some(2));
+
+        playback.resume();
+        wait_for(|| playback.position() > position);
+
+        playback.stop();
+        playback.wait().unwrap();
+        assert!(playback.is_finished());
+    }
 
-    let mut audio_generator = AudioGenerator::new(abc, sample_rate, channels);
+    #[test]
+    fn tempo_and_loop() {
+        let playback = play_headless();
+        wait_for(|| playback.note_index() == Some(0));
+
+        // a quarter of a second per note, repeating the second one
+        playback.set_tempo(4.);
+        playback.set_loop(Some(1..2));
+        assert_eq!(playback.loop_region(), Some(1..2));
+        playback.seek(1);
+        wait_for(|| playback.note_index() == Some(1));
+
+        // long enough to loop at least once, the position jumps back with it
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(playback.note_index(), Some(1));
+        assert!((1.0..2.0).contains(&position_secs(&playback)));
+
+        // plays out the rest of the song once the loop is cleared
+        playback.set_loop(None);
+        playback.wait().unwrap();
+        assert_eq!(position_secs(&playback), 4.0);
+    }
 
-    audio_generator.fill_output(&mut entire_song);
+    #[test]
+    fn position_across_tempo_changes() {
+        let mut audio_generator = AudioGenerator::new(parse_abc("C4 D4").unwrap(), 8000, 1);
+        // added up sample by sample, so only close to exact
+        let position_is = |audio_generator: &AudioGenerator, secs: f64| {
+            assert!((audio_generator.position_secs() - secs).abs() < 1e-9)
+        };
 
-    let mut file = std::fs::File::create(filename)?;
+        // a quarter of the first note at the written tempo
+        audio_generator.fill_output(&mut [0f32; 2000]);
+        position_is(&audio_generator, 0.25);
 
-    wav::write(header, &wav::BitDepth::Sixteen(entire_song), &mut file)
-        .map_err(|e| anyhow::anyhow!(e))
+        // another quarter twice as fast, the first quarter isn't rescaled
+        audio_generator.tempo = 2.;
+        audio_generator.fill_output(&mut [0f32; 1000]);
+        position_is(&audio_generator, 0.5);
+    }
 }
@@ -0,0 +1,387 @@
+//! Host side of the serial protocol in `ard_r_sound_base::protocol`,
+//! to control an Arduino running the firmware.
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use tracing::info;
+
+use crate::protocol::{
+    Command, Frame, FrameDecoder, Response, ResultCode, Status, Storage, MAX_CHUNK, MAX_FRAME,
+};
+
+/// Baud rate of `Peripherals::serial` in the firmware
+pub const BAUD_RATE: u32 = 57_600;
+
+/// How long to wait for an answer.
+/// Writing a chunk to EEPROM takes about 3.3 ms per byte.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Opening the port resets the Uno, so give the bootloader time to start the firmware
+pub const BOOT_DELAY: Duration = Duration::from_secs(2);
+
+/// Sent to wake the firmware up from power down, the frame decoder skips it
+const WAKE_BYTE: u8 = 0x00;
+/// How long the firmware takes to wake up and be ready to receive again
+const WAKE_DELAY: Duration = Duration::from_millis(20);
+
+/// An Arduino running the firmware, connected through `port`.
+pub struct Device<P> {
+    port: P,
+    decoder: FrameDecoder,
+}
+
+impl Device<Box<dyn serialport::SerialPort>> {
+    /// Open the serial port at `path` and wait `boot_delay` for the firmware to start.
+    pub fn open(path: &str, boot_delay: Duration) -> Result<Self, anyhow::Error> {
+        let mut port = serialport::new(path, BAUD_RATE)
+            .timeout(RESPONSE_TIMEOUT)
+            .open()
+            .with_context(|| format!("unable to open serial port {}", path))?;
+
+        std::thread::sleep(boot_delay);
+
+        // boards that aren't reset by opening the port may be powered down
+        port.write_all(&[WAKE_BYTE])?;
+        std::thread::sleep(WAKE_DELAY);
+
+        Ok(Self::new(port))
+    }
+}
+
+impl<P: Read + Write> Device<P> {
+    /// `port` must time out reads, or a device that doesn't answer blocks forever.
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            decoder: FrameDecoder::new(),
+        }
+    }
+
+    /// Upload `song` to `target`, in chunks.
+    /// `song` is in the `song_bytes` format for RAM, and a `library` for EEPROM.
+    pub fn upload(&mut self, target: Storage, song: &[u8]) -> Result<(), anyhow::Error> {
+        let length = song
+            .len()
+            .try_into()
+            .map_err(|_| anyhow!("song is too large to upload ({} bytes)", song.len()))?;
+
+        self.command(&Command::UploadBegin { target, length })?;
+
+        for (i, chunk) in song.chunks(MAX_CHUNK).enumerate() {
+            info!(
+                "uploading bytes {}..{}",
+                i * MAX_CHUNK,
+                i * MAX_CHUNK + chunk.len()
+            );
+            self.command(&Command::UploadData {
+                offset: (i * MAX_CHUNK) as u16,
+                data: chunk,
+            })?;
+        }
+
+        self.command(&Command::UploadEnd)
+    }
+
+    /// Play the song in `source`, `song` is its index in the EEPROM library.
+    pub fn play(&mut self, source: Storage, song: u8, repeat: bool) -> Result<(), anyhow::Error> {
+        self.command(&Command::Play {
+            source,
+            song,
+            repeat,
+        })
+    }
+
+    pub fn stop(&mut self) -> Result<(), anyhow::Error> {
+        self.command(&Command::Stop)
+    }
+
+    /// Play at `percent` of the song's tempo.
+    pub fn set_tempo(&mut self, percent: u16) -> Result<(), anyhow::Error> {
+        self.command(&Command::SetTempo { percent })
+    }
+
+    pub fn status(&mut self) -> Result<Status, anyhow::Error> {
+        match self.request(&Command::QueryStatus)? {
+            Response::Status(status) => Ok(status),
+            Response::Ack { result, .. } => bail!("device didn't send its status: {}", result),
+        }
+    }
+
+    /// Send a command that is answered with an `Ack`.
+    fn command(&mut self, command: &Command) -> Result<(), anyhow::Error> {
+        let kind = frame(command)?.kind;
+
+        match self.request(command)? {
+            Response::Ack {
+                kind: acked,
+                result: ResultCode::Ok,
+            } if acked == kind => Ok(()),
+            Response::Ack { result, .. } => bail!("device rejected the command: {}", result),
+            Response::Status(_) => bail!("device sent its status instead of an answer"),
+        }
+    }
+
+    fn request(&mut self, command: &Command) -> Result<Response, anyhow::Error> {
+        let mut buf = [0; MAX_FRAME];
+        self.port.write_all(frame(command)?.encode(&mut buf))?;
+        self.port.flush()?;
+
+        let mut byte = [0];
+        loop {
+            self.port
+                .read_exact(&mut byte)
+                .context("no answer from the device")?;
+
+            match self.decoder.push(byte[0]) {
+                Some(Ok(frame)) => {
+                    return Response::from_frame(&frame)
+                        .ok_or(anyhow!("invalid answer from the device"))
+                }
+                Some(Err(e)) => bail!("corrupted answer from the device: {:?}", e),
+                None => {}
+            }
+        }
+    }
+}
+
+/// The frame of `command`, which can't be sent if it doesn't fit in one
+fn frame(command: &Command) -> Result<Frame, anyhow::Error> {
+    command
+        .to_frame()
+        .map_err(|e| anyhow!("can't send the command: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ard_r_sound_base::library::Library;
+    use ard_r_sound_base::protocol::Frame;
+    use ard_r_sound_base::song_bytes::ByteSong;
+    use ard_r_sound_base::Song;
+    use serialport::{SerialPort, TTYPort};
+
+    /// Stands in for the firmware on the other end of a pseudo terminal.
+    #[derive(Default)]
+    struct FakeDevice {
+        /// target, bytes and how many of them were received
+        uploading: Option<(Storage, Vec<u8>, usize)>,
+        ram: Option<Vec<u8>>,
+        eeprom: Option<Vec<u8>>,
+        playing: bool,
+        tempo_percent: u16,
+    }
+
+    /// Largest song the fake device accepts
+    const FAKE_RAM: usize = 512;
+
+    impl FakeDevice {
+        fn handle(&mut self, frame: &Frame) -> Response {
+            let command = match Command::from_frame(frame) {
+                Ok(command) => command,
+                Err(result) => {
+                    return Response::Ack {
+                        kind: frame.kind,
+                        result,
+                    }
+                }
+            };
+
+            let result = match command {
+                Command::UploadBegin { length, .. } if length as usize > FAKE_RAM => {
+                    ResultCode::OutOfSpace
+                }
+                Command::UploadBegin { target, length } => {
+                    self.uploading = Some((target, vec![0; length as usize], 0));
+                    ResultCode::Ok
+                }
+                Command::UploadData { offset, data } => match &mut self.uploading {
+                    Some((_, buf, received)) => {
+                        let range = offset as usize..offset as usize + data.len();
+                        match buf.get_mut(range.clone()) {
+                            Some(bytes) => {
+                                bytes.copy_from_slice(data);
+                                *received = range.end.max(*received);
+                                ResultCode::Ok
+                            }
+                            None => ResultCode::BadPayload,
+                        }
+                    }
+                    None => ResultCode::NotUploading,
+                },
+                Command::UploadEnd => match self.uploading.take() {
+                    Some((_, buf, received)) if received < buf.len() => ResultCode::NotUploading,
+                    Some((Storage::Eeprom, buf, _))
+                        if Library::new(&buf[..]).validate().is_ok() =>
+                    {
+                        self.eeprom = Some(buf);
+                        ResultCode::Ok
+                    }
+                    Some((Storage::Ram, buf, _)) if ByteSong::new(&buf[..]).validate().is_ok() => {
+                        self.ram = Some(buf);
+                        ResultCode::Ok
+                    }
+                    Some(_) => ResultCode::InvalidSong,
+                    None => ResultCode::NotUploading,
+                },
+                Command::Play { .. } if self.ram.is_none() => ResultCode::NoSong,
+                Command::Play { .. } => {
+                    self.playing = true;
+                    ResultCode::Ok
+                }
+                Command::Stop => {
+                    self.playing = false;
+                    ResultCode::Ok
+                }
+                Command::SetTempo { percent } => {
+                    self.tempo_percent = percent;
+                    ResultCode::Ok
+                }
+                Command::QueryStatus => {
+                    let note_count = match &self.ram {
+                        Some(ram) => ByteSong::new(&ram[..]).len() as u16,
+                        None => 0,
+                    };
+                    return Response::Status(Status {
+                        playing: self.playing,
+                        source: Storage::Ram,
+                        song: 0,
+                        note_index: 0,
+                        note_count,
+                        tempo_percent: self.tempo_percent,
+                    });
+                }
+            };
+
+            Response::Ack {
+                kind: frame.kind,
+                result,
+            }
+        }
+
+        /// Answer frames from `port` until the other end is closed.
+        fn serve(mut self, mut port: TTYPort) -> Self {
+            port.set_timeout(Duration::from_millis(200)).unwrap();
+            let mut decoder = FrameDecoder::new();
+            let mut byte = [0];
+            let mut idle = 0;
+
+            // stop once the host has been quiet for a while
+            while idle < 10 {
+                if port.read_exact(&mut byte).is_err() {
+                    idle += 1;
+                    continue;
+                }
+                idle = 0;
+
+                if let Some(Ok(frame)) = decoder.push(byte[0]) {
+                    let mut buf = [0; MAX_FRAME];
+                    let response = self.handle(&frame).to_frame();
+                    port.write_all(response.encode(&mut buf)).unwrap();
+                }
+            }
+
+            self
+        }
+    }
+
+    fn abc() -> crate::abc::ABC {
+        // enough unique notes to need more than one chunk
+        crate::parser::parse_abc("K:C\n!p!C, D, E, F, G, A, B, | C D E F G A B |\nC, D, E, F, G, A, B, | C D E F G A B |\n").unwrap()
+    }
+
+    /// Run `host` against a fake device on the other end of a pseudo terminal.
+    fn with_fake_device<T>(
+        host: impl FnOnce(&mut Device<Box<dyn SerialPort>>) -> T,
+    ) -> (T, FakeDevice) {
+        let (fake_end, host_end) = TTYPort::pair().unwrap();
+        let path = host_end.name().unwrap();
+
+        let fake = std::thread::spawn(move || FakeDevice::default().serve(fake_end));
+
+        let mut device = Device::open(&path, Duration::ZERO).unwrap();
+        let result = host(&mut device);
+        drop(device);
+        drop(host_end);
+
+        (result, fake.join().unwrap())
+    }
+
+    #[test]
+    fn upload_play_and_status() {
+        let song = crate::codegen::to_song_bytes(&abc()).unwrap();
+
+        let (status, fake) = with_fake_device(|device| {
+            device.upload(Storage::Ram, &song).unwrap();
+            device.set_tempo(150).unwrap();
+            device.play(Storage::Ram, 0, true).unwrap();
+            let playing = device.status().unwrap();
+            device.stop().unwrap();
+            (playing, device.status().unwrap())
+        });
+
+        let (playing, stopped) = status;
+        assert!(playing.playing);
+        assert_eq!(playing.note_count, 28);
+        assert_eq!(playing.tempo_percent, 150);
+        assert!(!stopped.playing);
+
+        assert!(song.len() > MAX_CHUNK);
+        assert_eq!(fake.ram, Some(song));
+    }
+
+    #[test]
+    fn upload_library() {
+        let abc = abc();
+        let image = crate::codegen::to_library(&[abc.clone(), abc], 1).unwrap();
+
+        let (result, fake) = with_fake_device(|device| device.upload(Storage::Eeprom, &image));
+
+        result.unwrap();
+        assert_eq!(fake.eeprom, Some(image));
+    }
+
+    #[test]
+    fn rejected_commands_are_errors() {
+        let (results, _) = with_fake_device(|device| {
+            (
+                device.play(Storage::Ram, 0, false),
+                device.upload(Storage::Ram, &[0; FAKE_RAM + 1]),
+                device.upload(Storage::Ram, &[1, 1, 0]),
+                // a song rather than a library
+                device.upload(Storage::Eeprom, &[0, 0, 0]),
+            )
+        });
+
+        let (play, too_large, invalid, not_library) = results;
+        assert!(play.unwrap_err().to_string().contains("no song"));
+        assert!(too_large.unwrap_err().to_string().contains("too large"));
+        assert!(invalid.unwrap_err().to_string().contains("not a valid"));
+        assert!(not_library.unwrap_err().to_string().contains("not a valid"));
+    }
+
+    #[test]
+    fn partial_uploads_are_rejected() {
+        let song = crate::codegen::to_song_bytes(&abc()).unwrap();
+
+        let (result, fake) = with_fake_device(|device| {
+            device.command(&Command::UploadBegin {
+                target: Storage::Ram,
+                length: song.len() as u16,
+            })?;
+            device.command(&Command::UploadData {
+                offset: 0,
+                data: &song[..MAX_CHUNK],
+            })?;
+            device.command(&Command::UploadEnd)
+        });
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("not all of it was sent"));
+        assert_eq!(fake.ram, None);
+    }
+}
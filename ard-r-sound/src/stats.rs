@@ -0,0 +1,72 @@
+//! The `stats` subcommand, which only reports on songs and runs without an Arduino.
+//!
+//! It measures the phrase compression of `include_song!(.., encoding = phrases)`.
+
+use std::path::PathBuf;
+
+use ard_r_sound_lib::codegen::Optimized;
+use ard_r_sound_lib::compress::Phrases;
+use ard_r_sound_lib::parser;
+
+/// Print how much compressing repeated phrases shrinks every tune of `input_files`,
+/// compared to `PackedStatic`.
+/// A tune or file that can't be reported on is printed as an error,
+/// and only fails the command once every other tune is reported.
+pub fn run(input_files: &[PathBuf]) -> Result<(), anyhow::Error> {
+    let mut failed = 0;
+    for file in input_files {
+        let file_str = match std::fs::read_to_string(file) {
+            Ok(file_str) => file_str,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let tunes = parser::tunes(&file_str);
+        for (index, tune) in tunes.iter().enumerate() {
+            let name = match tunes.len() {
+                1 => file.display().to_string(),
+                _ => format!("{}, tune {}", file.display(), index),
+            };
+
+            match report(tune) {
+                Ok(report) => println!("{}: {}", name, report),
+                Err(e) => {
+                    eprintln!("{}: {}", name, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "{} tunes or files failed, see above",
+            failed
+        )),
+    }
+}
+
+/// How much the phrases of `tune` compress
+fn report(tune: &str) -> Result<String, anyhow::Error> {
+    let abc = parser::parse_abc(tune)?;
+    let optimized = Optimized::from(&abc);
+    let phrases = Phrases::try_from(&optimized)?;
+
+    // 2 bytes per packed unique note in either case, see `PackedStatic`
+    let notes_len = 2 * optimized.uniques.len();
+    let packed_len = notes_len + optimized.list.len();
+    let compressed_len = notes_len + phrases.encoded_len();
+    Ok(format!(
+        "{} notes, {} unique, {} bytes packed, {} bytes with {} phrases ({:.0}%)",
+        optimized.list.len(),
+        optimized.uniques.len(),
+        packed_len,
+        compressed_len,
+        phrases.rules.len(),
+        100.0 * compressed_len as f64 / packed_len.max(1) as f64
+    ))
+}